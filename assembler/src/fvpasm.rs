@@ -0,0 +1,749 @@
+//! The `.fvpasm` text assembly.
+//!
+//! One statement per line, `;` starts a comment and operands are separated by spaces or commas:
+//! * `.title "text"`, `.game_mode N`, `.globals NON_VOLATILE VOLATILE`, `.nls CODEPAGE` and
+//!   `.entry ROUTINE` set the header of the scenario
+//! * `.syscall NAME ARGS` declares the next syscall id, `.custom_syscall NAME ROUTINE ARGS` a
+//!   routine of the script called as a syscall
+//! * `.function NAME ARGS LOCALS` starts a routine, it stands for its `init_stack`
+//! * `LABEL:` names the instruction after it
+//! * `mnemonic operands...` is an instruction, `call`, `jmp` and `jz` take a routine name or a
+//!   label and strings are quoted, with the `\"`, `\\`, `\n`, `\r`, `\t` and `\u{XXXX}` escapes
+//!
+//! Instructions have no address, they are laid out in the order they are written.
+use std::{collections::HashSet, fmt};
+
+use rfvp_core::format::scenario::{instructions::Opcode, Nls};
+
+use crate::{
+    utils::{Function, Inst2},
+    CustomSyscallEntry, ProjectConfig, SyscallEntry,
+};
+
+/// An error at a line and a column of the source, both starting at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    Colon,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match &self.kind {
+            TokenKind::Word(word) => format!("`{}`", word),
+            TokenKind::Str(_) => "a string".to_string(),
+            TokenKind::Colon => "`:`".to_string(),
+        }
+    }
+}
+
+/// split a line into tokens, an error is the column and the message
+fn tokenize(line: &str) -> Result<Vec<Token>, (usize, String)> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        match chars[i] {
+            ';' => break,
+            c if c.is_whitespace() || c == ',' => i += 1,
+            ':' => {
+                tokens.push(Token {
+                    kind: TokenKind::Colon,
+                    column,
+                });
+                i += 1;
+            }
+            '"' => {
+                let (text, end) = read_string(&chars, i)?;
+                tokens.push(Token {
+                    kind: TokenKind::Str(text),
+                    column,
+                });
+                i = end;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !matches!(chars[i], ',' | ';' | ':' | '"')
+                {
+                    i += 1;
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Word(chars[start..i].iter().collect()),
+                    column,
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// read the string literal starting at `start`, returns its text and the index after it
+fn read_string(chars: &[char], start: usize) -> Result<(String, usize), (usize, String)> {
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return Ok((text, i + 1)),
+            '\\' => {
+                let (c, next) = match chars.get(i + 1) {
+                    Some('"') => ('"', i + 2),
+                    Some('\\') => ('\\', i + 2),
+                    Some('n') => ('\n', i + 2),
+                    Some('r') => ('\r', i + 2),
+                    Some('t') => ('\t', i + 2),
+                    Some('u') => read_unicode(chars, i + 2).ok_or((
+                        i + 1,
+                        "invalid unicode escape, expected `\\u{XXXX}`".to_string(),
+                    ))?,
+                    Some(c) => return Err((i + 1, format!("unknown escape `\\{}`", c))),
+                    None => break,
+                };
+                text.push(c);
+                i = next;
+            }
+            c => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err((start + 1, "unterminated string".to_string()))
+}
+
+/// the character of a `{XXXX}` escape starting at `start`, and the index after it
+fn read_unicode(chars: &[char], start: usize) -> Option<(char, usize)> {
+    if chars.get(start) != Some(&'{') {
+        return None;
+    }
+    let close = start + chars[start..].iter().position(|c| *c == '}')?;
+    let hex = chars[start + 1..close].iter().collect::<String>();
+    let c = char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?;
+    Some((c, close + 1))
+}
+
+/// a decimal or `0x` hexadecimal integer
+fn parse_int(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn count_operands(count: usize) -> String {
+    match count {
+        0 => "no operands".to_string(),
+        1 => "1 operand".to_string(),
+        count => format!("{} operands", count),
+    }
+}
+
+/// What an operand of an instruction is
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Int(i64, i64),
+    Float,
+    Str,
+    /// a routine or a label
+    Target,
+    /// a 32-bit integer, or a routine or a label pushed by its address
+    Value,
+    Syscall,
+}
+
+const I8: Operand = Operand::Int(i8::MIN as i64, i8::MAX as i64);
+const I16: Operand = Operand::Int(i16::MIN as i64, i16::MAX as i64);
+const U16: Operand = Operand::Int(0, u16::MAX as i64);
+
+fn operands(opcode: Opcode) -> &'static [Operand] {
+    match opcode {
+        Opcode::Call | Opcode::Jmp | Opcode::Jz => &[Operand::Target],
+        Opcode::Syscall => &[Operand::Syscall],
+        Opcode::PushI32 => &[Operand::Value],
+        Opcode::PushI16 => &[I16],
+        Opcode::PushI8 => &[I8],
+        Opcode::PushF32 => &[Operand::Float],
+        Opcode::PushString => &[Operand::Str],
+        Opcode::PushGlobal
+        | Opcode::PushGlobalTable
+        | Opcode::PopGlobal
+        | Opcode::PopGlobalTable => &[U16],
+        Opcode::PushStack | Opcode::PushLocalTable | Opcode::PopStack | Opcode::PopLocalTable => {
+            &[I8]
+        }
+        _ => &[],
+    }
+}
+
+/// A name used before it may be defined, checked once the whole source is read
+#[derive(Debug)]
+struct Reference {
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Default)]
+struct Parser {
+    diagnostics: Vec<Diagnostic>,
+    line: usize,
+
+    title: String,
+    game_mode: u16,
+    globals: (u16, u16),
+    nls: Option<String>,
+    entry: Option<Reference>,
+    syscalls: Vec<SyscallEntry>,
+    custom_syscalls: Vec<(CustomSyscallEntry, Reference)>,
+
+    functions: Vec<Function>,
+    /// the routines and the labeled instructions
+    labels: HashSet<String>,
+    routines: HashSet<String>,
+    /// labels waiting for the next instruction
+    pending_labels: Vec<Reference>,
+    targets: Vec<Reference>,
+    syscall_uses: Vec<Reference>,
+}
+
+impl Parser {
+    fn error(&mut self, column: usize, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            line: self.line,
+            column,
+            message: message.into(),
+        });
+    }
+
+    fn reference(&self, name: &str, column: usize) -> Reference {
+        Reference {
+            name: name.to_string(),
+            line: self.line,
+            column,
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) {
+        let tokens = match tokenize(line) {
+            Ok(tokens) => tokens,
+            Err((column, message)) => return self.error(column, message),
+        };
+
+        let mut tokens = tokens.as_slice();
+        if let [Token {
+            kind: TokenKind::Word(label),
+            column,
+        }, Token {
+            kind: TokenKind::Colon,
+            ..
+        }, rest @ ..] = tokens
+        {
+            let label = self.reference(label, *column);
+            self.pending_labels.push(label);
+            tokens = rest;
+        }
+
+        let Some((head, args)) = tokens.split_first() else {
+            return;
+        };
+        match &head.kind {
+            TokenKind::Word(word) if word.starts_with('.') => self.directive(word, head, args),
+            TokenKind::Word(word) => self.instruction(word, head, args),
+            _ => self.error(
+                head.column,
+                format!("expected a statement, found {}", head.describe()),
+            ),
+        }
+    }
+
+    /// check the operand count, reporting the first missing or extra operand
+    fn arity(&mut self, head: &Token, name: &str, args: &[Token], count: usize) -> bool {
+        if args.len() < count {
+            self.error(
+                head.column,
+                format!("{} takes {}", name, count_operands(count)),
+            );
+            return false;
+        }
+        if let Some(extra) = args.get(count) {
+            self.error(
+                extra.column,
+                format!(
+                    "unexpected {}, {} takes {}",
+                    extra.describe(),
+                    name,
+                    count_operands(count)
+                ),
+            );
+            return false;
+        }
+        true
+    }
+
+    fn word(&mut self, token: &Token) -> Option<String> {
+        match &token.kind {
+            TokenKind::Word(word) => Some(word.clone()),
+            _ => {
+                self.error(
+                    token.column,
+                    format!("expected a name, found {}", token.describe()),
+                );
+                None
+            }
+        }
+    }
+
+    fn string(&mut self, token: &Token) -> Option<String> {
+        match &token.kind {
+            TokenKind::Str(text) => Some(text.clone()),
+            _ => {
+                self.error(
+                    token.column,
+                    format!("expected a string, found {}", token.describe()),
+                );
+                None
+            }
+        }
+    }
+
+    fn int<T: TryFrom<i64>>(&mut self, token: &Token) -> Option<T> {
+        let TokenKind::Word(word) = &token.kind else {
+            self.error(
+                token.column,
+                format!("expected a number, found {}", token.describe()),
+            );
+            return None;
+        };
+        let Some(value) = parse_int(word) else {
+            self.error(token.column, format!("expected a number, found `{}`", word));
+            return None;
+        };
+        match T::try_from(value) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.error(token.column, format!("{} is out of range", value));
+                None
+            }
+        }
+    }
+
+    fn directive(&mut self, name: &str, head: &Token, args: &[Token]) {
+        match name {
+            ".title" => {
+                if self.arity(head, name, args, 1) {
+                    if let Some(title) = self.string(&args[0]) {
+                        self.title = title;
+                    }
+                }
+            }
+            ".game_mode" => {
+                if self.arity(head, name, args, 1) {
+                    if let Some(mode) = self.int(&args[0]) {
+                        self.game_mode = mode;
+                    }
+                }
+            }
+            ".globals" => {
+                if self.arity(head, name, args, 2) {
+                    if let (Some(non_volatile), Some(volatile)) =
+                        (self.int(&args[0]), self.int(&args[1]))
+                    {
+                        self.globals = (non_volatile, volatile);
+                    }
+                }
+            }
+            ".nls" => {
+                if !self.arity(head, name, args, 1) {
+                    return;
+                }
+                let Some(nls) = self.word(&args[0]) else {
+                    return;
+                };
+                if nls.parse::<Nls>().is_err() {
+                    self.error(args[0].column, format!("unknown text encoding `{}`", nls));
+                }
+                self.nls = Some(nls);
+            }
+            ".entry" => {
+                if self.arity(head, name, args, 1) {
+                    if let Some(routine) = self.word(&args[0]) {
+                        self.entry = Some(self.reference(&routine, args[0].column));
+                    }
+                }
+            }
+            ".syscall" => {
+                if !self.arity(head, name, args, 2) {
+                    return;
+                }
+                if let (Some(name), Some(args_count)) = (self.word(&args[0]), self.int(&args[1])) {
+                    self.declare_syscall(&name, args[0].column);
+                    self.syscalls.push(SyscallEntry {
+                        id: self.syscalls.len() as u32,
+                        name,
+                        args_count,
+                    });
+                }
+            }
+            ".custom_syscall" => {
+                if !self.arity(head, name, args, 3) {
+                    return;
+                }
+                if let (Some(name), Some(routine), Some(args_count)) =
+                    (self.word(&args[0]), self.word(&args[1]), self.int(&args[2]))
+                {
+                    self.declare_syscall(&name, args[0].column);
+                    let routine = self.reference(&routine, args[1].column);
+                    let entry = CustomSyscallEntry {
+                        address: 0,
+                        name,
+                        args_count,
+                        routine: None,
+                    };
+                    self.custom_syscalls.push((entry, routine));
+                }
+            }
+            ".function" => {
+                if !self.arity(head, name, args, 3) {
+                    return;
+                }
+                if let (Some(name), Some(args_count), Some(locals_count)) = (
+                    self.word(&args[0]),
+                    self.int::<u8>(&args[1]),
+                    self.int::<u8>(&args[2]),
+                ) {
+                    self.function(name, args[0].column, args_count, locals_count);
+                }
+            }
+            _ => self.error(head.column, format!("unknown directive `{}`", name)),
+        }
+    }
+
+    fn declare_syscall(&mut self, name: &str, column: usize) {
+        let declared = self.syscalls.iter().any(|s| s.name == name)
+            || self.custom_syscalls.iter().any(|(s, _)| s.name == name);
+        if declared {
+            self.error(column, format!("syscall `{}` is already declared", name));
+        }
+    }
+
+    fn define_label(&mut self, label: &Reference) -> bool {
+        if !self.labels.insert(label.name.clone()) {
+            self.diagnostics.push(Diagnostic {
+                line: label.line,
+                column: label.column,
+                message: format!("`{}` is already defined", label.name),
+            });
+            return false;
+        }
+        true
+    }
+
+    fn function(&mut self, name: String, column: usize, args_count: u8, locals_count: u8) {
+        self.unused_labels();
+        let label = self.reference(&name, column);
+        if self.define_label(&label) {
+            self.routines.insert(name.clone());
+        }
+
+        let init_stack = Inst2::new(
+            None,
+            "init_stack".to_string(),
+            vec![args_count.to_string(), locals_count.to_string()],
+        );
+        self.functions.push(Function::new(name, args_count, locals_count, vec![init_stack]));
+    }
+
+    /// labels must be followed by an instruction of the same routine
+    fn unused_labels(&mut self) {
+        for label in std::mem::take(&mut self.pending_labels) {
+            self.diagnostics.push(Diagnostic {
+                line: label.line,
+                column: label.column,
+                message: format!("label `{}` is not followed by an instruction", label.name),
+            });
+        }
+    }
+
+    fn instruction(&mut self, mnemonic: &str, head: &Token, args: &[Token]) {
+        // the labels are defined even if the instruction is wrong, so their uses are not reported
+        let mut label = None;
+        for pending in std::mem::take(&mut self.pending_labels) {
+            // an instruction holds one label, the others are aliases of it
+            if self.define_label(&pending) && label.is_none() {
+                label = Some(pending.name);
+            }
+        }
+
+        let opcode = match Opcode::try_from(mnemonic) {
+            Ok(Opcode::InitStack) => {
+                return self.error(head.column, "routines are started with .function");
+            }
+            Ok(opcode) => opcode,
+            Err(_) => {
+                return self.error(head.column, format!("unknown instruction `{}`", mnemonic))
+            }
+        };
+        if self.functions.is_empty() {
+            return self.error(
+                head.column,
+                "instruction outside of a routine, add .function",
+            );
+        }
+        let kinds = operands(opcode);
+        if !self.arity(head, mnemonic, args, kinds.len()) {
+            return;
+        }
+
+        let mut operands = Vec::new();
+        for (kind, token) in kinds.iter().zip(args) {
+            let operand = match kind {
+                Operand::Int(min, max) => self.int::<i64>(token).and_then(|value| {
+                    if value < *min || value > *max {
+                        self.error(token.column, format!("{} is out of range", value));
+                        return None;
+                    }
+                    Some(value.to_string())
+                }),
+                Operand::Float => self.word(token).and_then(|word| {
+                    if word.parse::<f32>().is_err() {
+                        self.error(token.column, format!("expected a float, found `{}`", word));
+                        return None;
+                    }
+                    Some(word)
+                }),
+                Operand::Str => self.string(token),
+                Operand::Target => self.word(token).and_then(|word| {
+                    if parse_int(&word).is_some() {
+                        self.error(token.column, "branch targets are routine names or labels");
+                        return None;
+                    }
+                    self.targets.push(self.reference(&word, token.column));
+                    Some(word)
+                }),
+                Operand::Value => match &token.kind {
+                    TokenKind::Word(word) if parse_int(word).is_none() => {
+                        self.targets.push(self.reference(word, token.column));
+                        Some(word.clone())
+                    }
+                    _ => self.int::<i32>(token).map(|value| value.to_string()),
+                },
+                Operand::Syscall => self.word(token).inspect(|word| {
+                    self.syscall_uses.push(self.reference(word, token.column));
+                }),
+            };
+            let Some(operand) = operand else {
+                return;
+            };
+            operands.push(operand);
+        }
+
+        let inst = Inst2::new(label, mnemonic.to_string(), operands);
+        self.functions.last_mut().unwrap().push_inst(inst);
+    }
+
+    /// check the names used before their definition and build the configuration
+    fn finish(mut self) -> Result<(ProjectConfig, Vec<Function>), Vec<Diagnostic>> {
+        self.unused_labels();
+
+        let mut unknown = Vec::new();
+        for target in &self.targets {
+            if !self.labels.contains(&target.name) {
+                unknown.push((target, format!("unknown label `{}`", target.name)));
+            }
+        }
+        for syscall in &self.syscall_uses {
+            let declared = self.syscalls.iter().any(|s| s.name == syscall.name)
+                || self
+                    .custom_syscalls
+                    .iter()
+                    .any(|(s, _)| s.name == syscall.name);
+            if !declared {
+                unknown.push((syscall, format!("undeclared syscall `{}`", syscall.name)));
+            }
+        }
+        let routines = self
+            .entry
+            .iter()
+            .chain(self.custom_syscalls.iter().map(|(_, r)| r));
+        for routine in routines {
+            if !self.routines.contains(&routine.name) {
+                unknown.push((routine, format!("`{}` is not a routine", routine.name)));
+            }
+        }
+        let mut diagnostics = unknown
+            .into_iter()
+            .map(|(reference, message)| Diagnostic {
+                line: reference.line,
+                column: reference.column,
+                message,
+            })
+            .collect::<Vec<_>>();
+
+        if self.entry.is_none() {
+            diagnostics.push(Diagnostic {
+                line: 1,
+                column: 1,
+                message: "the entry point is not set, add .entry".to_string(),
+            });
+        }
+
+        diagnostics.append(&mut self.diagnostics);
+        if !diagnostics.is_empty() {
+            diagnostics.sort_by_key(|d| (d.line, d.column));
+            return Err(diagnostics);
+        }
+
+        let custom_syscalls = self
+            .custom_syscalls
+            .into_iter()
+            .map(|(mut entry, routine)| {
+                entry.routine = Some(routine.name);
+                entry
+            })
+            .collect();
+        // the routines are laid out by the assembler, it finds them by name
+        let config = ProjectConfig {
+            entry_point: 0,
+            entry: self.entry.map(|entry| entry.name),
+            non_volatile_global_count: self.globals.0,
+            volatile_global_count: self.globals.1,
+            game_mode: self.game_mode,
+            game_title: self.title,
+            syscalls: self.syscalls,
+            custom_syscalls,
+            nls: self.nls,
+            scenario_checksum: None,
+        };
+        Ok((config, self.functions))
+    }
+}
+
+/// parse a `.fvpasm` source into the configuration and the routines of a project
+pub fn parse(source: &str) -> Result<(ProjectConfig, Vec<Function>), Vec<Diagnostic>> {
+    let mut parser = Parser::default();
+    for (index, line) in source.lines().enumerate() {
+        parser.line = index + 1;
+        parser.parse_line(line);
+    }
+    parser.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+; a routine calling itself
+.title "Snow"
+.globals 2 3
+.nls sjis
+.entry main
+
+.syscall ThreadNext 0
+.custom_syscall Double twice 1
+
+.function main 0 0
+loop: push_string "say \"hi\"\n\u{3042}"
+    push_i8 -5
+    syscall Double ; calls twice
+    call twice
+    jz loop
+
+.function twice 1 0
+    push_stack -2
+    retv
+"#;
+
+    #[test]
+    fn parse_source() {
+        let (config, functions) = parse(SOURCE).unwrap();
+        assert_eq!(config.game_title, "Snow");
+        assert_eq!(config.nls.as_deref(), Some("sjis"));
+        assert_eq!(
+            (
+                config.non_volatile_global_count,
+                config.volatile_global_count
+            ),
+            (2, 3)
+        );
+        assert_eq!(config.entry.as_deref(), Some("main"));
+        assert_eq!(config.custom_syscalls[0].routine.as_deref(), Some("twice"));
+        assert_eq!(functions[1].get_address(), None);
+
+        let insts = functions[0].get_insts();
+        assert_eq!(insts.len(), 6);
+        assert!(matches!(insts[0].get_opcode(), Ok(Opcode::InitStack)));
+        let insts = serde_yaml::to_value(insts).unwrap();
+        assert_eq!(insts[1]["label"].as_str(), Some("loop"));
+        assert_eq!(
+            insts[1]["operands"][0].as_str(),
+            Some("say \"hi\"\n\u{3042}")
+        );
+        assert_eq!(insts[2]["operands"][0].as_str(), Some("-5"));
+        assert_eq!(insts[4]["operands"][0].as_str(), Some("twice"));
+        assert_eq!(insts[5]["operands"][0].as_str(), Some("loop"));
+    }
+
+    #[test]
+    fn report_errors() {
+        let source = r#".entry main
+.syscall Wait 1
+.function main 0 0
+    push_i8 300
+    jmp nowhere
+    syscall Wait 1
+    push_string "open
+dup:
+dup:
+    frobnicate
+    push_i32 nobody
+"#;
+        let diagnostics = parse(source).unwrap_err();
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "4:13: 300 is out of range",
+                "5:9: unknown label `nowhere`",
+                "6:18: unexpected `1`, syscall takes 1 operand",
+                "7:17: unterminated string",
+                "9:1: `dup` is already defined",
+                "10:5: unknown instruction `frobnicate`",
+                "11:14: unknown label `nobody`",
+            ]
+        );
+    }
+}
@@ -1,26 +1,29 @@
 use anyhow::{bail, Result};
 use clap::Parser;
 use bytes::Bytes;
-use inst::Inst;
-use rfvp_core::format::scenario::instructions::Opcode;
-use rfvp_core::format::scenario::Nls;
+use rfvp_core::format::scenario::instructions::{inst::JmpInst, Inst, Opcode};
+use rfvp_core::vm::address_map::AddressMap;
+use rfvp_core::format::scenario::{
+    verify::{verify, Severity},
+    Nls, Scenario, ScenarioBuilder,
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    cell::RefCell,
     collections::BTreeMap,
     path::{Path, PathBuf},
-    rc::Rc,
 };
 
-use inst::*;
 use utils::*;
 
-mod inst;
+mod fvpasm;
+mod strings;
 mod utils;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FVPProject {
-    config_file: PathBuf,
+    /// not set when the configuration is part of a `.fvpasm` file
+    #[serde(default)]
+    config_file: Option<PathBuf>,
     disassembly_file: PathBuf,
 }
 
@@ -40,15 +43,36 @@ pub struct SyscallEntry {
     args_count: u8,
 }
 
+/// a script function registered as a syscall
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CustomSyscallEntry {
+    address: u32,
+    name: String,
+    args_count: u8,
+    /// the routine by name, from a `.fvpasm` file whose routines have no address
+    #[serde(skip)]
+    routine: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProjectConfig {
     entry_point: u32,
+    /// the entry routine by name, from a `.fvpasm` file whose routines have no address
+    #[serde(skip)]
+    entry: Option<String>,
     non_volatile_global_count: u16,
     volatile_global_count: u16,
     game_mode: u16,
     game_title: String,
     syscalls: Vec<SyscallEntry>,
-    custom_syscall_count: u16,
+    #[serde(default)]
+    custom_syscalls: Vec<CustomSyscallEntry>,
+    /// codepage the strings were decoded with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nls: Option<String>,
+    /// checksum of the disassembled scenario, the address map starts from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scenario_checksum: Option<u64>,
 }
 
 impl ProjectConfig {
@@ -59,474 +83,417 @@ impl ProjectConfig {
         Ok(config)
     }
 
-    pub fn put_u8(value: u8, buffer: &mut Vec<u8>) {
-        buffer.push(value);
-    }
-
-    pub fn put_u16_le(value: u16, buffer: &mut Vec<u8>) {
-        buffer.push((value & 0xff) as u8);
-        buffer.push(((value >> 8) & 0xff) as u8);
-    }
-
-    pub fn put_u32_le(value: u32, buffer: &mut Vec<u8>) {
-        buffer.push((value & 0xff) as u8);
-        buffer.push(((value >> 8) & 0xff) as u8);
-        buffer.push(((value >> 16) & 0xff) as u8);
-        buffer.push(((value >> 24) & 0xff) as u8);
-    }
-
-    fn string_to_blob(content: &str, nls: Nls) -> Vec<u8> {
-        // convert utf-8 string to local string via Nls
-        let mut content_bytes = match nls {
-            Nls::GBK => encoding_rs::GBK.encode(content).0.to_vec(),
-            Nls::ShiftJIS => encoding_rs::SHIFT_JIS.encode(content).0.to_vec(),
-            Nls::UTF8 => content.as_bytes().to_vec(),
-        };
-
-        if !content_bytes.ends_with(&[0]) {
-            content_bytes.push(0);
-        }
-
-        content_bytes
-    }
-
-    fn serialize_to_binary(&mut self, nls: Nls) -> Result<Vec<u8>> {
-        let mut data = Vec::new();
-        Self::put_u32_le(self.entry_point, &mut data);
-        Self::put_u16_le(self.non_volatile_global_count, &mut data);
-        Self::put_u16_le(self.volatile_global_count, &mut data);
-        Self::put_u16_le(self.game_mode, &mut data);
-
-        let game_title = Self::string_to_blob(&self.game_title, nls.clone());
-        let game_title_len = game_title.len() as u8;
-        Self::put_u8(game_title_len, &mut data);
-        data.extend_from_slice(&game_title);
-
-        Self::put_u16_le(self.syscalls.len() as u16, &mut data);
+    /// write the header after the code area
+    pub fn link(&mut self, entry_point: u32, code: &[u8], nls: Nls) -> Result<Vec<u8>> {
+        self.entry_point = entry_point;
         self.syscalls.sort_by_key(|x| x.id);
+
+        let mut builder = ScenarioBuilder::new(nls);
+        builder
+            .code(code)
+            .entry_point(self.entry_point)
+            .non_volatile_global_count(self.non_volatile_global_count)
+            .volatile_global_count(self.volatile_global_count)
+            .game_mode(self.game_mode)
+            .title(&self.game_title);
         for syscall in &self.syscalls {
-            Self::put_u8(syscall.args_count, &mut data);
-            let syscall_name = Self::string_to_blob(&syscall.name, nls.clone());
-            let syscall_name_len = syscall_name.len() as u8;
-            Self::put_u8(syscall_name_len, &mut data);
-            data.extend_from_slice(&syscall_name);
+            builder.syscall(syscall.args_count, &syscall.name);
         }
-
-        if self.custom_syscall_count > 0 {
-            bail!("custom syscall not supported");
+        for syscall in &self.custom_syscalls {
+            builder.custom_syscall(syscall.address, syscall.args_count, &syscall.name);
         }
 
-        Self::put_u16_le(self.custom_syscall_count, &mut data);
-
-        Ok(data)
-    }
-
-    pub fn link(&mut self, entry_point: u32, nls: Nls) -> Result<Vec<u8>> {
-        self.entry_point = entry_point;
-        self.serialize_to_binary(nls)
+        builder.to_bytes()
     }
 }
 
 pub struct Assembler {
-    project: FVPProject,
     config: ProjectConfig,
     functions: Vec<Function>,
     nls: Nls,
+    /// the instructions hold the address they were disassembled at, a `.fvpasm` file has none
+    original_addresses: bool,
 
     code_section: Vec<u8>,
-}
-
-pub enum InstSet {
-    Nop(NopInst),
-    InitStack(InitStackInst),
-    Call(CallInst),
-    Syscall(SyscallInst),
-    Ret(RetInst),
-    RetV(RetVInst),
-    Jmp(JmpInst),
-    Jz(JzInst),
-    PushNil(PushNilInst),
-    PushTrue(PushTrueInst),
-    PushI32(PushI32Inst),
-    PushI16(PushI16Inst),
-    PushI8(PushI8Inst),
-    PushF32(PushF32Inst),
-    PushString(PushStringInst),
-    PushGlobal(PushGlobalInst),
-    PushStack(PushStackInst),
-    PushGlobalTable(PushGlobalTableInst),
-    PushLocalTable(PushLocalTableInst),
-    PushTop(PushTopInst),
-    PushReturn(PushReturnInst),
-    PopGlobal(PopGlobalInst),
-    PopStack(PopStackInst),
-    PopGlobalTable(PopGlobalTableInst),
-    PopLocalTable(PopLocalTableInst),
-    Neg(NegInst),
-    Add(AddInst),
-    Sub(SubInst),
-    Mul(MulInst),
-    Div(DivInst),
-    Mod(ModInst),
-    BitTest(BitTestInst),
-    And(AndInst),
-    Or(OrInst),
-    SetE(SetEInst),
-    SetNE(SetNEInst),
-    SetG(SetGInst),
-    SetLE(SetLEInst),
-    SetL(SetLInst),
-    SetGE(SetGEInst),
-}
-
-impl InstSet {
-    pub fn set_address(&mut self, address: u32) {
-        match self {
-            InstSet::Nop(inst) => inst.set_address(address),
-            InstSet::InitStack(inst) => inst.set_address(address),
-            InstSet::Call(inst) => inst.set_address(address),
-            InstSet::Syscall(inst) => inst.set_address(address),
-            InstSet::Ret(inst) => inst.set_address(address),
-            InstSet::RetV(inst) => inst.set_address(address),
-            InstSet::Jmp(inst) => inst.set_address(address),
-            InstSet::Jz(inst) => inst.set_address(address),
-            InstSet::PushNil(inst) => inst.set_address(address),
-            InstSet::PushTrue(inst) => inst.set_address(address),
-            InstSet::PushI32(inst) => inst.set_address(address),
-            InstSet::PushI16(inst) => inst.set_address(address),
-            InstSet::PushI8(inst) => inst.set_address(address),
-            InstSet::PushF32(inst) => inst.set_address(address),
-            InstSet::PushString(inst) => inst.set_address(address),
-            InstSet::PushGlobal(inst) => inst.set_address(address),
-            InstSet::PushStack(inst) => inst.set_address(address),
-            InstSet::PushGlobalTable(inst) => inst.set_address(address),
-            InstSet::PushLocalTable(inst) => inst.set_address(address),
-            InstSet::PushTop(inst) => inst.set_address(address),
-            InstSet::PushReturn(inst) => inst.set_address(address),
-            InstSet::PopGlobal(inst) => inst.set_address(address),
-            InstSet::PopStack(inst) => inst.set_address(address),
-            InstSet::PopGlobalTable(inst) => inst.set_address(address),
-            InstSet::PopLocalTable(inst) => inst.set_address(address),
-            InstSet::Neg(inst) => inst.set_address(address),
-            InstSet::Add(inst) => inst.set_address(address),
-            InstSet::Sub(inst) => inst.set_address(address),
-            InstSet::Mul(inst) => inst.set_address(address),
-            InstSet::Div(inst) => inst.set_address(address),
-            InstSet::Mod(inst) => inst.set_address(address),
-            InstSet::BitTest(inst) => inst.set_address(address),
-            InstSet::And(inst) => inst.set_address(address),
-            InstSet::Or(inst) => inst.set_address(address),
-            InstSet::SetE(inst) => inst.set_address(address),
-            InstSet::SetNE(inst) => inst.set_address(address),
-            InstSet::SetG(inst) => inst.set_address(address),
-            InstSet::SetLE(inst) => inst.set_address(address),
-            InstSet::SetL(inst) => inst.set_address(address),
-            InstSet::SetGE(inst) => inst.set_address(address),
-        }
-    }
-
-    pub fn get_address(&self) -> u32 {
-        match self {
-            InstSet::Nop(inst) => inst.address(),
-            InstSet::InitStack(inst) => inst.address(),
-            InstSet::Call(inst) => inst.address(),
-            InstSet::Syscall(inst) => inst.address(),
-            InstSet::Ret(inst) => inst.address(),
-            InstSet::RetV(inst) => inst.address(),
-            InstSet::Jmp(inst) => inst.address(),
-            InstSet::Jz(inst) => inst.address(),
-            InstSet::PushNil(inst) => inst.address(),
-            InstSet::PushTrue(inst) => inst.address(),
-            InstSet::PushI32(inst) => inst.address(),
-            InstSet::PushI16(inst) => inst.address(),
-            InstSet::PushI8(inst) => inst.address(),
-            InstSet::PushF32(inst) => inst.address(),
-            InstSet::PushString(inst) => inst.address(),
-            InstSet::PushGlobal(inst) => inst.address(),
-            InstSet::PushStack(inst) => inst.address(),
-            InstSet::PushGlobalTable(inst) => inst.address(),
-            InstSet::PushLocalTable(inst) => inst.address(),
-            InstSet::PushTop(inst) => inst.address(),
-            InstSet::PushReturn(inst) => inst.address(),
-            InstSet::PopGlobal(inst) => inst.address(),
-            InstSet::PopStack(inst) => inst.address(),
-            InstSet::PopGlobalTable(inst) => inst.address(),
-            InstSet::PopLocalTable(inst) => inst.address(),
-            InstSet::Neg(inst) => inst.address(),
-            InstSet::Add(inst) => inst.address(),
-            InstSet::Sub(inst) => inst.address(),
-            InstSet::Mul(inst) => inst.address(),
-            InstSet::Div(inst) => inst.address(),
-            InstSet::Mod(inst) => inst.address(),
-            InstSet::BitTest(inst) => inst.address(),
-            InstSet::And(inst) => inst.address(),
-            InstSet::Or(inst) => inst.address(),
-            InstSet::SetE(inst) => inst.address(),
-            InstSet::SetNE(inst) => inst.address(),
-            InstSet::SetG(inst) => inst.address(),
-            InstSet::SetLE(inst) => inst.address(),
-            InstSet::SetL(inst) => inst.address(),
-            InstSet::SetGE(inst) => inst.address(),
-        }
-    }
-
-    pub fn size(&self) -> u32 {
-        match self {
-            InstSet::Nop(inst) => inst.size(),
-            InstSet::InitStack(inst) => inst.size(),
-            InstSet::Call(inst) => inst.size(),
-            InstSet::Syscall(inst) => inst.size(),
-            InstSet::Ret(inst) => inst.size(),
-            InstSet::RetV(inst) => inst.size(),
-            InstSet::Jmp(inst) => inst.size(),
-            InstSet::Jz(inst) => inst.size(),
-            InstSet::PushNil(inst) => inst.size(),
-            InstSet::PushTrue(inst) => inst.size(),
-            InstSet::PushI32(inst) => inst.size(),
-            InstSet::PushI16(inst) => inst.size(),
-            InstSet::PushI8(inst) => inst.size(),
-            InstSet::PushF32(inst) => inst.size(),
-            InstSet::PushString(inst) => inst.size(),
-            InstSet::PushGlobal(inst) => inst.size(),
-            InstSet::PushStack(inst) => inst.size(),
-            InstSet::PushGlobalTable(inst) => inst.size(),
-            InstSet::PushLocalTable(inst) => inst.size(),
-            InstSet::PushTop(inst) => inst.size(),
-            InstSet::PushReturn(inst) => inst.size(),
-            InstSet::PopGlobal(inst) => inst.size(),
-            InstSet::PopStack(inst) => inst.size(),
-            InstSet::PopGlobalTable(inst) => inst.size(),
-            InstSet::PopLocalTable(inst) => inst.size(),
-            InstSet::Neg(inst) => inst.size(),
-            InstSet::Add(inst) => inst.size(),
-            InstSet::Sub(inst) => inst.size(),
-            InstSet::Mul(inst) => inst.size(),
-            InstSet::Div(inst) => inst.size(),
-            InstSet::Mod(inst) => inst.size(),
-            InstSet::BitTest(inst) => inst.size(),
-            InstSet::And(inst) => inst.size(),
-            InstSet::Or(inst) => inst.size(),
-            InstSet::SetE(inst) => inst.size(),
-            InstSet::SetNE(inst) => inst.size(),
-            InstSet::SetG(inst) => inst.size(),
-            InstSet::SetLE(inst) => inst.size(),
-            InstSet::SetL(inst) => inst.size(),
-            InstSet::SetGE(inst) => inst.size(),
-        }
-    }
-
-    pub fn serialize_to_binary(&self) -> Vec<u8> {
-        match self {
-            InstSet::Nop(inst) => inst.serialize_to_binary(),
-            InstSet::InitStack(inst) => inst.serialize_to_binary(),
-            InstSet::Call(inst) => inst.serialize_to_binary(),
-            InstSet::Syscall(inst) => inst.serialize_to_binary(),
-            InstSet::Ret(inst) => inst.serialize_to_binary(),
-            InstSet::RetV(inst) => inst.serialize_to_binary(),
-            InstSet::Jmp(inst) => inst.serialize_to_binary(),
-            InstSet::Jz(inst) => inst.serialize_to_binary(),
-            InstSet::PushNil(inst) => inst.serialize_to_binary(),
-            InstSet::PushTrue(inst) => inst.serialize_to_binary(),
-            InstSet::PushI32(inst) => inst.serialize_to_binary(),
-            InstSet::PushI16(inst) => inst.serialize_to_binary(),
-            InstSet::PushI8(inst) => inst.serialize_to_binary(),
-            InstSet::PushF32(inst) => inst.serialize_to_binary(),
-            InstSet::PushString(inst) => inst.serialize_to_binary(),
-            InstSet::PushGlobal(inst) => inst.serialize_to_binary(),
-            InstSet::PushStack(inst) => inst.serialize_to_binary(),
-            InstSet::PushGlobalTable(inst) => inst.serialize_to_binary(),
-            InstSet::PushLocalTable(inst) => inst.serialize_to_binary(),
-            InstSet::PushTop(inst) => inst.serialize_to_binary(),
-            InstSet::PushReturn(inst) => inst.serialize_to_binary(),
-            InstSet::PopGlobal(inst) => inst.serialize_to_binary(),
-            InstSet::PopStack(inst) => inst.serialize_to_binary(),
-            InstSet::PopGlobalTable(inst) => inst.serialize_to_binary(),
-            InstSet::PopLocalTable(inst) => inst.serialize_to_binary(),
-            InstSet::Neg(inst) => inst.serialize_to_binary(),
-            InstSet::Add(inst) => inst.serialize_to_binary(),
-            InstSet::Sub(inst) => inst.serialize_to_binary(),
-            InstSet::Mul(inst) => inst.serialize_to_binary(),
-            InstSet::Div(inst) => inst.serialize_to_binary(),
-            InstSet::Mod(inst) => inst.serialize_to_binary(),
-            InstSet::BitTest(inst) => inst.serialize_to_binary(),
-            InstSet::And(inst) => inst.serialize_to_binary(),
-            InstSet::Or(inst) => inst.serialize_to_binary(),
-            InstSet::SetE(inst) => inst.serialize_to_binary(),
-            InstSet::SetNE(inst) => inst.serialize_to_binary(),
-            InstSet::SetG(inst) => inst.serialize_to_binary(),
-            InstSet::SetLE(inst) => inst.serialize_to_binary(),
-            InstSet::SetL(inst) => inst.serialize_to_binary(),
-            InstSet::SetGE(inst) => inst.serialize_to_binary(),
-        }
-    }
+    /// the new address of every instruction, by the address it was disassembled at
+    address_map: BTreeMap<u32, u32>,
 }
 
 impl Assembler {
+    /// load a project directory, or a single `.fvpasm` file
     pub fn new(project_dir: impl AsRef<Path>, nls: Nls) -> Result<Self> {
-        let proj_path = project_dir.as_ref().join("project.toml");
+        let (config, functions, original_addresses) = if is_fvpasm(project_dir.as_ref()) {
+            let (config, functions) = load_fvpasm(project_dir.as_ref())?;
+            (config, functions, false)
+        } else {
+            load_project(project_dir.as_ref())?
+        };
 
-        let project = FVPProject::new(proj_path)?;
-        let disassembly_path = project_dir.as_ref().join(&project.disassembly_file);
-        let config_path = project_dir.as_ref().join(&project.config_file);
-        let config = ProjectConfig::new(config_path)?;
-        let functions = std::fs::read_to_string(disassembly_path)?;
-        let functions: Vec<Function> = serde_yaml::from_str(&functions)?;
+        // strings are written back in the codepage they were disassembled with
+        let nls = match (nls, &config.nls) {
+            (Nls::Auto, Some(project_nls)) => project_nls.parse()?,
+            (Nls::Auto, None) => bail!("the project does not record its text encoding, pass --nls"),
+            (nls, _) => nls,
+        };
+        log::info!("text encoding: {}", nls);
 
         Ok(Self {
-            project,
             config,
             functions,
             nls,
+            original_addresses,
 
             code_section: Vec::new(),
+            address_map: BTreeMap::new(),
         })
     }
 
     fn inst2_to_inst(
         inst: &Inst2,
-        nls: &Nls,
         syscall_table: &BTreeMap<String, u32>,
-    ) -> Result<InstSet> {
+        targets: &Targets,
+    ) -> Result<Inst> {
         let opcode = inst.get_opcode()?;
         let wrapped_inst = match opcode {
-            Opcode::Nop => InstSet::Nop(to_nop(inst)?),
-            Opcode::InitStack => InstSet::InitStack(to_init_stack(inst)?),
-            Opcode::Call => InstSet::Call(to_call(inst)?),
-            Opcode::Syscall => InstSet::Syscall(to_syscall(inst, syscall_table)?),
-            Opcode::Ret => InstSet::Ret(to_ret(inst)?),
-            Opcode::RetV => InstSet::RetV(to_ret_v(inst)?),
-            Opcode::Jmp => InstSet::Jmp(to_jmp(inst)?),
-            Opcode::Jz => InstSet::Jz(to_jz(inst)?),
-            Opcode::PushNil => InstSet::PushNil(to_push_nil(inst)?),
-            Opcode::PushTrue => InstSet::PushTrue(to_push_true(inst)?),
-            Opcode::PushI32 => InstSet::PushI32(to_push_i32(inst)?),
-            Opcode::PushI16 => InstSet::PushI16(to_push_i16(inst)?),
-            Opcode::PushI8 => InstSet::PushI8(to_push_i8(inst)?),
-            Opcode::PushF32 => InstSet::PushF32(to_push_f32(inst)?),
-            Opcode::PushString => InstSet::PushString(to_push_string(inst, nls.clone())?),
-            Opcode::PushGlobal => InstSet::PushGlobal(to_push_global(inst)?),
-            Opcode::PushStack => InstSet::PushStack(to_push_stack(inst)?),
-            Opcode::PushGlobalTable => InstSet::PushGlobalTable(to_push_global_table(inst)?),
-            Opcode::PushLocalTable => InstSet::PushLocalTable(to_push_local_table(inst)?),
-            Opcode::PushTop => InstSet::PushTop(to_push_top(inst)?),
-            Opcode::PushReturn => InstSet::PushReturn(to_push_return(inst)?),
-            Opcode::PopGlobal => InstSet::PopGlobal(to_pop_global(inst)?),
-            Opcode::PopStack => InstSet::PopStack(to_pop_stack(inst)?),
-            Opcode::PopGlobalTable => InstSet::PopGlobalTable(to_pop_global_table(inst)?),
-            Opcode::PopLocalTable => InstSet::PopLocalTable(to_pop_local_table(inst)?),
-            Opcode::Neg => InstSet::Neg(to_neg(inst)?),
-            Opcode::Add => InstSet::Add(to_add(inst)?),
-            Opcode::Sub => InstSet::Sub(to_sub(inst)?),
-            Opcode::Mul => InstSet::Mul(to_mul(inst)?),
-            Opcode::Div => InstSet::Div(to_div(inst)?),
-            Opcode::Mod => InstSet::Mod(to_mod(inst)?),
-            Opcode::BitTest => InstSet::BitTest(to_bit_test(inst)?),
-            Opcode::And => InstSet::And(to_and(inst)?),
-            Opcode::Or => InstSet::Or(to_or(inst)?),
-            Opcode::SetE => InstSet::SetE(to_set_e(inst)?),
-            Opcode::SetNE => InstSet::SetNE(to_set_ne(inst)?),
-            Opcode::SetG => InstSet::SetG(to_set_g(inst)?),
-            Opcode::SetLE => InstSet::SetLE(to_set_le(inst)?),
-            Opcode::SetL => InstSet::SetL(to_set_l(inst)?),
-            Opcode::SetGE => InstSet::SetGE(to_set_ge(inst)?),
+            Opcode::Nop => Inst::Nop(to_nop(inst)?),
+            Opcode::InitStack => Inst::InitStack(to_init_stack(inst)?),
+            Opcode::Call => Inst::Call(to_call(inst, targets)?),
+            Opcode::Syscall => Inst::Syscall(to_syscall(inst, syscall_table)?),
+            Opcode::Ret => Inst::Ret(to_ret(inst)?),
+            Opcode::RetV => Inst::RetV(to_ret_v(inst)?),
+            Opcode::Jmp => Inst::Jmp(to_jmp(inst, targets)?),
+            Opcode::Jz => Inst::Jz(to_jz(inst, targets)?),
+            Opcode::PushNil => Inst::PushNil(to_push_nil(inst)?),
+            Opcode::PushTrue => Inst::PushTrue(to_push_true(inst)?),
+            Opcode::PushI32 => Inst::PushI32(to_push_i32(inst, targets)?),
+            Opcode::PushI16 => Inst::PushI16(to_push_i16(inst)?),
+            Opcode::PushI8 => Inst::PushI8(to_push_i8(inst)?),
+            Opcode::PushF32 => Inst::PushF32(to_push_f32(inst)?),
+            Opcode::PushString => Inst::PushString(to_push_string(inst)?),
+            Opcode::PushGlobal => Inst::PushGlobal(to_push_global(inst)?),
+            Opcode::PushStack => Inst::PushStack(to_push_stack(inst)?),
+            Opcode::PushGlobalTable => Inst::PushGlobalTable(to_push_global_table(inst)?),
+            Opcode::PushLocalTable => Inst::PushLocalTable(to_push_local_table(inst)?),
+            Opcode::PushTop => Inst::PushTop(to_push_top(inst)?),
+            Opcode::PushReturn => Inst::PushReturn(to_push_return(inst)?),
+            Opcode::PopGlobal => Inst::PopGlobal(to_pop_global(inst)?),
+            Opcode::PopStack => Inst::PopStack(to_pop_stack(inst)?),
+            Opcode::PopGlobalTable => Inst::PopGlobalTable(to_pop_global_table(inst)?),
+            Opcode::PopLocalTable => Inst::PopLocalTable(to_pop_local_table(inst)?),
+            Opcode::Neg => Inst::Neg(to_neg(inst)?),
+            Opcode::Add => Inst::Add(to_add(inst)?),
+            Opcode::Sub => Inst::Sub(to_sub(inst)?),
+            Opcode::Mul => Inst::Mul(to_mul(inst)?),
+            Opcode::Div => Inst::Div(to_div(inst)?),
+            Opcode::Mod => Inst::Mod(to_mod(inst)?),
+            Opcode::BitTest => Inst::BitTest(to_bit_test(inst)?),
+            Opcode::And => Inst::And(to_and(inst)?),
+            Opcode::Or => Inst::Or(to_or(inst)?),
+            Opcode::SetE => Inst::SetE(to_set_e(inst)?),
+            Opcode::SetNE => Inst::SetNE(to_set_ne(inst)?),
+            Opcode::SetG => Inst::SetG(to_set_g(inst)?),
+            Opcode::SetLE => Inst::SetLE(to_set_le(inst)?),
+            Opcode::SetL => Inst::SetL(to_set_l(inst)?),
+            Opcode::SetGE => Inst::SetGE(to_set_ge(inst)?),
         };
 
         Ok(wrapped_inst)
     }
 
-    fn compile(&mut self, old_entry_point: u32) -> Result<u32> {
-        let mut map = BTreeMap::new();
-        for func in &self.functions {
-            for inst in func.get_insts() {
-                let addr = inst.get_address();
-                map.insert(addr, inst);
+    /// Lay the routines out one after the other, in the order they are written
+    fn sequential_layout(sizes: &[u32]) -> Vec<u32> {
+        let mut cursor = 4u32;
+        sizes
+            .iter()
+            .map(|size| {
+                let start = cursor;
+                cursor += size;
+                start
+            })
+            .collect()
+    }
+
+    /// Keep the routines at the address they were disassembled from
+    ///
+    /// A routine that grew into the next one is moved after the code. What is left at its old
+    /// address is its `init_stack` and a `jmp` to the rest of the routine, so the callers and the
+    /// saves that still use the old address end up in the moved routine. The routines added to
+    /// the project go after the code too. Returns the start of every routine and the moved ones.
+    fn stable_layout(
+        &self,
+        routines: &[Vec<Inst>],
+        sizes: &[u32],
+    ) -> Result<(Vec<u32>, Vec<usize>)> {
+        let jmp_size = Inst::Jmp(JmpInst::new(0, 0)).encode(&self.nls)?.len() as u32;
+        let mut originals = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, func)| Some((func.get_address()?, index)))
+            .collect::<Vec<_>>();
+        originals.sort();
+
+        let mut starts = vec![None; self.functions.len()];
+        let mut end = 4u32;
+        for (i, &(address, index)) in originals.iter().enumerate() {
+            // the last routine can grow, nothing follows it
+            let slot = originals.get(i + 1).map(|(next, _)| next - address);
+            if slot == Some(0) {
+                bail!("duplicate address: {}", address);
+            }
+            if slot.is_none_or(|slot| sizes[index] <= slot) {
+                starts[index] = Some(address);
+                end = end.max(address + sizes[index]);
+                continue;
+            }
+
+            let name = self.functions[index].get_name().unwrap_or_default();
+            let Some(init_stack @ Inst::InitStack(_)) = routines[index].first() else {
+                bail!("routine {} grew and does not start with init_stack", name);
+            };
+            let trampoline_size = init_stack.encode(&self.nls)?.len() as u32 + jmp_size;
+            if slot.is_some_and(|slot| slot < trampoline_size) {
+                bail!("routine {} grew and has no room for a jump to its new address", name);
             }
         }
 
-        // phase 1: set address
+        let mut moved = Vec::new();
+        for (index, start) in starts.iter_mut().enumerate() {
+            if start.is_some() {
+                continue;
+            }
+            let function = &self.functions[index];
+            if let Some(address) = function.get_address() {
+                log::info!(
+                    "routine {} moved from 0x{:08x} to 0x{:08x}",
+                    function.get_name().unwrap_or_default(),
+                    address,
+                    end
+                );
+                moved.push(index);
+            }
+            *start = Some(end);
+            end += sizes[index];
+        }
+
+        Ok((starts.into_iter().flatten().collect(), moved))
+    }
+
+    fn compile(&mut self, stable: bool) -> Result<u32> {
+        if stable && !self.original_addresses {
+            bail!("the instructions of a .fvpasm file have no address to keep");
+        }
+
         let mut syscall_table = BTreeMap::new();
         for entry in self.config.syscalls.iter() {
             syscall_table.insert(entry.name.clone(), entry.id);
         }
-        let mut insts = BTreeMap::new();
-        let mut cursor = 4u32;
-        for (addr, inst) in map {
-            let mut wrapped_inst = Self::inst2_to_inst(inst, &self.nls, &syscall_table)?;
-            wrapped_inst.set_address(cursor);
-            let size = wrapped_inst.size();
-            let wrapped_inst = Rc::new(RefCell::new(wrapped_inst));
-            insts.insert(addr, wrapped_inst);
-            cursor += size;
+        // custom syscalls take the ids after the syscall table
+        let syscall_count = self.config.syscalls.len() as u32;
+        for (i, entry) in self.config.custom_syscalls.iter().enumerate() {
+            if syscall_table
+                .insert(entry.name.clone(), syscall_count + i as u32)
+                .is_some()
+            {
+                bail!("custom syscall {} has the name of another syscall", entry.name);
+            }
         }
-        let entry_point = insts
-            .get(&old_entry_point)
-            .ok_or_else(|| anyhow::anyhow!("entry point not found"))?
-            .borrow()
-            .get_address();
-
-        // phase 2: set jump target
-        for (_, inst) in &insts {
-            let inst = &mut *inst.borrow_mut();
-            match inst {
-                InstSet::Jmp(inst) => {
-                    let old_target = inst.get_old_target();
-                    let target_inst = insts
-                        .get(&old_target)
-                        .ok_or_else(|| anyhow::anyhow!(format!("target not found: {}", old_target)))?;
-                    inst.set_target(target_inst.borrow().get_address());
-                }
-                InstSet::Jz(inst) => {
-                    let old_target = inst.get_old_target();
-                    let target_inst = insts
-                        .get(&old_target)
-                        .ok_or_else(|| anyhow::anyhow!(format!("target not found: {}", old_target)))?;
-                    inst.set_target(target_inst.borrow().get_address());
-                }
-                InstSet::Call(inst) => {
-                    let old_target = inst.get_old_func_target();
-                    let target_inst = insts
-                        .get(&old_target)
-                        .ok_or_else(|| anyhow::anyhow!(format!("target not found: {}", old_target)))?;
-                    inst.set_func_target(target_inst.borrow().get_address());
+
+        // phase 1: set address, each routine is laid out in the order its instructions are
+        // written
+        let targets = Targets::new(&self.functions)?;
+        let mut routines = Vec::new();
+        let mut sizes = Vec::new();
+        for func in &self.functions {
+            let mut insts = Vec::new();
+            let mut size = 0;
+            for inst in func.get_insts() {
+                let inst = Self::inst2_to_inst(inst, &syscall_table, &targets)?;
+                size += inst.encode(&self.nls)?.len() as u32;
+                insts.push(inst);
+            }
+            routines.push(insts);
+            sizes.push(size);
+        }
+        let (starts, moved) = if stable {
+            self.stable_layout(&routines, &sizes)?
+        } else {
+            (Self::sequential_layout(&sizes), Vec::new())
+        };
+
+        let mut addresses = Vec::new();
+        self.address_map.clear();
+        for ((func, insts), start) in self.functions.iter().zip(&routines).zip(&starts) {
+            let mut cursor = *start;
+            for (inst2, inst) in func.get_insts().iter().zip(insts) {
+                if let Some(old_address) = inst2.get_address() {
+                    self.address_map.insert(old_address, cursor);
                 }
-                _ => {}
+                addresses.push(cursor);
+                cursor += inst.encode(&self.nls)?.len() as u32;
             }
         }
+        let new_address = |old_address: u32| {
+            Some(addresses[targets.at_address(old_address)? as usize])
+        };
+        // the routines are named in a .fvpasm file, and by their old address in a project
+        let routine_address = |name: Option<&str>, old_address: u32| match name {
+            Some(name) => Some(addresses[targets.label(name)? as usize]),
+            None => new_address(old_address),
+        };
+        let entry_point = routine_address(self.config.entry.as_deref(), self.config.entry_point)
+            .ok_or_else(|| anyhow::anyhow!("entry point not found"))?;
+        for entry in &mut self.config.custom_syscalls {
+            entry.address = routine_address(entry.routine.as_deref(), entry.address)
+                .ok_or_else(|| anyhow::anyhow!("custom syscall {} target not found", entry.name))?;
+        }
 
-        // phase 3: serialize
-        self.code_section.clear();
-        for (_, inst) in insts {
-            let blob = inst.borrow().serialize_to_binary();
-            self.code_section.extend_from_slice(&blob);
+        // phase 2: set jump target, and the routine addresses pushed by push_i32
+        let insts2 = self.functions.iter().flat_map(|func| func.get_insts());
+        for (inst2, inst) in insts2.zip(routines.iter_mut().flatten()) {
+            if let Some(position) = inst.target() {
+                inst.set_target(addresses[position as usize]);
+                continue;
+            }
+            let Inst::PushI32(push) = inst else {
+                continue;
+            };
+            if inst2.get_pushed_label().is_some() {
+                push.set_value(addresses[push.get_value() as usize] as i32);
+                continue;
+            }
+            // a routine pushed by its address, from a project disassembled before it was named
+            let old = push.get_value() as u32;
+            let moved = self.functions.iter().any(|func| func.get_address() == Some(old))
+                && new_address(old).is_some_and(|new| new != old);
+            if moved && !stable {
+                log::warn!(
+                    "push_i32 {}: the routine at this address has moved, name it to follow it",
+                    old
+                );
+            }
+        }
+
+        // phase 3: serialize, the space left by a routine that shrank is filled with nop
+        let end = starts.iter().zip(&sizes).map(|(start, size)| start + size).max();
+        self.code_section = vec![Opcode::Nop as u8; end.unwrap_or(4) as usize - 4];
+        for (insts, start) in routines.iter().zip(&starts) {
+            let mut cursor = *start as usize - 4;
+            for inst in insts {
+                let blob = inst.encode(&self.nls)?;
+                self.code_section[cursor..cursor + blob.len()].copy_from_slice(&blob);
+                cursor += blob.len();
+            }
+        }
+        for index in moved {
+            let address = self.functions[index].get_address().unwrap_or_default();
+            let mut blob = routines[index][0].encode(&self.nls)?;
+            let body = blob.len() as u32;
+            let jmp = Inst::Jmp(JmpInst::new(address + body, starts[index] + body));
+            blob.extend_from_slice(&jmp.encode(&self.nls)?);
+            let cursor = address as usize - 4;
+            self.code_section[cursor..cursor + blob.len()].copy_from_slice(&blob);
         }
 
         Ok(entry_point)
     }
 
-    fn size(&self) -> u32 {
-        self.code_section.len() as u32
+    fn link(&mut self, new_entry_point: u32) -> Result<Vec<u8>> {
+        self.config.link(new_entry_point, &self.code_section, self.nls.clone())
     }
+}
 
-    fn link(&mut self, new_entry_point: u32) -> Result<Vec<u8>> {
-        let mut data = Vec::new();
-        let header_offset = 4 + self.size();
+fn is_fvpasm(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "fvpasm")
+}
 
-        ProjectConfig::put_u32_le(header_offset, &mut data);
-        data.extend_from_slice(&self.code_section);
+/// load the project, and whether its instructions hold their original address
+fn load_project(project_dir: &Path) -> Result<(ProjectConfig, Vec<Function>, bool)> {
+    let project = FVPProject::new(project_dir.join("project.toml"))?;
+    let disassembly_path = project_dir.join(&project.disassembly_file);
+    if is_fvpasm(&disassembly_path) {
+        let (config, functions) = load_fvpasm(&disassembly_path)?;
+        return Ok((config, functions, false));
+    }
 
-        let header = self.config.link(new_entry_point, self.nls.clone())?;
-        data.extend_from_slice(&header);
+    let Some(config_file) = &project.config_file else {
+        bail!("project.toml has no config_file");
+    };
+    let config = ProjectConfig::new(project_dir.join(config_file))?;
+    let functions = std::fs::read_to_string(disassembly_path)?;
+    let functions: Vec<Function> = serde_yaml::from_str(&functions)?;
+    Ok((config, functions, true))
+}
 
-        Ok(data)
-    }
+/// parse a text assembly file, every error is reported with its line and column
+fn load_fvpasm(path: &Path) -> Result<(ProjectConfig, Vec<Function>)> {
+    let source = std::fs::read_to_string(path)?;
+    fvpasm::parse(&source).map_err(|diagnostics| {
+        let mut message = format!("{} errors in {}", diagnostics.len(), path.display());
+        for diagnostic in &diagnostics {
+            message.push_str(&format!("\n{}:{}", path.display(), diagnostic));
+        }
+        anyhow::anyhow!(message)
+    })
 }
 
-fn compile(project_dir: impl AsRef<Path>, output: impl AsRef<Path>, nls: Nls) -> Result<()> {
+fn compile(
+    project_dir: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    nls: Nls,
+    verify: bool,
+    strings: Option<&Path>,
+    stable: bool,
+) -> Result<()> {
     let mut assembler = Assembler::new(project_dir, nls)?;
-    let entry_point = assembler.compile(assembler.config.entry_point)?;
+    if let Some(path) = strings {
+        let entries = strings::read(path)?;
+        strings::apply(&mut assembler.functions, &entries).log();
+    }
+    let entry_point = assembler.compile(stable)?;
     let data = assembler.link(entry_point)?;
+    let scenario = Scenario::new(Bytes::from(data.clone()), Some(assembler.nls.clone()))?;
+    // a scenario that fails the checks is not written
+    if verify {
+        verify_scenario(&scenario)?;
+    }
     let output_path = output.as_ref();
-    std::fs::write(output_path, data)?;
+    std::fs::write(output_path, &data)?;
+
+    // saves made with the original scenario are moved to the new one with the address map
+    match assembler.config.scenario_checksum {
+        Some(original) if assembler.original_addresses => {
+            let mut map = AddressMap::new(original, &scenario);
+            for (old, new) in &assembler.address_map {
+                map.insert(*old, *new);
+            }
+            let map_path = output_path.with_extension("addrmap");
+            map.save(&map_path)?;
+            log::info!("address map written to {}", map_path.display());
+        }
+        None if assembler.original_addresses => {
+            log::warn!("the project doesn't record the original scenario, no address map written")
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// run the static checks over the assembled scenario, any error fails the build
+fn verify_scenario(scenario: &Scenario) -> Result<()> {
+    let diagnostics = verify(scenario);
+
+    let mut errors = 0;
+    for diagnostic in &diagnostics {
+        if diagnostic.severity == Severity::Error {
+            errors += 1;
+        }
+        eprintln!("{}", diagnostic);
+    }
+    if errors > 0 {
+        bail!("the verifier found {} errors", errors);
+    }
 
     Ok(())
 }
@@ -534,26 +501,48 @@ fn compile(project_dir: impl AsRef<Path>, output: impl AsRef<Path>, nls: Nls) ->
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// The project written by the disassembler, or a `.fvpasm` file
     #[clap(short, long)]
     project_dir: String,
     #[clap(short, long)]
     output: String,
+    /// Text encoding of the strings: sjis, gbk, utf8, big5, euckr, cp1252, or auto to use the
+    /// one the project was disassembled with
     #[clap(short, long)]
     nls: Nls,
+    /// check the assembled scenario for broken jumps, stack misuse and undecodable strings
+    #[clap(long)]
+    verify: bool,
+    /// translated strings to put in the scenario, a `.po`, `.csv` or `.json` file exported by
+    /// the disassembler
+    #[clap(long)]
+    strings: Option<PathBuf>,
+    /// keep the routines at the address they were disassembled from, the ones that grew are
+    /// moved after the code with a jump left at their old address
+    #[clap(long)]
+    stable: bool,
 }
 
 fn main() {
     env_logger::init();
     let args = Args::parse();
-    if let Err(e) = compile(args.project_dir, args.output, args.nls) {
+    let strings = args.strings.as_deref();
+    let result = compile(
+        args.project_dir,
+        args.output,
+        args.nls,
+        args.verify,
+        strings,
+        args.stable,
+    );
+    if let Err(e) = result {
         log::error!("Error: {}", e);
+        std::process::exit(1);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use rfvp_core::format::scenario::Scenario;
-
     use super::*;
 
     #[test]
@@ -567,9 +556,149 @@ mod tests {
             "/testcase/Snow_new.bin"
         ));
         let nls = Nls::ShiftJIS;
-        compile(input, output, nls.clone()).unwrap();
+        compile(input, output, nls.clone(), true, None, false).unwrap();
         let outdata = std::fs::read(output).unwrap();
         let outdata = Bytes::from(outdata);
         let _parser = Scenario::new(outdata, Some(nls)).unwrap();
     }
+
+    #[test]
+    fn insert_instructions() {
+        let (config, _) = fvpasm::parse(".entry main\n.function main 0 0\n").unwrap();
+        let yaml = r#"
+- address: 0
+  name: main
+  args_count: 0
+  locals_count: 0
+  insts:
+  - { address: 0, mnemonic: init_stack, operands: ['0', '0'] }
+  - { mnemonic: call, operands: [helper] }
+  - { mnemonic: push_nil, operands: [] }
+  - { address: 1, mnemonic: jmp, operands: ['1'] }
+- name: helper
+  args_count: 0
+  locals_count: 0
+  insts:
+  - { mnemonic: init_stack, operands: ['0', '0'] }
+  - { mnemonic: ret, operands: [] }
+"#;
+        let mut assembler = Assembler {
+            config,
+            functions: serde_yaml::from_str(yaml).unwrap(),
+            nls: Nls::ShiftJIS,
+            original_addresses: true,
+            code_section: Vec::new(),
+            address_map: BTreeMap::new(),
+        };
+        let entry_point = assembler.compile(false).unwrap();
+        assert_eq!(entry_point, 4);
+        // call at 0x07 enters the helper at 0x12, jmp at 0x0D jumps to itself
+        assert_eq!(assembler.code_section[3..8], [0x02, 0x12, 0x00, 0x00, 0x00]);
+        assert_eq!(assembler.code_section[9..14], [0x06, 0x0D, 0x00, 0x00, 0x00]);
+
+        let yaml = r#"
+- name: a
+  args_count: 0
+  locals_count: 0
+  insts: [{ address: 0, mnemonic: ret, operands: [] }]
+- name: b
+  args_count: 0
+  locals_count: 0
+  insts: [{ address: 0, mnemonic: ret, operands: [] }]
+"#;
+        assembler.functions = serde_yaml::from_str(yaml).unwrap();
+        let error = assembler.compile(false).unwrap_err();
+        assert_eq!(error.to_string(), "duplicate address: 0");
+
+        let yaml = "- { args_count: 0, locals_count: 0, insts: [{ mnemonic: ret, operands: [] }] }";
+        assembler.functions = serde_yaml::from_str(yaml).unwrap();
+        let error = assembler.compile(false).unwrap_err();
+        assert_eq!(error.to_string(), "a routine added to the project needs a name");
+    }
+
+    #[test]
+    fn thread_entry() {
+        let (config, _) = fvpasm::parse(".entry main\n.function main 0 0\n").unwrap();
+        let yaml = r#"
+- address: 4
+  name: main
+  args_count: 0
+  locals_count: 0
+  insts:
+  - { address: 4, mnemonic: init_stack, operands: ['0', '0'] }
+  - { mnemonic: nop, operands: [] }
+  - { address: 7, mnemonic: push_i32, operands: [worker] }
+  - { address: 12, mnemonic: push_i32, operands: ['13'] }
+  - { address: 17, mnemonic: ret, operands: [] }
+- address: 18
+  name: worker
+  args_count: 0
+  locals_count: 0
+  insts:
+  - { address: 18, mnemonic: init_stack, operands: ['0', '0'] }
+  - { address: 21, mnemonic: ret, operands: [] }
+"#;
+        let mut assembler = Assembler {
+            config,
+            functions: serde_yaml::from_str(yaml).unwrap(),
+            nls: Nls::ShiftJIS,
+            original_addresses: true,
+            code_section: Vec::new(),
+            address_map: BTreeMap::new(),
+        };
+        assembler.compile(false).unwrap();
+        // the thread routine moved from 0x12 to 0x13, a plain number is left as it is
+        assert_eq!(assembler.code_section[4..9], [0x0A, 0x13, 0x00, 0x00, 0x00]);
+        assert_eq!(assembler.code_section[9..14], [0x0A, 0x0D, 0x00, 0x00, 0x00]);
+        assert_eq!(assembler.address_map.get(&18), Some(&0x13));
+    }
+
+    #[test]
+    fn stable_layout() {
+        let (config, _) = fvpasm::parse(".entry main\n.function main 0 0\n").unwrap();
+        let yaml = r#"
+- address: 4
+  name: main
+  args_count: 0
+  locals_count: 0
+  insts:
+  - { address: 4, mnemonic: init_stack, operands: ['0', '0'] }
+  - { address: 7, mnemonic: call, operands: [helper] }
+  - { mnemonic: nop, operands: [] }
+  - { address: 12, label: again, mnemonic: jmp, operands: [again] }
+- address: 17
+  name: helper
+  args_count: 0
+  locals_count: 0
+  insts:
+  - { address: 17, mnemonic: init_stack, operands: ['0', '0'] }
+  - { address: 20, mnemonic: ret, operands: [] }
+"#;
+        let mut assembler = Assembler {
+            config,
+            functions: serde_yaml::from_str(yaml).unwrap(),
+            nls: Nls::ShiftJIS,
+            original_addresses: true,
+            code_section: Vec::new(),
+            address_map: BTreeMap::new(),
+        };
+        // main grew into the helper, it is moved after it
+        let entry_point = assembler.compile(true).unwrap();
+        assert_eq!(entry_point, 0x15);
+        #[rustfmt::skip]
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            0x06, 0x18, 0x00, 0x00, 0x00, // 0x07: jmp 0x18
+            0x00, 0x00, 0x00, 0x00, 0x00, // 0x0C: nop
+            0x01, 0x00, 0x00, // 0x11: init_stack 0 0
+            0x04, // 0x14: ret
+            0x01, 0x00, 0x00, // 0x15: init_stack 0 0
+            0x02, 0x11, 0x00, 0x00, 0x00, // 0x18: call 0x11
+            0x00, // 0x1D: nop
+            0x06, 0x1E, 0x00, 0x00, 0x00, // 0x1E: jmp 0x1E
+        ];
+        assert_eq!(assembler.code_section, code);
+        let map = assembler.address_map.into_iter().collect::<Vec<_>>();
+        assert_eq!(map, [(4, 0x15), (7, 0x18), (12, 0x1E), (17, 17), (20, 20)]);
+    }
 }
@@ -0,0 +1,263 @@
+//! Import of translated strings.
+//!
+//! The entries come from the string export of the disassembler, as gettext PO, CSV or JSON.
+//! An entry is identified by its routine and the index of the `push_string` in it, its source
+//! text must still be the one of the instruction for the translation to be applied.
+use std::path::Path;
+
+use anyhow::{bail, Context as _, Result};
+use serde::Deserialize;
+
+use crate::utils::Function;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct StringEntry {
+    pub id: String,
+    pub source: String,
+    #[serde(default)]
+    pub translation: String,
+}
+
+/// What importing a string file did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub applied: usize,
+    /// entries without a translation, the source is kept
+    pub untranslated: Vec<String>,
+    /// entries whose source is not the text of the instruction anymore
+    pub stale: Vec<String>,
+    /// entries that don't match a `push_string` of the project
+    pub orphaned: Vec<String>,
+}
+
+impl ImportReport {
+    pub fn log(&self) {
+        for id in &self.untranslated {
+            log::debug!("{}: untranslated", id);
+        }
+        for id in &self.stale {
+            log::warn!(
+                "{}: the source text has changed, the translation is not applied",
+                id
+            );
+        }
+        for id in &self.orphaned {
+            log::warn!("{}: no such string in the project", id);
+        }
+        log::info!(
+            "strings: {} applied, {} untranslated, {} stale, {} orphaned",
+            self.applied,
+            self.untranslated.len(),
+            self.stale.len(),
+            self.orphaned.len()
+        );
+    }
+}
+
+/// Put the translations in place of the strings of the project
+pub fn apply(functions: &mut [Function], entries: &[StringEntry]) -> ImportReport {
+    let mut report = ImportReport::default();
+    for entry in entries {
+        let inst = entry.id.rsplit_once(':').and_then(|(name, index)| {
+            let function = functions.iter_mut().find(|f| f.get_name().as_deref() == Some(name))?;
+            function
+                .get_insts_mut()
+                .get_mut(index.parse::<usize>().ok()?)
+        });
+        let Some(inst) = inst.filter(|inst| inst.get_string().is_some()) else {
+            report.orphaned.push(entry.id.clone());
+            continue;
+        };
+
+        if inst.get_string() != Some(entry.source.as_str()) {
+            report.stale.push(entry.id.clone());
+        } else if entry.translation.is_empty() {
+            report.untranslated.push(entry.id.clone());
+        } else {
+            inst.set_string(entry.translation.clone());
+            report.applied += 1;
+        }
+    }
+    report
+}
+
+/// the text of a quoted PO string
+fn po_unquote(text: &str) -> Option<String> {
+    let inner = text.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => unquoted.push('\n'),
+            'r' => unquoted.push('\r'),
+            't' => unquoted.push('\t'),
+            c => unquoted.push(c),
+        }
+    }
+    Some(unquoted)
+}
+
+/// Read a gettext PO file, the context of an entry is its id
+///
+/// Fuzzy entries are read as untranslated.
+fn read_po(source: &str) -> Result<Vec<StringEntry>> {
+    #[derive(PartialEq)]
+    enum Field {
+        Context,
+        Id,
+        Str,
+    }
+
+    let mut entries = Vec::new();
+    let mut entry = StringEntry::default();
+    let mut field = None;
+    let mut fuzzy = false;
+    let mut push = |entry: &mut StringEntry, fuzzy: &mut bool| {
+        let mut entry = std::mem::take(entry);
+        if *fuzzy {
+            entry.translation.clear();
+        }
+        *fuzzy = false;
+        // the header has no context
+        if !entry.id.is_empty() {
+            entries.push(entry);
+        }
+    };
+
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('"') {
+            let Some(field) = &field else {
+                bail!("line {}: string outside of an entry", index + 1);
+            };
+            let text =
+                po_unquote(line).with_context(|| format!("line {}: invalid string", index + 1))?;
+            match field {
+                Field::Context => entry.id.push_str(&text),
+                Field::Id => entry.source.push_str(&text),
+                Field::Str => entry.translation.push_str(&text),
+            }
+            continue;
+        }
+
+        // anything but a msgstr after the msgstr starts the next entry
+        let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+        if keyword != "msgstr" && field == Some(Field::Str) {
+            push(&mut entry, &mut fuzzy);
+            field = None;
+        }
+        let next = match keyword {
+            "#," => {
+                fuzzy |= rest.split(',').any(|flag| flag.trim() == "fuzzy");
+                continue;
+            }
+            _ if keyword.starts_with('#') => continue,
+            "msgctxt" => Field::Context,
+            "msgid" => Field::Id,
+            "msgstr" => Field::Str,
+            _ => bail!("line {}: unexpected `{}`", index + 1, keyword),
+        };
+        let text =
+            po_unquote(rest).with_context(|| format!("line {}: invalid string", index + 1))?;
+        match next {
+            Field::Context => entry.id = text,
+            Field::Id => entry.source = text,
+            Field::Str => entry.translation = text,
+        }
+        field = Some(next);
+    }
+    push(&mut entry, &mut fuzzy);
+
+    Ok(entries)
+}
+
+/// read a string file, gettext PO, CSV or JSON after its extension
+pub fn read(path: &Path) -> Result<Vec<StringEntry>> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let entries = match extension {
+        Some("po" | "pot") => read_po(&std::fs::read_to_string(path)?)?,
+        Some("csv") => {
+            let mut reader = csv::Reader::from_path(path)?;
+            reader.deserialize().collect::<Result<_, _>>()?
+        }
+        Some("json") => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        _ => bail!("unknown string file format, use .po, .csv or .json"),
+    };
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_po() {
+        let po = r#"msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+
+#. syscall: TextPrint
+msgctxt "main:1"
+msgid "こんにちは"
+msgstr ""
+"Hello,\n"
+"\"world\""
+
+#, fuzzy
+msgctxt "main:3"
+msgid "guess"
+msgstr "Guess"
+
+msgctxt "main:4"
+msgid "old"
+msgstr "Old"
+
+msgctxt "main:2"
+msgid "push_i8"
+msgstr "x"
+
+msgctxt "gone:1"
+msgid "text"
+msgstr "Text"
+"#;
+        let entries = read_po(po).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].translation, "Hello,\n\"world\"");
+        assert_eq!(entries[1].translation, "");
+
+        let yaml = r#"
+- address: 0
+  name: main
+  args_count: 0
+  locals_count: 0
+  insts:
+    - { address: 0, mnemonic: init_stack, operands: ["0", "0"] }
+    - { address: 1, mnemonic: push_string, operands: ["こんにちは"] }
+    - { address: 2, mnemonic: push_i8, operands: ["1"] }
+    - { address: 3, mnemonic: push_string, operands: ["guess"] }
+    - { address: 4, mnemonic: push_string, operands: ["new"] }
+"#;
+        let mut functions: Vec<Function> = serde_yaml::from_str(yaml).unwrap();
+        let report = apply(&mut functions, &entries);
+        assert_eq!(
+            report,
+            ImportReport {
+                applied: 1,
+                untranslated: vec!["main:3".to_string()],
+                stale: vec!["main:4".to_string()],
+                orphaned: vec!["main:2".to_string(), "gone:1".to_string()],
+            }
+        );
+        assert_eq!(
+            functions[0].get_insts()[1].get_string(),
+            Some("Hello,\n\"world\"")
+        );
+    }
+}
@@ -1,37 +1,176 @@
 use std::collections::BTreeMap;
 
-use crate::inst::*;
-use anyhow::Result;
-use rfvp_core::format::scenario::instructions::Opcode;
-use rfvp_core::format::scenario::Nls;
+use anyhow::{bail, Result};
+use rfvp_core::format::scenario::instructions::{inst::*, Opcode};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Function {
-    address: u32,
+    /// where the routine was disassembled from, not set on the routines added to the project
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address: Option<u32>,
+    /// projects disassembled before routines were named have no name
+    #[serde(default)]
+    name: Option<String>,
     args_count: u8,
     locals_count: u8,
     insts: Vec<Inst2>,
 }
 
 impl Function {
+    /// a routine without an address, reached by its name
+    pub fn new(name: String, args_count: u8, locals_count: u8, insts: Vec<Inst2>) -> Self {
+        Self {
+            address: None,
+            name: Some(name),
+            args_count,
+            locals_count,
+            insts,
+        }
+    }
+
+    pub fn get_address(&self) -> Option<u32> {
+        self.address
+    }
+
+    pub fn push_inst(&mut self, inst: Inst2) {
+        self.insts.push(inst);
+    }
+
+    /// the name of the routine, the one the disassembler gives for unnamed ones
+    pub fn get_name(&self) -> Option<String> {
+        match (&self.name, self.address) {
+            (Some(name), _) => Some(name.clone()),
+            (None, Some(address)) => Some(format!("func_{:08x}", address)),
+            (None, None) => None,
+        }
+    }
+
     pub fn get_insts(&self) -> &Vec<Inst2> {
         &self.insts
     }
+
+    pub fn get_insts_mut(&mut self) -> &mut Vec<Inst2> {
+        &mut self.insts
+    }
+}
+
+/// Where the branches go, as the position of the instruction in the project
+///
+/// The instructions are laid out in the order they are written, routines first to last. A
+/// target is a routine name, an instruction label, or the address an instruction was
+/// disassembled at.
+pub struct Targets {
+    labels: BTreeMap<String, u32>,
+    addresses: BTreeMap<u32, u32>,
+}
+
+impl Targets {
+    pub fn new(functions: &[Function]) -> Result<Self> {
+        let mut labels = BTreeMap::new();
+        let mut addresses = BTreeMap::new();
+        let mut position = 0u32;
+        for function in functions {
+            if function.insts.is_empty() {
+                bail!("routine {} has no instructions", function.get_name().unwrap_or_default());
+            }
+            let Some(name) = function.get_name() else {
+                bail!("a routine added to the project needs a name");
+            };
+            let names = std::iter::once((name, position));
+            let insts = function.insts.iter().enumerate().filter_map(|(i, inst)| {
+                Some((inst.label.clone()?, position + i as u32))
+            });
+            for (label, position) in names.chain(insts) {
+                if labels.insert(label.clone(), position).is_some() {
+                    bail!("duplicate label: {}", label);
+                }
+            }
+
+            for inst in &function.insts {
+                if let Some(addr) = inst.address {
+                    if addresses.insert(addr, position).is_some() {
+                        bail!("duplicate address: {}", addr);
+                    }
+                }
+                position += 1;
+            }
+        }
+        Ok(Self { labels, addresses })
+    }
+
+    /// the position of the instruction disassembled at `address`
+    pub fn at_address(&self, address: u32) -> Option<u32> {
+        self.addresses.get(&address).copied()
+    }
+
+    /// the position of a routine or a labeled instruction
+    pub fn label(&self, label: &str) -> Option<u32> {
+        self.labels.get(label).copied()
+    }
+
+    fn resolve(&self, operand: &str) -> Result<u32> {
+        if let Some(position) = self.label(operand) {
+            return Ok(position);
+        }
+        let Ok(address) = operand.parse() else {
+            bail!("unknown label: {}", operand);
+        };
+        self.at_address(address)
+            .ok_or_else(|| anyhow::anyhow!("target not found: {}", address))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Inst2 {
-    address: u32,
+    /// not set on the instructions added to the project, they are reached by label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address: Option<u32>,
+    #[serde(default)]
+    label: Option<String>,
     mnemonic: String,
     operands: Vec<String>,
 }
 
 impl Inst2 {
-    pub fn get_address(&self) -> u32 {
+    /// an instruction without an address, reached by its label
+    pub fn new(label: Option<String>, mnemonic: String, operands: Vec<String>) -> Self {
+        Self {
+            address: None,
+            label,
+            mnemonic,
+            operands,
+        }
+    }
+
+    pub fn get_address(&self) -> Option<u32> {
         self.address
     }
 
+    /// the text of a `push_string`
+    pub fn get_string(&self) -> Option<&str> {
+        match self.mnemonic.as_str() {
+            "push_string" => self.operands.first().map(String::as_str),
+            _ => None,
+        }
+    }
+
+    pub fn set_string(&mut self, text: String) {
+        self.operands = vec![text];
+    }
+
+    /// the routine or label a `push_i32` pushes the address of, like the entry of a thread
+    pub fn get_pushed_label(&self) -> Option<&str> {
+        match self.mnemonic.as_str() {
+            "push_i32" => self
+                .operands
+                .first()
+                .filter(|operand| operand.parse::<i32>().is_err())
+                .map(String::as_str),
+            _ => None,
+        }
+    }
+
     pub fn get_opcode(&self) -> Result<Opcode> {
         match Opcode::try_from(self.mnemonic.as_str()) {
             Ok(opcode) => Ok(opcode),
@@ -40,12 +179,21 @@ impl Inst2 {
     }
 }
 
-pub fn to_nop(_inst: &Inst2) -> Result<NopInst> {
-    Ok(NopInst::new())
+/// the position of the target of a branch
+fn to_target(inst: &Inst2, targets: &Targets) -> Result<u32> {
+    let operand = inst
+        .operands.first()
+        .ok_or(anyhow::anyhow!("missing operand"))?;
+    targets.resolve(operand)
+}
+
+pub fn to_nop(inst: &Inst2) -> Result<NopInst> {
+    Ok(NopInst::new(inst.address.unwrap_or_default()))
 }
 
 pub fn to_init_stack(inst: &Inst2) -> Result<InitStackInst> {
     Ok(InitStackInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -56,12 +204,8 @@ pub fn to_init_stack(inst: &Inst2) -> Result<InitStackInst> {
     ))
 }
 
-pub fn to_call(inst: &Inst2) -> Result<CallInst> {
-    Ok(CallInst::new(
-        inst.operands.first()
-            .ok_or(anyhow::anyhow!("missing operand"))?
-            .parse()?,
-    ))
+pub fn to_call(inst: &Inst2, targets: &Targets) -> Result<CallInst> {
+    Ok(CallInst::new(inst.address.unwrap_or_default(), to_target(inst, targets)?))
 }
 
 pub fn to_syscall(inst: &Inst2, syscalls: &BTreeMap<String, u32>) -> Result<SyscallInst> {
@@ -72,51 +216,50 @@ pub fn to_syscall(inst: &Inst2, syscalls: &BTreeMap<String, u32>) -> Result<Sysc
         .get(syscall_name)
         .ok_or(anyhow::anyhow!("invalid syscall"))?
         .to_owned();
-    Ok(SyscallInst::new(id as u16))
+    Ok(SyscallInst::new(inst.address.unwrap_or_default(), id as u16, syscall_name.to_owned()))
 }
 
-pub fn to_ret(_inst: &Inst2) -> Result<RetInst> {
-    Ok(RetInst::new())
+pub fn to_ret(inst: &Inst2) -> Result<RetInst> {
+    Ok(RetInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_ret_v(_inst: &Inst2) -> Result<RetVInst> {
-    Ok(RetVInst::new())
+pub fn to_ret_v(inst: &Inst2) -> Result<RetValueInst> {
+    Ok(RetValueInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_jmp(inst: &Inst2) -> Result<JmpInst> {
-    Ok(JmpInst::new(
-        inst.operands.first()
-            .ok_or(anyhow::anyhow!("missing operand"))?
-            .parse()?,
-    ))
+pub fn to_jmp(inst: &Inst2, targets: &Targets) -> Result<JmpInst> {
+    Ok(JmpInst::new(inst.address.unwrap_or_default(), to_target(inst, targets)?))
 }
 
-pub fn to_jz(inst: &Inst2) -> Result<JzInst> {
-    Ok(JzInst::new(
-        inst.operands.first()
-            .ok_or(anyhow::anyhow!("missing operand"))?
-            .parse()?,
-    ))
+pub fn to_jz(inst: &Inst2, targets: &Targets) -> Result<JzInst> {
+    Ok(JzInst::new(inst.address.unwrap_or_default(), to_target(inst, targets)?))
 }
 
-pub fn to_push_nil(_inst: &Inst2) -> Result<PushNilInst> {
-    Ok(PushNilInst::new())
+pub fn to_push_nil(inst: &Inst2) -> Result<PushNilInst> {
+    Ok(PushNilInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_push_true(_inst: &Inst2) -> Result<PushTrueInst> {
-    Ok(PushTrueInst::new())
+pub fn to_push_true(inst: &Inst2) -> Result<PushTrueInst> {
+    Ok(PushTrueInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_push_i32(inst: &Inst2) -> Result<PushI32Inst> {
-    Ok(PushI32Inst::new(
-        inst.operands.first()
-            .ok_or(anyhow::anyhow!("missing operand"))?
-            .parse()?,
-    ))
+/// a routine or a label is pushed by position, its address is set once the code is laid out
+pub fn to_push_i32(inst: &Inst2, targets: &Targets) -> Result<PushI32Inst> {
+    let operand = inst
+        .operands.first()
+        .ok_or(anyhow::anyhow!("missing operand"))?;
+    let value = match inst.get_pushed_label() {
+        Some(label) => targets
+            .label(label)
+            .ok_or_else(|| anyhow::anyhow!("unknown label: {}", label))? as i32,
+        None => operand.parse()?,
+    };
+    Ok(PushI32Inst::new(inst.address.unwrap_or_default(), value))
 }
 
 pub fn to_push_i16(inst: &Inst2) -> Result<PushI16Inst> {
     Ok(PushI16Inst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -125,6 +268,7 @@ pub fn to_push_i16(inst: &Inst2) -> Result<PushI16Inst> {
 
 pub fn to_push_i8(inst: &Inst2) -> Result<PushI8Inst> {
     Ok(PushI8Inst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -133,23 +277,25 @@ pub fn to_push_i8(inst: &Inst2) -> Result<PushI8Inst> {
 
 pub fn to_push_f32(inst: &Inst2) -> Result<PushF32Inst> {
     Ok(PushF32Inst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
     ))
 }
 
-pub fn to_push_string(inst: &Inst2, nls: Nls) -> Result<PushStringInst> {
+pub fn to_push_string(inst: &Inst2) -> Result<PushStringInst> {
     Ok(PushStringInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .to_owned(),
-        nls,
     ))
 }
 
 pub fn to_push_global(inst: &Inst2) -> Result<PushGlobalInst> {
     Ok(PushGlobalInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -158,6 +304,7 @@ pub fn to_push_global(inst: &Inst2) -> Result<PushGlobalInst> {
 
 pub fn to_push_stack(inst: &Inst2) -> Result<PushStackInst> {
     Ok(PushStackInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -166,6 +313,7 @@ pub fn to_push_stack(inst: &Inst2) -> Result<PushStackInst> {
 
 pub fn to_push_global_table(inst: &Inst2) -> Result<PushGlobalTableInst> {
     Ok(PushGlobalTableInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -174,22 +322,24 @@ pub fn to_push_global_table(inst: &Inst2) -> Result<PushGlobalTableInst> {
 
 pub fn to_push_local_table(inst: &Inst2) -> Result<PushLocalTableInst> {
     Ok(PushLocalTableInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
     ))
 }
 
-pub fn to_push_top(_inst: &Inst2) -> Result<PushTopInst> {
-    Ok(PushTopInst::new())
+pub fn to_push_top(inst: &Inst2) -> Result<PushTopInst> {
+    Ok(PushTopInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_push_return(_inst: &Inst2) -> Result<PushReturnInst> {
-    Ok(PushReturnInst::new())
+pub fn to_push_return(inst: &Inst2) -> Result<PushReturnInst> {
+    Ok(PushReturnInst::new(inst.address.unwrap_or_default()))
 }
 
 pub fn to_pop_global(inst: &Inst2) -> Result<PopGlobalInst> {
     Ok(PopGlobalInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -198,6 +348,7 @@ pub fn to_pop_global(inst: &Inst2) -> Result<PopGlobalInst> {
 
 pub fn to_pop_stack(inst: &Inst2) -> Result<PopStackInst> {
     Ok(PopStackInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -206,6 +357,7 @@ pub fn to_pop_stack(inst: &Inst2) -> Result<PopStackInst> {
 
 pub fn to_pop_global_table(inst: &Inst2) -> Result<PopGlobalTableInst> {
     Ok(PopGlobalTableInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
@@ -214,69 +366,70 @@ pub fn to_pop_global_table(inst: &Inst2) -> Result<PopGlobalTableInst> {
 
 pub fn to_pop_local_table(inst: &Inst2) -> Result<PopLocalTableInst> {
     Ok(PopLocalTableInst::new(
+        inst.address.unwrap_or_default(),
         inst.operands.first()
             .ok_or(anyhow::anyhow!("missing operand"))?
             .parse()?,
     ))
 }
 
-pub fn to_neg(_inst: &Inst2) -> Result<NegInst> {
-    Ok(NegInst::new())
+pub fn to_neg(inst: &Inst2) -> Result<NegInst> {
+    Ok(NegInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_add(_inst: &Inst2) -> Result<AddInst> {
-    Ok(AddInst::new())
+pub fn to_add(inst: &Inst2) -> Result<AddInst> {
+    Ok(AddInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_sub(_inst: &Inst2) -> Result<SubInst> {
-    Ok(SubInst::new())
+pub fn to_sub(inst: &Inst2) -> Result<SubInst> {
+    Ok(SubInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_mul(_inst: &Inst2) -> Result<MulInst> {
-    Ok(MulInst::new())
+pub fn to_mul(inst: &Inst2) -> Result<MulInst> {
+    Ok(MulInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_div(_inst: &Inst2) -> Result<DivInst> {
-    Ok(DivInst::new())
+pub fn to_div(inst: &Inst2) -> Result<DivInst> {
+    Ok(DivInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_mod(_inst: &Inst2) -> Result<ModInst> {
-    Ok(ModInst::new())
+pub fn to_mod(inst: &Inst2) -> Result<ModInst> {
+    Ok(ModInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_bit_test(_inst: &Inst2) -> Result<BitTestInst> {
-    Ok(BitTestInst::new())
+pub fn to_bit_test(inst: &Inst2) -> Result<BitTestInst> {
+    Ok(BitTestInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_and(_inst: &Inst2) -> Result<AndInst> {
-    Ok(AndInst::new())
+pub fn to_and(inst: &Inst2) -> Result<AndInst> {
+    Ok(AndInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_or(_inst: &Inst2) -> Result<OrInst> {
-    Ok(OrInst::new())
+pub fn to_or(inst: &Inst2) -> Result<OrInst> {
+    Ok(OrInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_set_e(_inst: &Inst2) -> Result<SetEInst> {
-    Ok(SetEInst::new())
+pub fn to_set_e(inst: &Inst2) -> Result<SeteInst> {
+    Ok(SeteInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_set_ne(_inst: &Inst2) -> Result<SetNEInst> {
-    Ok(SetNEInst::new())
+pub fn to_set_ne(inst: &Inst2) -> Result<SetneInst> {
+    Ok(SetneInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_set_g(_inst: &Inst2) -> Result<SetGInst> {
-    Ok(SetGInst::new())
+pub fn to_set_g(inst: &Inst2) -> Result<SetgInst> {
+    Ok(SetgInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_set_le(_inst: &Inst2) -> Result<SetLEInst> {
-    Ok(SetLEInst::new())
+pub fn to_set_le(inst: &Inst2) -> Result<SetleInst> {
+    Ok(SetleInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_set_l(_inst: &Inst2) -> Result<SetLInst> {
-    Ok(SetLInst::new())
+pub fn to_set_l(inst: &Inst2) -> Result<SetlInst> {
+    Ok(SetlInst::new(inst.address.unwrap_or_default()))
 }
 
-pub fn to_set_ge(_inst: &Inst2) -> Result<SetGEInst> {
-    Ok(SetGEInst::new())
+pub fn to_set_ge(inst: &Inst2) -> Result<SetgeInst> {
+    Ok(SetgeInst::new(inst.address.unwrap_or_default()))
 }
 
@@ -0,0 +1,346 @@
+use anyhow::{bail, Context as _, Result};
+use clap::Parser as ClapParser;
+use rfvp_core::vm::coverage::Coverage;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Deserialize)]
+struct FVPProject {
+    disassembly_file: PathBuf,
+}
+
+/// A routine of `disassembly.yaml`, the fields this tool does not know are kept as they are
+#[derive(Debug, Serialize, Deserialize)]
+struct Function {
+    address: u32,
+    #[serde(flatten)]
+    rest: serde_yaml::Mapping,
+    insts: Vec<Inst>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Inst {
+    address: u32,
+    mnemonic: String,
+    operands: Vec<String>,
+    #[serde(flatten)]
+    rest: serde_yaml::Mapping,
+}
+
+impl Function {
+    /// the name the disassembler gave the routine
+    fn name(&self) -> String {
+        match self.rest.get("name").and_then(|v| v.as_str()) {
+            Some(name) => name.to_string(),
+            None => function_label(self.address),
+        }
+    }
+}
+
+impl Inst {
+    fn is_string(&self) -> bool {
+        self.mnemonic == "push_string"
+    }
+
+    fn hit(&self) -> bool {
+        self.rest
+            .get("hit")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+}
+
+/// mark every instruction as hit or missed, with the threads that executed it
+fn annotate(functions: &mut [Function], coverage: &Coverage) {
+    for inst in functions.iter_mut().flat_map(|f| f.insts.iter_mut()) {
+        let threads = coverage.threads_of(inst.address);
+        inst.rest.insert("hit".into(), (!threads.is_empty()).into());
+        if threads.is_empty() {
+            inst.rest.remove("threads");
+        } else {
+            let threads = threads.into_iter().map(serde_yaml::Value::from);
+            inst.rest.insert("threads".into(), threads.collect());
+        }
+    }
+}
+
+/// Hit and total counts of the instructions, the strings and the routines
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Totals {
+    insts: (usize, usize),
+    strings: (usize, usize),
+    functions: (usize, usize),
+}
+
+impl Totals {
+    fn new(functions: &[Function]) -> Self {
+        let mut totals = Self::default();
+        for function in functions {
+            for inst in &function.insts {
+                count(&mut totals.insts, inst.hit());
+                if inst.is_string() {
+                    count(&mut totals.strings, inst.hit());
+                }
+            }
+            count(
+                &mut totals.functions,
+                function.insts.first().is_some_and(Inst::hit),
+            );
+        }
+        totals
+    }
+}
+
+fn count(counter: &mut (usize, usize), hit: bool) {
+    counter.0 += hit as usize;
+    counter.1 += 1;
+}
+
+fn percent((hit, total): (usize, usize)) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    hit as f64 * 100.0 / total as f64
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, counter) in [
+            ("instructions", self.insts),
+            ("strings", self.strings),
+            ("functions", self.functions),
+        ] {
+            writeln!(
+                f,
+                "{:<13} {:>8} / {:<8} {:>6.2}%",
+                name,
+                counter.0,
+                counter.1,
+                percent(counter)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const STYLE: &str = "body { font-family: sans-serif; }
+table { border-collapse: collapse; }
+td, th { padding: 0 8px; text-align: left; }
+td.code { font-family: monospace; white-space: pre; }
+tr.hit { background: #dfd; }
+tr.miss { background: #fdd; }
+tr.string td.code { font-weight: bold; }";
+
+/// a standalone page with the totals, the routines and their listing
+fn html_report(functions: &[Function], totals: &Totals) -> Result<String> {
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>")?;
+    writeln!(html, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(html, "<title>Scenario coverage</title>")?;
+    writeln!(html, "<style>\n{}\n</style></head><body>", STYLE)?;
+    writeln!(html, "<h1>Scenario coverage</h1>\n<pre>{}</pre>", totals)?;
+
+    writeln!(html, "<h2>Functions</h2>\n<table>")?;
+    writeln!(
+        html,
+        "<tr><th>function</th><th>instructions</th><th>strings</th></tr>"
+    )?;
+    for function in functions {
+        let totals = Totals::new(std::slice::from_ref(function));
+        let class = if totals.functions.0 > 0 {
+            "hit"
+        } else {
+            "miss"
+        };
+        writeln!(
+            html,
+            "<tr class=\"{}\"><td><a href=\"#{label}\">{label}</a></td>\
+             <td>{}/{}</td><td>{}/{}</td></tr>",
+            class,
+            totals.insts.0,
+            totals.insts.1,
+            totals.strings.0,
+            totals.strings.1,
+            label = escape(&function.name()),
+        )?;
+    }
+    writeln!(html, "</table>")?;
+
+    for function in functions {
+        let label = escape(&function.name());
+        writeln!(html, "<h3 id=\"{label}\">{label}</h3>\n<table>")?;
+        for inst in &function.insts {
+            let status = if inst.hit() { "hit" } else { "miss" };
+            let class = if inst.is_string() {
+                format!("{} string", status)
+            } else {
+                status.to_string()
+            };
+            writeln!(
+                html,
+                "<tr class=\"{}\"><td class=\"code\">0x{:08x}</td><td>{}</td>\
+                 <td class=\"code\">{} {}</td></tr>",
+                class,
+                inst.address,
+                status,
+                inst.mnemonic,
+                escape(&inst.operands.join(", ")),
+            )?;
+        }
+        writeln!(html, "</table>")?;
+    }
+
+    writeln!(html, "</body></html>")?;
+    Ok(html)
+}
+
+fn function_label(addr: u32) -> String {
+    format!("func_{:08x}", addr)
+}
+
+fn load_functions(project_dir: &Path) -> Result<Vec<Function>> {
+    let project = std::fs::read_to_string(project_dir.join("project.toml"))?;
+    let project: FVPProject = toml::from_str(&project)?;
+    let path = project_dir.join(project.disassembly_file);
+    if path.extension().is_some_and(|ext| ext != "yaml") {
+        bail!("the report needs a project disassembled to yaml");
+    }
+    let yaml = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(serde_yaml::from_str(&yaml)?)
+}
+
+/// Merges coverage files and maps them onto a disassembled project
+#[derive(ClapParser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Coverage files recorded with the same scenario
+    #[arg(short, long, required = true)]
+    coverage: Vec<PathBuf>,
+
+    /// Where to write the merged coverage
+    #[arg(short, long)]
+    merged: Option<PathBuf>,
+
+    /// The disassembled project of the scenario
+    #[arg(short, long)]
+    project_dir: Option<PathBuf>,
+
+    /// Where to write `disassembly.yaml` with a `hit` field on every instruction
+    #[arg(long)]
+    yaml: Option<PathBuf>,
+
+    /// Where to write the HTML report
+    #[arg(long)]
+    html: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+
+    let mut coverage = Coverage::load(&args.coverage[0])?;
+    for path in &args.coverage[1..] {
+        coverage
+            .merge(&Coverage::load(path)?)
+            .with_context(|| format!("can't merge {}", path.display()))?;
+    }
+    if let Some(path) = &args.merged {
+        coverage.save(path)?;
+    }
+
+    let Some(project_dir) = &args.project_dir else {
+        if args.yaml.is_some() || args.html.is_some() {
+            bail!("the reports need the disassembled project, pass --project-dir");
+        }
+        return Ok(());
+    };
+    let mut functions = load_functions(project_dir)?;
+    annotate(&mut functions, &coverage);
+    let totals = Totals::new(&functions);
+    print!("{}", totals);
+
+    if let Some(path) = &args.yaml {
+        let writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_yaml::to_writer(writer, &functions)?;
+    }
+    if let Some(path) = &args.html {
+        std::fs::write(path, html_report(&functions, &totals)?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rfvp_core::format::scenario::{Nls, ScenarioBuilder};
+
+    #[test]
+    fn annotate_project() {
+        let yaml = r#"
+- address: 4
+  args_count: 0
+  locals_count: 0
+  insts:
+    - { address: 4, mnemonic: init_stack, operands: ["0", "0"] }
+    - { address: 7, mnemonic: jz, operands: ["15"] }
+    - { address: 12, mnemonic: push_string, operands: ["<seen>"] }
+    - { address: 15, mnemonic: push_string, operands: ["unseen"] }
+- address: 18
+  args_count: 0
+  locals_count: 0
+  insts:
+    - { address: 18, mnemonic: ret, operands: [] }
+"#;
+        let mut functions: Vec<Function> = serde_yaml::from_str(yaml).unwrap();
+        let scenario = ScenarioBuilder::new(Nls::ShiftJIS).build().unwrap();
+        let mut coverage = Coverage::new(&scenario);
+        for addr in [4, 7, 12] {
+            coverage.record(0, addr);
+        }
+        coverage.record(3, 12);
+        annotate(&mut functions, &coverage);
+
+        let totals = Totals::new(&functions);
+        assert_eq!(
+            totals,
+            Totals {
+                insts: (3, 5),
+                strings: (1, 2),
+                functions: (1, 2),
+            }
+        );
+
+        let annotated = serde_yaml::to_string(&functions).unwrap();
+        let annotated: serde_yaml::Value = serde_yaml::from_str(&annotated).unwrap();
+        let inst = &annotated[0]["insts"][2];
+        assert_eq!(inst["hit"].as_bool(), Some(true));
+        assert_eq!(
+            inst["threads"],
+            serde_yaml::from_str::<serde_yaml::Value>("[0, 3]").unwrap()
+        );
+        assert_eq!(annotated[0]["args_count"].as_u64(), Some(0));
+        assert!(annotated[0]["insts"][3].get("threads").is_none());
+
+        let html = html_report(&functions, &totals).unwrap();
+        assert!(html.contains("push_string &lt;seen&gt;"));
+    }
+}
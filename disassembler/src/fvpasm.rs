@@ -0,0 +1,119 @@
+//! Writes a project as a single `.fvpasm` file, the text syntax read by the assembler.
+use std::{collections::HashMap, io::Write};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{is_branch, Function, ProjectConfig};
+
+/// quote a string operand, escaping the quotes, backslashes and control characters
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Write the header directives, the syscall declarations and every routine
+///
+/// Every branch target must be a label, a target in the middle of an instruction can only be
+/// kept by the YAML output.
+pub fn write(
+    writer: &mut impl Write,
+    config: &ProjectConfig,
+    functions: &[Function],
+) -> Result<()> {
+    let names: HashMap<u32, &str> = functions
+        .iter()
+        .map(|f| (f.address, f.name.as_str()))
+        .collect();
+    let name_of = |addr: u32| {
+        names
+            .get(&addr)
+            .copied()
+            .ok_or_else(|| anyhow!("0x{:08x} is not the start of a routine", addr))
+    };
+
+    writeln!(writer, ".title {}", quote(&config.game_title))?;
+    writeln!(writer, ".game_mode {}", config.game_mode)?;
+    writeln!(
+        writer,
+        ".globals {} {}",
+        config.non_volatile_global_count, config.volatile_global_count
+    )?;
+    if let Some(nls) = &config.nls {
+        writeln!(writer, ".nls {}", nls)?;
+    }
+    writeln!(writer, ".entry {}", name_of(config.entry_point)?)?;
+
+    // syscalls are declared in the order of their ids
+    let mut syscalls = config.syscalls.iter().collect::<Vec<_>>();
+    syscalls.sort_by_key(|s| s.id);
+    if !syscalls.is_empty() {
+        writeln!(writer)?;
+    }
+    for (id, syscall) in syscalls.iter().enumerate() {
+        if syscall.id != id as u32 {
+            bail!("the syscall ids are not contiguous at {}", syscall.name);
+        }
+        writeln!(writer, ".syscall {} {}", syscall.name, syscall.args_count)?;
+    }
+    for syscall in &config.custom_syscalls {
+        writeln!(
+            writer,
+            ".custom_syscall {} {} {}",
+            syscall.name,
+            name_of(syscall.address)?,
+            syscall.args_count
+        )?;
+    }
+
+    for function in functions {
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            ".function {} {} {}",
+            function.name, function.args_count, function.locals_count
+        )?;
+        // the init_stack starting the routine is written by the directive
+        for inst in function.insts.iter().skip(1) {
+            if let Some(label) = &inst.label {
+                writeln!(writer, "{}:", label)?;
+            }
+            if inst.mnemonic == "push_string" {
+                let text = inst
+                    .operands
+                    .first()
+                    .map(String::as_str)
+                    .unwrap_or_default();
+                writeln!(writer, "    push_string {}", quote(text))?;
+                continue;
+            }
+            if is_branch(&inst.mnemonic) && inst.operands.iter().any(|op| op.parse::<u32>().is_ok())
+            {
+                bail!(
+                    "0x{:08x}: the target of {} is not an instruction, it can't be written as text",
+                    inst.address,
+                    inst.mnemonic
+                );
+            }
+            if inst.operands.is_empty() {
+                writeln!(writer, "    {}", inst.mnemonic)?;
+            } else {
+                writeln!(writer, "    {} {}", inst.mnemonic, inst.operands.join(" "))?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,1130 +1,245 @@
 use anyhow::{bail, Result};
-use clap::Parser as ClapParser;
+use clap::{Parser as ClapParser, ValueEnum};
 use serde::{Deserialize, Serialize};
-use std::mem::size_of;
+use std::collections::{HashMap, HashSet};
 use std::path::{PathBuf, Path};
-use rfvp_core::format::scenario::instructions::{inst::*, Opcode, OpcodeBase};
+use rfvp_core::format::scenario::instructions::{inst::NopInst, Inst as CoreInst, Opcode, OpcodeBase};
 use rfvp_core::format::scenario::{Nls, Scenario};
+use rfvp_core::vm::snapshot::scenario_checksum;
 use bytes::Bytes;
 
 use std::io::Write;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Function {
-    address: u32,
-    args_count: u8,
-    locals_count: u8,
-    insts: Vec<Inst>
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Inst {
-    address: u32,
-    mnemonic: String,
-    operands: Vec<String>,
-}
-
-impl Inst {
-    pub fn from_nop(inst: NopInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_init_stack(inst: InitStackInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_arg_count().to_string(), inst.get_local_count().to_string()],
-        }
-    }
-
-    pub fn from_call(inst: CallInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_target().to_string()],
-        }
-    }
-
-    pub fn from_syscall(inst: SyscallInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_syscall_name().to_string()],
-        }
-    }
-
-    pub fn from_ret(inst: RetInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_ret_value(inst: RetValueInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_jmp(inst: JmpInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_target().to_string()],
-        }
-    }
-
-    pub fn from_jz(inst: JzInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_target().to_string()],
-        }
-    }
-
-    pub fn from_push_nil(inst: PushNilInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_push_true(inst: PushTrueInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_push_i32(inst: PushI32Inst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_value().to_string()],
-        }
-    }
-
-    pub fn from_push_i16(inst: PushI16Inst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_value().to_string()],
-        }
-    }
-
-    pub fn from_push_i8(inst: PushI8Inst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_value().to_string()],
-        }
-    }
-
-    pub fn from_push_f32(inst: PushF32Inst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_value().to_string()],
-        }
-    }
-
-    pub fn from_push_string(inst: PushStringInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_value().to_string()],
-        }
-    }
-
-    pub fn from_push_global(inst: PushGlobalInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_idx().to_string()],
-        }
-    }
-
-    pub fn from_push_stack(inst: PushStackInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_idx().to_string()],
-        }
-    }
-
-    pub fn from_push_global_table(inst: PushGlobalTableInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_idx().to_string()],
-        }
-    }
-
-    pub fn from_push_local_table(inst: PushLocalTableInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_idx().to_string()],
-        }
-    }
-
-    pub fn from_push_top(inst: PushTopInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_push_return(inst: PushReturnInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-    
-    pub fn from_pop_global(inst: PopGlobalInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_idx().to_string()],
-        }
-    }
-
-    pub fn from_pop_stack(inst: PopStackInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_idx().to_string()],
-        }
-    }
-
-    pub fn from_pop_global_table(inst: PopGlobalTableInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_idx().to_string()],
-        }
-    }
-
-    pub fn from_pop_local_table(inst: PopLocalTableInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: vec![inst.get_idx().to_string()],
-        }
-    }
-
-    pub fn from_neg(inst: NegInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_add(inst: AddInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_sub(inst: SubInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_mul(inst: MulInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_div(inst: DivInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_mod(inst: ModInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_bittest(inst: BitTestInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_and(inst: AndInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_or(inst: OrInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_sete(inst: SeteInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_setne(inst: SetneInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_setg(inst: SetgInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_setle(inst: SetleInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_setl(inst: SetlInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-    pub fn from_setge(inst: SetgeInst) -> Self {
-        Self {
-            address: inst.address(),
-            mnemonic: inst.opcode().to_string(),
-            operands: Vec::new(),
-        }
-    }
-
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SyscallEntry {
-    id: u32,
-    name: String,
-    args_count: u8,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ProjectConfig {
-    entry_point: u32,
-    non_volatile_global_count: u16,
-    volatile_global_count: u16,
-    game_mode: u16,
-    game_title: String,
-    syscalls: Vec<SyscallEntry>,
-    custom_syscall_count: u16,
-}
-
-pub struct Disassembler {
-    scenario: Scenario,
-    cursor: usize,
-    functions: Vec<Function>,
-}
-
-impl Disassembler {
-    pub fn new(path: impl AsRef<Path>, nls: Nls) -> Result<Self> {
-        let data = std::fs::read(path.as_ref())?;
-        let data = Bytes::from(data);
-        let scenario = Scenario::new(data, Some(nls))?;
-        Ok(Self {
-            scenario,
-            cursor: 4,
-            functions: Vec::new(),
-        })
-    }
-
-    pub fn get_scenario(&self) -> &Scenario {
-        &self.scenario
-    }
-
-    pub fn get_pc(&self) -> usize {
-        self.cursor
-    }
-
-    /// 0x00 nop instruction
-    /// nop, no operation
-    pub fn nop(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let inst = NopInst::new(addr);
-        let inst = Inst::from_nop(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x01 init stack instruction
-    /// initialize the local routine stack, as well as
-    /// the post-phase of perforimg call instruction or launching a new routine
-    pub fn init_stack(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        // how many arguments are passed to the routine
-        let args_count = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
-        // how many locals are declared in the routine
-        let locals_count = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
-        self.functions.push(Function {
-            address: addr,
-            args_count: args_count as u8,
-            locals_count: locals_count as u8,
-            insts: Vec::new(),
-        });
-
-        let inst = InitStackInst::new(addr, args_count as u8, locals_count as u8);
-        let inst = Inst::from_init_stack(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-        
-        Ok(())
-    }
-
-
-    /// 0x02 call instruction
-    /// call a routine
-    pub fn call(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let target = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
-
-        let inst = CallInst::new(addr, target);
-        let inst = Inst::from_call(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x03 syscall
-    /// call a system call
-    pub fn syscall(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let id = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
-        if let Some(syscall) = scenario.get_syscall(id) {
-            let inst = SyscallInst::new(addr, syscall.name.clone());
-            let inst = Inst::from_syscall(inst);
-            self.functions.last_mut().unwrap().insts.push(inst);
-
-        } else {
-            bail!("syscall not found: {}", id);
-        }
-
-        Ok(())
-    }
-
-    /// 0x04 ret instruction
-    /// return from a routine
-    pub fn ret(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = RetInst::new(addr);
-        let inst = Inst::from_ret(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-        
-        Ok(())
-    }
-
-    /// 0x05 retv instruction
-    /// return from a routine with a value
-    pub fn retv(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = RetValueInst::new(addr);
-        let inst = Inst::from_ret_value(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-        
-        Ok(())
-    }
-
-    /// 0x06 jmp instruction
-    /// jump to the address
-    pub fn jmp(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let target = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
-
-        let inst = JmpInst::new(addr, target);
-        let inst = Inst::from_jmp(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x07 jz instruction
-    /// jump to the address if the top of the stack is zero
-    pub fn jz(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let target = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
-
-        let inst = JzInst::new(addr, target);
-        let inst = Inst::from_jz(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x08 push nil
-    /// push a nil value onto the stack
-    pub fn push_nil(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = PushNilInst::new(addr);
-        let inst = Inst::from_push_nil(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x09 push true
-    /// push a true value onto the stack
-    pub fn push_true(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = PushTrueInst::new(addr);
-        let inst = Inst::from_push_true(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x0A push i32
-    /// push an i32 value onto the stack
-    pub fn push_i32(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let value = scenario.read_i32(self.cursor)?;
-        self.cursor += size_of::<i32>();
-
-        let inst = PushI32Inst::new(addr, value);
-        let inst = Inst::from_push_i32(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x0B push i16
-    /// push an i16 value onto the stack
-    pub fn push_i16(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let value = scenario.read_i16(self.cursor)?;
-        self.cursor += size_of::<i16>();
-
-        let inst = PushI16Inst::new(addr, value);
-        let inst = Inst::from_push_i16(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-        
-        Ok(())
-    }
-
-    /// 0x0C push i8
-    /// push an i8 value onto the stack
-    pub fn push_i8(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let value = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
-        let inst = PushI8Inst::new(addr, value);
-        let inst = Inst::from_push_i8(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x0D push f32
-    /// push an f32 value onto the stack
-    pub fn push_f32(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let value = scenario.read_f32(self.cursor)?;
-        self.cursor += size_of::<f32>();
-
-        let inst = PushF32Inst::new(addr, value);
-        let inst = Inst::from_push_f32(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x0E push string
-    /// push a string onto the stack
-    pub fn push_string(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let len = scenario.read_u8(self.cursor)? as usize;
-        self.cursor += size_of::<u8>();
-
-        let s = scenario.read_cstring(self.cursor, len)?;
-        self.cursor += len;
-
-        let inst = PushStringInst::new(addr, s);
-        let inst = Inst::from_push_string(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x0F push global
-    /// push a global variable onto the stack
-    pub fn push_global(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
-        let inst = PushGlobalInst::new(addr, key as u32);
-        let inst = Inst::from_push_global(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x10 push stack
-    /// push a stack variable onto the stack
-    pub fn push_stack(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let offset = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
-        let inst = PushStackInst::new(addr, offset);
-        let inst = Inst::from_push_stack(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x11 push global table
-    /// push a value than stored in the global table by immediate key onto the stack
-    /// we assume that if any failure occurs, such as the key not found, 
-    /// we will push a nil value onto the stack for compatibility reasons.
-    pub fn push_global_table(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
-        let inst = PushGlobalTableInst::new(addr, key as u32);
-        let inst = Inst::from_push_global_table(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x12 push local table
-    /// push a value than stored in the local table by key onto the stack
-    pub fn push_local_table(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
-        let inst = PushLocalTableInst::new(addr, idx);
-        let inst = Inst::from_push_local_table(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x13 push top
-    /// push the top of the stack onto the stack
-    pub fn push_top(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = PushTopInst::new(addr);
-        let inst = Inst::from_push_top(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x14 push return value
-    /// push the return value onto the stack
-    pub fn push_return_value(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = PushReturnInst::new(addr);
-        let inst = Inst::from_push_return(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x15 pop global
-    /// pop the top of the stack and store it in the global table
-    pub fn pop_global(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
-        let inst = PopGlobalInst::new(addr, key as u32);
-        let inst = Inst::from_pop_global(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x16 local copy
-    /// copy the top of the stack to the local variable
-    pub fn local_copy(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
-        let inst = PopStackInst::new(addr, idx);
-        let inst = Inst::from_pop_stack(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x17 pop global table
-    /// pop the top of the stack and store it in the global table by key
-    pub fn pop_global_table(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
-        let inst = PopGlobalTableInst::new(addr, key as u32);
-        let inst = Inst::from_pop_global_table(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x18 pop local table 
-    /// pop the top of the stack and store it in the local table by key
-    pub fn pop_local_table(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
-        let inst = PopLocalTableInst::new(addr, idx);
-        let inst = Inst::from_pop_local_table(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x19 neg 
-    /// negate the top of the stack, only works for integers and floats
-    pub fn neg(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = NegInst::new(addr);
-        let inst = Inst::from_neg(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x1A add
-    /// add the top two values on the stack
-    pub fn add(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
+mod fvpasm;
+mod strings;
 
-        let inst = AddInst::new(addr);
-        let inst = Inst::from_add(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x1B sub
-    /// subtract the top two values on the stack
-    pub fn sub(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = SubInst::new(addr);
-        let inst = Inst::from_sub(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x1C mul
-    /// multiply the top two values on the stack
-    pub fn mul(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = MulInst::new(addr);
-        let inst = Inst::from_mul(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x1D div
-    /// divide the top two values on the stack
-    pub fn div(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = DivInst::new(addr);
-        let inst = Inst::from_div(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x1E modulo
-    /// modulo the top two values on the stack
-    pub fn modulo(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = ModInst::new(addr);
-        let inst = Inst::from_mod(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x1F bittest
-    /// test with the top two values on the stack
-    pub fn bittest(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = BitTestInst::new(addr);
-        let inst = Inst::from_bittest(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x20 and
-    /// push true if both the top two values on the stack are none-nil
-    pub fn and(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = AndInst::new(addr);
-        let inst = Inst::from_and(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x21 or
-    /// push true if either of the top two values on the stack is none-nil
-    pub fn or(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = OrInst::new(addr);
-        let inst = Inst::from_or(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
-
-    /// 0x22 sete
-    /// set the top of the stack to true if the top two values on the stack are equal
-    pub fn sete(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = SeteInst::new(addr);
-        let inst = Inst::from_sete(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Function {
+    address: u32,
+    /// label of the routine, what `call` refers to it by
+    name: String,
+    args_count: u8,
+    locals_count: u8,
+    insts: Vec<Inst>
+}
 
-    /// 0x23 setne
-    /// set the top of the stack to true if the top two values on the stack are not equal
-    pub fn setne(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Inst {
+    address: u32,
+    /// set on the branch targets that are not the start of a routine
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    mnemonic: String,
+    operands: Vec<String>,
+}
 
-        let inst = SetneInst::new(addr);
-        let inst = Inst::from_setne(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
+impl From<&CoreInst> for Inst {
+    fn from(inst: &CoreInst) -> Self {
+        let operands = match inst {
+            CoreInst::InitStack(inst) => vec![
+                inst.get_arg_count().to_string(),
+                inst.get_local_count().to_string(),
+            ],
+            CoreInst::Call(inst) => vec![inst.get_target().to_string()],
+            CoreInst::Syscall(inst) => vec![inst.get_syscall_name().to_string()],
+            CoreInst::Jmp(inst) => vec![inst.get_target().to_string()],
+            CoreInst::Jz(inst) => vec![inst.get_target().to_string()],
+            CoreInst::PushI32(inst) => vec![inst.get_value().to_string()],
+            CoreInst::PushI16(inst) => vec![inst.get_value().to_string()],
+            CoreInst::PushI8(inst) => vec![inst.get_value().to_string()],
+            CoreInst::PushF32(inst) => vec![inst.get_value().to_string()],
+            CoreInst::PushString(inst) => vec![inst.get_value().to_string()],
+            CoreInst::PushGlobal(inst) => vec![inst.get_idx().to_string()],
+            CoreInst::PushStack(inst) => vec![inst.get_idx().to_string()],
+            CoreInst::PushGlobalTable(inst) => vec![inst.get_idx().to_string()],
+            CoreInst::PushLocalTable(inst) => vec![inst.get_idx().to_string()],
+            CoreInst::PopGlobal(inst) => vec![inst.get_idx().to_string()],
+            CoreInst::PopStack(inst) => vec![inst.get_idx().to_string()],
+            CoreInst::PopGlobalTable(inst) => vec![inst.get_idx().to_string()],
+            CoreInst::PopLocalTable(inst) => vec![inst.get_idx().to_string()],
+            _ => Vec::new(),
+        };
 
-        Ok(())
+        Self {
+            address: inst.address(),
+            label: None,
+            mnemonic: inst.opcode().to_string(),
+            operands,
+        }
     }
+}
 
-    /// 0x24 setg
-    /// set the top of the stack to true if the top two values on the stack are greater
-    pub fn setg(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = SetgInst::new(addr);
-        let inst = Inst::from_setg(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyscallEntry {
+    id: u32,
+    name: String,
+    args_count: u8,
+}
 
-        Ok(())
-    }
+/// a script function registered as a syscall
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CustomSyscallEntry {
+    address: u32,
+    name: String,
+    args_count: u8,
+}
 
-    /// 0x25 setle
-    /// set the top of the stack to true if the top two values on the stack are less or equal
-    pub fn setle(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectConfig {
+    entry_point: u32,
+    non_volatile_global_count: u16,
+    volatile_global_count: u16,
+    game_mode: u16,
+    game_title: String,
+    syscalls: Vec<SyscallEntry>,
+    #[serde(default)]
+    custom_syscalls: Vec<CustomSyscallEntry>,
+    /// codepage the strings were decoded with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nls: Option<String>,
+    /// checksum of the disassembled scenario, the address map starts from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scenario_checksum: Option<u64>,
+}
 
-        let inst = SetleInst::new(addr);
-        let inst = Inst::from_setle(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
+pub struct Disassembler {
+    scenario: Scenario,
+    functions: Vec<Function>,
+}
 
-        Ok(())
+impl Disassembler {
+    pub fn new(path: impl AsRef<Path>, nls: Nls) -> Result<Self> {
+        let data = std::fs::read(path.as_ref())?;
+        let data = Bytes::from(data);
+        let scenario = Scenario::new(data, Some(nls))?;
+        Ok(Self {
+            scenario,
+            functions: Vec::new(),
+        })
     }
 
-    /// 0x26 setl
-    /// set the top of the stack to true if the top two values on the stack are less
-    pub fn setl(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let inst = SetlInst::new(addr);
-        let inst = Inst::from_setl(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
-
-        Ok(())
+    pub fn get_scenario(&self) -> &Scenario {
+        &self.scenario
     }
 
-    /// 0x27 setge
-    /// set the top of the stack to true if the top two values on the stack are greater or equal
-    pub fn setge(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
+    /// append an instruction to the current function, init_stack starts a new one
+    fn push_inst(&mut self, inst: &CoreInst) -> Result<()> {
+        if let CoreInst::InitStack(init) = inst {
+            self.functions.push(Function {
+                address: init.address(),
+                name: function_label(init.address()),
+                args_count: init.get_arg_count(),
+                locals_count: init.get_local_count(),
+                insts: Vec::new(),
+            });
+        }
 
-        let inst = SetgeInst::new(addr);
-        let inst = Inst::from_setge(inst);
-        self.functions.last_mut().unwrap().insts.push(inst);
+        let Some(function) = self.functions.last_mut() else {
+            bail!("instruction outside of a function at 0x{:08x}", inst.address());
+        };
+        function.insts.push(Inst::from(inst));
 
         Ok(())
     }
 
-    fn disassemble_opcode(&mut self, scenario: &Scenario) -> Result<()> {
-        let opcode = scenario.read_u8(self.get_pc())? as i32;
-        
-        match opcode.try_into() {
-            Ok(Opcode::Nop) => {
-                self.nop()?;
-            }
-            Ok(Opcode::InitStack) => {
-                self.init_stack(scenario)?;
-            }
-            Ok(Opcode::Call) => {
-                self.call(scenario)?;
-            }
-            Ok(Opcode::Syscall) => {
-                self.syscall(scenario)?;
-            }
-            Ok(Opcode::Ret) => {
-                self.ret()?;
-            }
-            Ok(Opcode::RetV) => {
-                self.retv()?;
-            }
-            Ok(Opcode::Jmp) => {
-                self.jmp(scenario)?;
-            }
-            Ok(Opcode::Jz) => {
-                self.jz(scenario)?;
-            }
-            Ok(Opcode::PushNil) => {
-                self.push_nil()?;
-            }
-            Ok(Opcode::PushTrue) => {
-                self.push_true()?;
-            }
-            Ok(Opcode::PushI32) => {
-                self.push_i32(scenario)?;
-            }
-            Ok(Opcode::PushI16) => {
-                self.push_i16(scenario)?;
-            }
-            Ok(Opcode::PushI8) => {
-                self.push_i8(scenario)?;
-            }
-            Ok(Opcode::PushF32) => {
-                self.push_f32(scenario)?;
-            }
-            Ok(Opcode::PushString) => {
-                self.push_string(scenario)?;
-            }
-            Ok(Opcode::PushGlobal) => {
-                self.push_global(scenario)?;
-            }
-            Ok(Opcode::PushStack) => {
-                self.push_stack(scenario)?;
-            }
-            Ok(Opcode::PushGlobalTable) => {
-                self.push_global_table(scenario)?;
-            }
-            Ok(Opcode::PushLocalTable) => {
-                self.push_local_table(scenario)?;
-            }
-            Ok(Opcode::PushTop) => {
-                self.push_top()?;
-            }
-            Ok(Opcode::PushReturn) => {
-                self.push_return_value()?;
-            }
-            Ok(Opcode::PopGlobal) => {
-                self.pop_global(scenario)?;
-            }
-            Ok(Opcode::PopStack) => {
-                self.local_copy(scenario)?;
-            }
-            Ok(Opcode::PopGlobalTable) => {
-                self.pop_global_table(scenario)?;
-            }
-            Ok(Opcode::PopLocalTable) => {
-                self.pop_local_table(scenario)?;
-            }
-            Ok(Opcode::Neg) => {
-                self.neg()?;
-            }
-            Ok(Opcode::Add) => {
-                self.add()?;
-            }
-            Ok(Opcode::Sub) => {
-                self.sub()?;
-            }
-            Ok(Opcode::Mul) => {
-                self.mul()?;
-            }
-            Ok(Opcode::Div) => {
-                self.div()?;
-            }
-            Ok(Opcode::Mod) => {
-                self.modulo()?;
-            }
-            Ok(Opcode::BitTest) => {
-                self.bittest()?;
-            }
-            Ok(Opcode::And) => {
-                self.and()?;
-            }
-            Ok(Opcode::Or) => {
-                self.or()?;
-            }
-            Ok(Opcode::SetE) => {
-                self.sete()?;
-            }
-            Ok(Opcode::SetNE) => {
-                self.setne()?;
-            }
-            Ok(Opcode::SetG) => {
-                self.setg()?;
-            }
-            Ok(Opcode::SetLE) => {
-                self.setle()?;
+    pub fn disassemble(&mut self) -> Result<()> {
+        let scenario = self.scenario.clone();
+        let mut iter = scenario.instructions();
+        loop {
+            let offset = iter.offset();
+            let inst = match iter.next() {
+                None => break,
+                Some(Ok((_, inst))) => inst,
+                Some(Err(e)) => {
+                    // unknown opcodes are kept as nop so the layout of the code survives
+                    let opcode = scenario.read_u8(offset)?;
+                    if Opcode::try_from(opcode as i32).is_ok() {
+                        return Err(e);
+                    }
+                    log::error!("unknown opcode: {}", opcode);
+                    CoreInst::Nop(NopInst::new(offset as u32))
+                }
+            };
+            self.push_inst(&inst)?;
+        }
+
+        self.resolve_targets();
+        Ok(())
+    }
+
+    /// Replace the targets of `call`, `jmp` and `jz` with labels
+    ///
+    /// A routine is referred to by its name, any other instruction gets a `L_xxxxxxxx` label.
+    /// Targets that are not the start of an instruction are kept as addresses. The `push_i32` of
+    /// the entry of a thread, right before `ThreadStart`, names the routine too. Any other
+    /// `push_i32` is kept as a number, it may be a constant that happens to equal an address.
+    fn resolve_targets(&mut self) {
+        let targets = self
+            .functions
+            .iter()
+            .flat_map(|f| &f.insts)
+            .filter(|inst| is_branch(&inst.mnemonic))
+            .filter_map(|inst| inst.operands.first()?.parse::<u32>().ok())
+            .collect::<HashSet<_>>();
+
+        let mut labels = HashMap::new();
+        for function in &mut self.functions {
+            if targets.contains(&function.address) {
+                labels.insert(function.address, function.name.clone());
+            }
+            for inst in &mut function.insts {
+                if targets.contains(&inst.address) && !labels.contains_key(&inst.address) {
+                    let label = format!("L_{:08x}", inst.address);
+                    inst.label = Some(label.clone());
+                    labels.insert(inst.address, label);
+                }
             }
-            Ok(Opcode::SetL) => {
-                self.setl()?;
+        }
+
+        let routines = self
+            .functions
+            .iter()
+            .map(|f| (f.address, f.name.clone()))
+            .collect::<HashMap<_, _>>();
+        for function in &mut self.functions {
+            for i in 0..function.insts.len() {
+                if pushes_thread_entry(&function.insts, i) {
+                    let inst = &mut function.insts[i];
+                    let routine = inst.operands.first().and_then(|operand| {
+                        routines.get(&operand.parse::<i32>().ok()?.try_into().ok()?)
+                    });
+                    if let Some(name) = routine {
+                        inst.operands = vec![name.clone()];
+                    }
+                }
             }
-            Ok(Opcode::SetGE) => {
-                self.setge()?;
+        }
+        for inst in self.functions.iter_mut().flat_map(|f| &mut f.insts) {
+            if !is_branch(&inst.mnemonic) {
+                continue;
             }
-            _ => {
-                self.nop()?;
-                log::error!("unknown opcode: {}", opcode);
+            let Some(operand) = inst.operands.first_mut() else {
+                continue;
+            };
+            match operand.parse().ok().and_then(|target: u32| labels.get(&target)) {
+                Some(label) => *operand = label.clone(),
+                None => log::warn!(
+                    "0x{:08x}: the target of {} is not an instruction: {}",
+                    inst.address,
+                    inst.mnemonic,
+                    operand
+                ),
             }
-        };
-
-        Ok(())
-    }
-
-    pub fn disassemble(&mut self) -> Result<()> {
-        let mut scenario = self.scenario.clone();
-        while self.get_pc() < scenario.get_sys_desc_offset() as usize {
-            self.disassemble_opcode(&mut scenario)?;
         }
-
-        Ok(())
     }
 
-    pub fn write_insts(&self, path: impl AsRef<Path>) -> Result<()> {
+    pub fn write_insts(&self, path: impl AsRef<Path>, format: Format) -> Result<()> {
         // create a new directory
         let output = path.as_ref();
         if !output.exists() {
             std::fs::create_dir_all(output)?;
         }
 
-        let disassembly_path = output.join("disassembly.yaml");
-        let mut writer = std::fs::File::create(disassembly_path)?;
-        serde_yaml::to_writer(&mut writer, &self.functions)?;
-
         let config = ProjectConfig {
             entry_point: self.get_scenario().get_entry_point(),
             non_volatile_global_count: self.get_scenario().get_non_volatile_global_count(),
@@ -1138,16 +253,47 @@ impl Disassembler {
                     args_count: sys.args,
                 }
             }).collect(),
-            custom_syscall_count: self.get_scenario().get_custom_syscall_count(),
+            custom_syscalls: self
+                .get_scenario()
+                .get_custom_syscalls()
+                .iter()
+                .map(|sys| CustomSyscallEntry {
+                    address: sys.address,
+                    name: sys.name.clone(),
+                    args_count: sys.args,
+                })
+                .collect(),
+            nls: Some(self.get_scenario().nls.to_string()),
+            scenario_checksum: Some(scenario_checksum(self.get_scenario())),
         };
 
-        let yaml_config = output.join("config.yaml");
-        let mut writer = std::fs::File::create(yaml_config)?;
-        serde_yaml::to_writer(&mut writer, &config)?;
+        let project = match format {
+            Format::Yaml => {
+                let disassembly_path = output.join("disassembly.yaml");
+                let mut writer = std::fs::File::create(disassembly_path)?;
+                serde_yaml::to_writer(&mut writer, &self.functions)?;
+
+                let yaml_config = output.join("config.yaml");
+                let mut writer = std::fs::File::create(yaml_config)?;
+                serde_yaml::to_writer(&mut writer, &config)?;
 
-        let project = FVPProject {
-            config_file: PathBuf::from("config.yaml"),
-            disassembly_file: PathBuf::from("disassembly.yaml"),
+                FVPProject {
+                    config_file: Some(PathBuf::from("config.yaml")),
+                    disassembly_file: PathBuf::from("disassembly.yaml"),
+                }
+            }
+            // the configuration is part of the text
+            Format::Fvpasm => {
+                let disassembly_path = output.join("disassembly.fvpasm");
+                let mut writer = std::io::BufWriter::new(std::fs::File::create(disassembly_path)?);
+                fvpasm::write(&mut writer, &config, &self.functions)?;
+                writer.flush()?;
+
+                FVPProject {
+                    config_file: None,
+                    disassembly_file: PathBuf::from("disassembly.fvpasm"),
+                }
+            }
         };
 
         let toml_project = output.join("project.toml");
@@ -1159,10 +305,27 @@ impl Disassembler {
     }
 }
 
+fn is_branch(mnemonic: &str) -> bool {
+    matches!(mnemonic, "call" | "jmp" | "jz")
+}
+
+/// whether the instruction `i` pushes the entry of a thread, the last argument of `ThreadStart`
+fn pushes_thread_entry(insts: &[Inst], i: usize) -> bool {
+    insts[i].mnemonic == "push_i32"
+        && insts.get(i + 1).is_some_and(|next| {
+            next.mnemonic == "syscall" && next.operands.first().is_some_and(|s| s == "ThreadStart")
+        })
+}
+
+pub fn function_label(addr: u32) -> String {
+    format!("func_{:08x}", addr)
+}
+
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FVPProject {
-    config_file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config_file: Option<PathBuf>,
     disassembly_file: PathBuf,
 }
 
@@ -1176,17 +339,41 @@ struct Args {
     #[arg(short, long, required = true)]
     output: PathBuf,
 
+    /// Text encoding of the scenario: sjis, gbk, utf8, big5, euckr, cp1252 or auto
     #[arg(short, long, default_value = "sjis")]
     lang: Nls,
+
+    /// How to write the instructions: yaml, or fvpasm for a single text assembly file
+    #[arg(short, long, value_enum, default_value_t = Format::Yaml)]
+    format: Format,
+
+    /// Export the strings for translation to this file, gettext `.po`, `.csv` or `.json`
+    #[arg(long)]
+    strings: Option<PathBuf>,
+}
+
+/// What the disassembly is written as
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `disassembly.yaml` and `config.yaml`
+    Yaml,
+    /// `disassembly.fvpasm`
+    Fvpasm,
 }
 
 
 
 fn main() -> Result<()> {
+    env_logger::init();
     let args = Args::parse();
     let mut disassembler = Disassembler::new(args.input, args.lang)?;
     disassembler.disassemble()?;
-    disassembler.write_insts(args.output)?;
+    disassembler.write_insts(args.output, args.format)?;
+    if let Some(path) = &args.strings {
+        let entries = strings::extract(&disassembler.functions);
+        strings::write(path, &entries)?;
+        log::info!("exported {} strings to {}", entries.len(), path.display());
+    }
 
     Ok(())
 }
@@ -1201,8 +388,45 @@ mod tests {
         let output = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/testcase/Snow"));
         let mut disassembler = Disassembler::new(input, Nls::ShiftJIS)?;
         disassembler.disassemble()?;
-        disassembler.write_insts(output)?;
+        disassembler.write_insts(output, Format::Yaml)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn labels_targets() -> Result<()> {
+        use rfvp_core::format::scenario::ScenarioBuilder;
+
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            0x02, 0x20, 0x00, 0x00, 0x00, // 0x07: call 0x20
+            0x0A, 0x20, 0x00, 0x00, 0x00, // 0x0C: push_i32 0x20, a constant
+            0x0C, 0x01, // 0x11: push_i8 1
+            0x0A, 0x20, 0x00, 0x00, 0x00, // 0x13: push_i32 0x20, the entry of the thread
+            0x03, 0x00, 0x00, // 0x18: syscall ThreadStart
+            0x06, 0x07, 0x00, 0x00, 0x00, // 0x1B: jmp 0x07
+            0x01, 0x00, 0x00, // 0x20: init_stack 0 0
+            0x04, // 0x23: ret
+        ];
+        let scenario = ScenarioBuilder::new(Nls::ShiftJIS)
+            .code(&code)
+            .syscall(2, "ThreadStart")
+            .build()?;
+        let mut disassembler = Disassembler {
+            scenario,
+            functions: Vec::new(),
+        };
+        disassembler.disassemble()?;
+
+        let main = &disassembler.functions[0];
+        assert_eq!(main.name, "func_00000004");
+        assert_eq!(main.insts[1].label.as_deref(), Some("L_00000007"));
+        assert_eq!(main.insts[1].operands, ["func_00000020"]);
+        assert_eq!(main.insts[2].operands, ["32"]);
+        assert_eq!(main.insts[4].operands, ["func_00000020"]);
+        assert_eq!(main.insts[6].operands, ["L_00000007"]);
+        assert_eq!(disassembler.functions[1].insts[0].label, None);
 
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,194 @@
+//! Export of the strings of a project for translation.
+//!
+//! Every `push_string` becomes an entry identified by its routine and the index of the
+//! instruction in it, e.g. `func_0000abcd:12`, which stays the same as long as the routine is
+//! not edited. The entries carry what helps a translator: the syscall the string is passed to,
+//! a guess of the speaker and the strings around it.
+use std::{io::Write, path::Path};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::Function;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StringEntry {
+    pub id: String,
+    pub source: String,
+    /// empty until translated
+    pub translation: String,
+    /// the first syscall after the string, what it is most likely passed to
+    pub syscall: Option<String>,
+    pub speaker: Option<String>,
+    /// the strings before and after it in the same routine
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+/// a name the line starts with, `NAME「...` or `【NAME】...`
+fn inline_speaker(text: &str) -> Option<&str> {
+    let name = match text.strip_prefix('【') {
+        Some(rest) => rest.split_once('】')?.0,
+        None => text.split_once(['「', '『'])?.0,
+    };
+    (!name.is_empty() && name.chars().count() <= 16).then_some(name)
+}
+
+/// a short line passed to another syscall than the dialogue after it, a name box
+fn is_name(text: &str) -> bool {
+    !text.is_empty() && text.chars().count() <= 16 && !text.contains(['\n', '「', '。', ' '])
+}
+
+/// collect the strings of every routine, in the order of the routines
+pub fn extract(functions: &[Function]) -> Vec<StringEntry> {
+    let mut entries = Vec::new();
+    for function in functions {
+        let first = entries.len();
+        for (index, inst) in function.insts.iter().enumerate() {
+            if inst.mnemonic != "push_string" {
+                continue;
+            }
+            let syscall = function.insts[index..]
+                .iter()
+                .find(|inst| inst.mnemonic == "syscall")
+                .and_then(|inst| inst.operands.first().cloned());
+            entries.push(StringEntry {
+                id: format!("{}:{}", function.name, index),
+                source: inst.operands.first().cloned().unwrap_or_default(),
+                syscall,
+                ..Default::default()
+            });
+        }
+
+        let strings = &mut entries[first..];
+        for i in 0..strings.len() {
+            let previous = i.checked_sub(1).map(|p| strings[p].clone());
+            strings[i].previous = previous.as_ref().map(|p| p.source.clone());
+            strings[i].next = strings.get(i + 1).map(|n| n.source.clone());
+
+            let speaker = match inline_speaker(&strings[i].source) {
+                Some(name) => Some(name.to_string()),
+                None => previous
+                    .filter(|p| p.syscall != strings[i].syscall && is_name(&p.source))
+                    .map(|p| p.source),
+            };
+            strings[i].speaker = speaker;
+        }
+    }
+    entries
+}
+
+/// quote a PO string, newlines are kept as escapes on a single line
+fn po_quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// one line of a PO comment, the text is shown on a single line
+fn po_comment(text: &str) -> String {
+    text.replace('\n', "\\n")
+}
+
+fn write_po(writer: &mut impl Write, entries: &[StringEntry]) -> Result<()> {
+    writeln!(writer, "msgid \"\"")?;
+    writeln!(writer, "msgstr \"\"")?;
+    writeln!(writer, "\"Content-Type: text/plain; charset=UTF-8\\n\"")?;
+    for entry in entries {
+        writeln!(writer)?;
+        let context = [
+            ("syscall", &entry.syscall),
+            ("speaker", &entry.speaker),
+            ("previous", &entry.previous),
+            ("next", &entry.next),
+        ];
+        for (name, value) in context {
+            if let Some(value) = value {
+                writeln!(writer, "#. {}: {}", name, po_comment(value))?;
+            }
+        }
+        writeln!(writer, "msgctxt {}", po_quote(&entry.id))?;
+        writeln!(writer, "msgid {}", po_quote(&entry.source))?;
+        writeln!(writer, "msgstr {}", po_quote(&entry.translation))?;
+    }
+    Ok(())
+}
+
+/// write the entries as gettext PO, CSV or JSON, after the extension of `path`
+pub fn write(path: &Path, entries: &[StringEntry]) -> Result<()> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    if !matches!(extension, Some("po" | "pot" | "csv" | "json")) {
+        bail!("unknown string file format, use .po, .csv or .json");
+    }
+
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    match extension {
+        Some("po" | "pot") => write_po(&mut writer, entries)?,
+        Some("csv") => {
+            let mut csv = csv::Writer::from_writer(&mut writer);
+            for entry in entries {
+                csv.serialize(entry)?;
+            }
+            csv.flush()?;
+        }
+        _ => serde_json::to_writer_pretty(&mut writer, entries)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_strings() {
+        let yaml = r#"
+- address: 4
+  name: main
+  args_count: 0
+  locals_count: 0
+  insts:
+    - { address: 4, mnemonic: init_stack, operands: ["0", "0"] }
+    - { address: 7, mnemonic: push_string, operands: ["悠"] }
+    - { address: 10, mnemonic: syscall, operands: ["TextName"] }
+    - { address: 13, mnemonic: push_string, operands: ["「おはよう」\n"] }
+    - { address: 16, mnemonic: syscall, operands: ["TextPrint"] }
+    - { address: 19, mnemonic: push_string, operands: ["【雪】\"寒い\""] }
+    - { address: 22, mnemonic: syscall, operands: ["TextPrint"] }
+"#;
+        let functions: Vec<Function> = serde_yaml::from_str(yaml).unwrap();
+        let entries = extract(&functions);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].id, "main:3");
+        assert_eq!(entries[1].syscall.as_deref(), Some("TextPrint"));
+        assert_eq!(entries[1].speaker.as_deref(), Some("悠"));
+        assert_eq!(entries[1].previous.as_deref(), Some("悠"));
+        assert_eq!(entries[2].speaker.as_deref(), Some("雪"));
+        assert_eq!(entries[0].speaker, None);
+
+        let mut po = Vec::new();
+        write_po(&mut po, &entries[1..]).unwrap();
+        let po = String::from_utf8(po).unwrap();
+        let expected = r#"#. syscall: TextPrint
+#. speaker: 悠
+#. previous: 悠
+#. next: 【雪】"寒い"
+msgctxt "main:3"
+msgid "「おはよう」\n"
+msgstr ""
+"#;
+        assert!(po.contains(expected), "{}", po);
+    }
+}
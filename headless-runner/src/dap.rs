@@ -0,0 +1,844 @@
+//! Debug Adapter Protocol server.
+//!
+//! The client drives the runner: nothing is executed before `configurationDone`, and the frames
+//! are run at their simulated duration until a breakpoint, a step or a pause stops the VM.
+//! Breakpoints can be set by address (instruction breakpoints), by label or address (function
+//! breakpoints) and on the lines of the listing built from `disassembly.yaml`. A breakpoint
+//! condition of the form `thread == N` restricts it to a single thread.
+use std::{
+    io::{BufRead, Write},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use rfvp_core::{
+    format::scenario::{
+        context::{CallFrame, CONTEXT_STATUS_NONE},
+        variant::Variant,
+    },
+    vm::debugger::{BreakpointId, StepMode, StopReason},
+};
+use serde_json::{json, Value};
+
+use crate::{
+    symbols::{function_label, Symbols},
+    Runner,
+};
+
+/// the only source the client can see, the listing of the scenario
+const LISTING_REFERENCE: i64 = 1;
+/// frame ids encode the thread and the depth of the frame
+const FRAMES_PER_THREAD: i64 = 1024;
+
+/// Read the messages of the client on a background thread
+pub fn spawn_reader(mut reader: impl BufRead + Send + 'static) -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || loop {
+        match read_message(&mut reader) {
+            Ok(Some(message)) => {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                log::error!("dap: {:#}", e);
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// read a single `Content-Length` framed message, `None` at the end of the stream
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let Some(length) = length else {
+        bail!("message without Content-Length");
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+/// What a `variablesReference` handed to the client points to
+#[derive(Debug, Clone)]
+enum Scope {
+    Arguments(u32, CallFrame),
+    Locals(u32, CallFrame),
+    Globals,
+    Table(Variant),
+}
+
+/// A breakpoint as requested by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakpointKind {
+    Source,
+    Function,
+    Instruction,
+}
+
+pub struct DapServer<W: Write> {
+    runner: Runner,
+    writer: W,
+    symbols: Symbols,
+    frame_time: u64,
+    seq: i64,
+    /// the client sent `configurationDone`, the script may run
+    configured: bool,
+    terminated: bool,
+    /// the breakpoints set by each kind of request, replaced as a whole by the next one
+    breakpoints: Vec<(BreakpointKind, BreakpointId)>,
+    /// valid until the VM continues
+    scopes: Vec<Scope>,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(runner: Runner, writer: W, symbols: Symbols, frame_time: u64) -> Self {
+        Self {
+            runner,
+            writer,
+            symbols,
+            frame_time,
+            seq: 1,
+            configured: false,
+            terminated: false,
+            breakpoints: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// is there something to execute
+    fn is_running(&self) -> bool {
+        self.configured && !self.terminated && !self.runner.scripter().is_stopped()
+    }
+
+    /// Serve the client until it disconnects
+    pub fn serve(&mut self, requests: Receiver<Value>) -> Result<()> {
+        loop {
+            let request = if self.is_running() {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => break,
+                }
+            };
+
+            match request {
+                Some(request) => {
+                    if !self.handle(&request)? {
+                        break;
+                    }
+                }
+                None => {
+                    self.run_frame()?;
+                    std::thread::sleep(Duration::from_millis(self.frame_time));
+                }
+            }
+        }
+
+        self.runner.shutdown()
+    }
+
+    /// Run a frame and report why it ended early
+    pub fn run_frame(&mut self) -> Result<()> {
+        if let Err(e) = self.runner.run_frame(self.frame_time) {
+            self.send_event(
+                "output",
+                json!({ "category": "stderr", "output": format!("{:#}\n", e) }),
+            )?;
+            return self.terminate();
+        }
+
+        if let Some(stop) = self
+            .runner
+            .scripter()
+            .debugger()
+            .and_then(|d| d.get_stop())
+            .copied()
+        {
+            let mut body = json!({
+                "threadId": stop.thread + 1,
+                "allThreadsStopped": true,
+            });
+            body["reason"] = match stop.reason {
+                StopReason::Breakpoint(id) => {
+                    body["hitBreakpointIds"] = json!([id.0]);
+                    "breakpoint".into()
+                }
+                StopReason::Step => "step".into(),
+                StopReason::Pause => "pause".into(),
+            };
+            self.send_event("stopped", body)?;
+        } else if self.runner.is_finished() {
+            self.terminate()?;
+        }
+        Ok(())
+    }
+
+    fn terminate(&mut self) -> Result<()> {
+        if !self.terminated {
+            self.terminated = true;
+            self.send_event("terminated", json!({}))?;
+        }
+        Ok(())
+    }
+
+    /// Handle a request of the client, returns `false` once the client disconnected
+    pub fn handle(&mut self, request: &Value) -> Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        log::debug!("dap: {}", request);
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" | "attach" => {
+                if args["stopOnEntry"].as_bool() == Some(true) {
+                    self.runner.scripter_mut().debugger_mut().request_pause();
+                }
+                Ok(Value::Null)
+            }
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => self.set_source_breakpoints(args),
+            "setFunctionBreakpoints" => {
+                self.set_breakpoints(BreakpointKind::Function, args, |symbols, bp| {
+                    bp["name"].as_str().and_then(|name| symbols.resolve(name))
+                })
+            }
+            "setInstructionBreakpoints" => {
+                self.set_breakpoints(BreakpointKind::Instruction, args, |symbols, bp| {
+                    let addr = symbols.resolve(bp["instructionReference"].as_str()?)?;
+                    let offset = bp["offset"].as_i64().unwrap_or(0);
+                    u32::try_from(addr as i64 + offset).ok()
+                })
+            }
+            "source" => Ok(json!({ "content": self.symbols.listing() })),
+            "threads" => Ok(self.threads()),
+            "stackTrace" => self.stack_trace(args),
+            "scopes" => self.scopes(args),
+            "variables" => self.variables(args),
+            "evaluate" => self.evaluate(args),
+            "continue" => {
+                self.scopes.clear();
+                self.runner.scripter_mut().continue_execution();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => self.step(args, StepMode::Over),
+            "stepIn" => self.step(args, StepMode::Into),
+            "stepOut" => self.step(args, StepMode::Out),
+            "pause" => {
+                self.runner.scripter_mut().debugger_mut().request_pause();
+                Ok(Value::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Value::Null))?;
+                self.terminate()?;
+                return Ok(false);
+            }
+            _ => Err(anyhow!("unsupported request: {}", command)),
+        };
+
+        self.respond(request, result)?;
+        if command == "initialize" {
+            self.send_event("initialized", json!({}))?;
+        }
+        Ok(true)
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value>) -> Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+        });
+        match result {
+            Ok(body) => {
+                response["success"] = true.into();
+                if !body.is_null() {
+                    response["body"] = body;
+                }
+            }
+            Err(e) => {
+                response["success"] = false.into();
+                response["message"] = format!("{:#}", e).into();
+            }
+        }
+        self.send(response)
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        message["seq"] = self.seq.into();
+        self.seq += 1;
+
+        let body = serde_json::to_string(&message)?;
+        write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn listing_source(&self) -> Value {
+        json!({ "name": "disassembly", "sourceReference": LISTING_REFERENCE })
+    }
+
+    fn set_source_breakpoints(&mut self, args: &Value) -> Result<Value> {
+        // lines are 1-based
+        self.set_breakpoints(BreakpointKind::Source, args, |symbols, bp| {
+            let line = bp["line"].as_u64()?.checked_sub(1)?;
+            symbols.addr_of(line as usize)
+        })
+    }
+
+    /// replace the breakpoints of a kind with the ones of the request
+    fn set_breakpoints(
+        &mut self,
+        kind: BreakpointKind,
+        args: &Value,
+        resolve: impl Fn(&Symbols, &Value) -> Option<u32>,
+    ) -> Result<Value> {
+        let debugger = self.runner.scripter_mut().debugger_mut();
+        self.breakpoints.retain(|(k, id)| {
+            if *k == kind {
+                debugger.remove_breakpoint(*id);
+            }
+            *k != kind
+        });
+
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut breakpoints = Vec::new();
+        for bp in &requested {
+            let addr = resolve(&self.symbols, bp);
+            let thread = match bp["condition"].as_str() {
+                Some(condition) => match parse_thread_condition(condition) {
+                    Some(thread) => Some(thread),
+                    None => {
+                        breakpoints.push(json!({
+                            "verified": false,
+                            "message": "only `thread == N` conditions are supported",
+                        }));
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            let Some(addr) = addr else {
+                breakpoints.push(json!({ "verified": false, "message": "unknown location" }));
+                continue;
+            };
+            let id = self
+                .runner
+                .scripter_mut()
+                .debugger_mut()
+                .add_breakpoint(addr, thread);
+            self.breakpoints.push((kind, id));
+
+            let mut breakpoint = json!({
+                "id": id.0,
+                "verified": true,
+                "instructionReference": format!("0x{:08x}", addr),
+            });
+            if let Some(line) = self.symbols.line_of(addr) {
+                breakpoint["source"] = self.listing_source();
+                breakpoint["line"] = (line + 1).into();
+            }
+            breakpoints.push(breakpoint);
+        }
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn threads(&self) -> Value {
+        let threads = self
+            .runner
+            .scripter()
+            .contexts
+            .iter()
+            .enumerate()
+            .filter(|(_, context)| context.borrow().get_status() != CONTEXT_STATUS_NONE)
+            .map(|(id, _)| json!({ "id": id + 1, "name": format!("thread {}", id) }))
+            .collect::<Vec<_>>();
+        json!({ "threads": threads })
+    }
+
+    /// the context id of a DAP thread id
+    fn thread_id(&self, thread: &Value) -> Result<u32> {
+        match thread.as_u64() {
+            Some(id) if id >= 1 && id as usize <= self.runner.scripter().contexts.len() => {
+                Ok(id as u32 - 1)
+            }
+            _ => bail!("invalid thread id: {}", thread),
+        }
+    }
+
+    fn frames(&self, thread: u32) -> Vec<CallFrame> {
+        let scripter = self.runner.scripter();
+        let context = scripter.thread(thread);
+        context.frames(self.runner.scenario())
+    }
+
+    /// find the frame of a frame id
+    fn frame(&self, frame_id: &Value) -> Result<(u32, CallFrame)> {
+        let Some(frame_id) = frame_id.as_i64() else {
+            bail!("invalid frame id: {}", frame_id);
+        };
+        let thread = self.thread_id(&(frame_id / FRAMES_PER_THREAD).into())?;
+        let depth = (frame_id % FRAMES_PER_THREAD) as usize;
+        match self.frames(thread).into_iter().nth(depth) {
+            Some(frame) => Ok((thread, frame)),
+            None => bail!("invalid frame id: {}", frame_id),
+        }
+    }
+
+    fn stack_trace(&self, args: &Value) -> Result<Value> {
+        let thread = self.thread_id(&args["threadId"])?;
+        let frames = self.frames(thread);
+        let total = frames.len();
+        let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match args["levels"].as_u64() {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => total,
+        };
+
+        let frames = frames
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(depth, frame)| {
+                let name = match frame.function {
+                    Some(function) => self
+                        .symbols
+                        .function_name(function)
+                        .unwrap_or_else(|| function_label(function)),
+                    None => self
+                        .symbols
+                        .function_name(frame.pc as u32)
+                        .unwrap_or_else(|| "?".to_string()),
+                };
+                let mut value = json!({
+                    "id": (thread as i64 + 1) * FRAMES_PER_THREAD + depth as i64,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{:08x}", frame.pc),
+                });
+                if let Some(line) = self.symbols.line_of(frame.pc as u32) {
+                    value["source"] = self.listing_source();
+                    value["line"] = (line + 1).into();
+                    value["column"] = 1.into();
+                }
+                value
+            })
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "stackFrames": frames, "totalFrames": total }))
+    }
+
+    fn add_scope(&mut self, scope: Scope) -> usize {
+        self.scopes.push(scope);
+        self.scopes.len()
+    }
+
+    fn scopes(&mut self, args: &Value) -> Result<Value> {
+        let (thread, frame) = self.frame(&args["frameId"])?;
+        let arguments = self.add_scope(Scope::Arguments(thread, frame.clone()));
+        let locals = self.add_scope(Scope::Locals(thread, frame));
+        let globals = self.add_scope(Scope::Globals);
+
+        Ok(json!({ "scopes": [
+            { "name": "Arguments", "variablesReference": arguments, "expensive": false },
+            { "name": "Locals", "variablesReference": locals, "expensive": false },
+            { "name": "Globals", "variablesReference": globals, "expensive": true },
+        ] }))
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value> {
+        let scope = args["variablesReference"]
+            .as_u64()
+            .and_then(|r| self.scopes.get((r as usize).checked_sub(1)?))
+            .cloned()
+            .context("invalid variables reference")?;
+
+        let values = {
+            let scripter = self.runner.scripter();
+            match &scope {
+                Scope::Arguments(thread, frame) => {
+                    named(scripter.thread(*thread).frame_args(frame), "arg")
+                }
+                Scope::Locals(thread, frame) => named(
+                    scripter
+                        .thread(*thread)
+                        .frame_locals(self.runner.scenario(), frame),
+                    "local",
+                ),
+                Scope::Globals => scripter
+                    .globals()
+                    .iter()
+                    .map(|(slot, value)| (format!("g{}", slot), value.clone()))
+                    .collect(),
+                Scope::Table(Variant::Table(table)) => {
+                    let mut entries = table.iter().collect::<Vec<_>>();
+                    entries.sort_by_key(|(key, _)| *key);
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| (format!("[{}]", key), value.clone()))
+                        .collect()
+                }
+                Scope::Table(_) => Vec::new(),
+            }
+        };
+
+        let variables = values
+            .into_iter()
+            .map(|(name, value)| {
+                let mut variable = self.describe(&value);
+                variable["name"] = name.into();
+                variable
+            })
+            .collect::<Vec<_>>();
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// `value`, `type` and `variablesReference` of a variable or an evaluation result
+    fn describe(&mut self, value: &Variant) -> Value {
+        let reference = match value {
+            Variant::Table(_) => self.add_scope(Scope::Table(value.clone())),
+            _ => 0,
+        };
+        json!({
+            "value": format_variant(value),
+            "type": variant_type(value),
+            "variablesReference": reference,
+        })
+    }
+
+    fn step(&mut self, args: &Value, mode: StepMode) -> Result<Value> {
+        let thread = self.thread_id(&args["threadId"])?;
+        let stopped = self
+            .runner
+            .scripter()
+            .debugger()
+            .and_then(|d| d.get_stop())
+            .map(|s| s.thread);
+        if stopped != Some(thread) {
+            bail!("only the stopped thread can be stepped");
+        }
+
+        self.scopes.clear();
+        self.runner.scripter_mut().step(mode)?;
+        Ok(Value::Null)
+    }
+
+    /// Evaluate a simple expression
+    ///
+    /// `gN` is a global slot, `aN` and `lN` an argument and a local of the frame, `ret` the
+    /// return value of the thread and `pc` its program counter. Table values can be indexed
+    /// with `[key]`, e.g. `g12[3]`.
+    fn evaluate(&mut self, args: &Value) -> Result<Value> {
+        let expression = args["expression"].as_str().unwrap_or_default().trim();
+        let (thread, frame) = match &args["frameId"] {
+            Value::Null => {
+                let stop = self
+                    .runner
+                    .scripter()
+                    .debugger()
+                    .and_then(|d| d.get_stop())
+                    .copied();
+                match stop {
+                    Some(stop) => {
+                        let frame = self.frames(stop.thread).into_iter().next();
+                        (Some(stop.thread), frame)
+                    }
+                    None => (None, None),
+                }
+            }
+            frame_id => {
+                let (thread, frame) = self.frame(frame_id)?;
+                (Some(thread), Some(frame))
+            }
+        };
+
+        let (base, mut rest) = match expression.find('[') {
+            Some(index) => expression.split_at(index),
+            None => (expression, ""),
+        };
+        let base = base.trim();
+
+        let mut value = {
+            let scripter = self.runner.scripter();
+            let context = thread.map(|thread| scripter.thread(thread));
+            let index = |prefix: &str| base.strip_prefix(prefix)?.parse::<usize>().ok();
+
+            if let Some(slot) = index("g") {
+                let slot = u16::try_from(slot).context("invalid global slot")?;
+                scripter.globals().read(slot)?.clone()
+            } else if base == "ret" || base == "pc" {
+                let context = context.context("no thread is stopped")?;
+                if base == "ret" {
+                    context.get_return_value().clone()
+                } else {
+                    return Ok(json!({
+                        "result": format!("0x{:08x}", context.get_pc()),
+                        "variablesReference": 0,
+                    }));
+                }
+            } else if let Some(i) = index("a").or_else(|| index("l")) {
+                let (context, frame) = context
+                    .zip(frame.as_ref())
+                    .context("no frame is selected")?;
+                let values = if base.starts_with('a') {
+                    context.frame_args(frame)
+                } else {
+                    context.frame_locals(self.runner.scenario(), frame)
+                };
+                match values.get(i) {
+                    Some(value) => value.clone(),
+                    None => bail!("{} is out of range", base),
+                }
+            } else {
+                bail!("can't evaluate `{}`", expression);
+            }
+        };
+
+        while let Some(index) = rest.strip_prefix('[') {
+            let Some((key, tail)) = index.split_once(']') else {
+                bail!("missing `]`");
+            };
+            let key = key.trim().parse::<u32>().context("invalid table key")?;
+            value = match &value {
+                Variant::Table(table) => table.get(key).cloned().unwrap_or_default(),
+                _ => bail!("not a table"),
+            };
+            rest = tail.trim_start();
+        }
+        if !rest.is_empty() {
+            bail!("unexpected `{}`", rest);
+        }
+
+        let mut result = self.describe(&value);
+        result["result"] = result["value"].take();
+        Ok(result)
+    }
+}
+
+fn named(values: Vec<Variant>, prefix: &str) -> Vec<(String, Variant)> {
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| (format!("{}{}", prefix, i), value))
+        .collect()
+}
+
+/// parse a `thread == N` breakpoint condition
+fn parse_thread_condition(condition: &str) -> Option<u32> {
+    let (lhs, rhs) = condition.split_once("==")?;
+    if lhs.trim() != "thread" {
+        return None;
+    }
+    rhs.trim().parse().ok()
+}
+
+fn format_variant(value: &Variant) -> String {
+    match value {
+        Variant::Nil => "nil".to_string(),
+        Variant::True => "true".to_string(),
+        Variant::Int(v) => v.to_string(),
+        Variant::Float(v) => v.to_string(),
+        Variant::String(v) | Variant::ConstString(v, _) => format!("{:?}", v),
+        Variant::Table(table) => format!("table ({} entries)", table.iter().count()),
+        // a saved call frame
+        _ => "<call frame>".to_string(),
+    }
+}
+
+fn variant_type(value: &Variant) -> &'static str {
+    match value {
+        Variant::Nil => "nil",
+        Variant::True => "bool",
+        Variant::Int(_) => "int",
+        Variant::Float(_) => "float",
+        Variant::String(_) | Variant::ConstString(_, _) => "string",
+        Variant::Table(_) => "table",
+        _ => "frame",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rfvp_core::{
+        format::scenario::{Nls, ScenarioBuilder},
+        vm::syscall::SyscallRegistry,
+    };
+
+    use super::*;
+    use crate::{
+        stub::{StubConfig, StubHost},
+        trace::TraceWriter,
+    };
+
+    fn make_runner() -> Runner {
+        let code = [
+            0x01, 0x00, 0x00, // init_stack 0 0
+            0x0C, 0x07, // push_i8 7
+            0x02, 0x1A, 0x00, 0x00, 0x00, // call 0x1A
+            0x14, // push_return
+            0x15, 0x01, 0x00, // pop_global 1
+            0x03, 0x00, 0x00, // syscall ThreadNext
+            0x06, 0x07, 0x00, 0x00, 0x00, // jmp 0x07
+            0x01, 0x01, 0x01, // init_stack 1 1
+            0x0C, 0x03, // push_i8 3
+            0x16, 0x00, // local_copy 0
+            0x10, 0x00, // push_stack 0
+            0x05, // retv
+        ];
+        let scenario = ScenarioBuilder::new(Nls::ShiftJIS)
+            .code(&code)
+            .non_volatile_global_count(1)
+            .volatile_global_count(1)
+            .title("test")
+            .syscall(0, "ThreadNext")
+            .build()
+            .unwrap();
+        Runner::from_scenario(
+            scenario,
+            &SyscallRegistry::default(),
+            StubHost::new(StubConfig::default()),
+            TraceWriter::new(Box::new(std::io::sink()), false),
+            None,
+        )
+        .unwrap()
+    }
+
+    /// send a request and return the messages written in response
+    fn request(server: &mut DapServer<Vec<u8>>, command: &str, arguments: Value) -> Vec<Value> {
+        let request =
+            json!({ "seq": 1, "type": "request", "command": command, "arguments": arguments });
+        assert!(server.handle(&request).unwrap());
+        messages(server)
+    }
+
+    fn messages(server: &mut DapServer<Vec<u8>>) -> Vec<Value> {
+        let output = std::mem::take(&mut server.writer);
+        let mut reader = output.as_slice();
+        std::iter::from_fn(|| read_message(&mut reader).unwrap()).collect()
+    }
+
+    #[test]
+    fn debug_session() {
+        let symbols = Symbols::from_yaml(
+            r#"
+- address: 26
+  args_count: 1
+  locals_count: 1
+  insts:
+    - { address: 26, mnemonic: init_stack, operands: ["1", "1"] }
+    - { address: 29, mnemonic: push_i8, operands: ["3"] }
+    - { address: 31, mnemonic: local_copy, operands: ["0"] }
+    - { address: 33, mnemonic: push_stack, operands: ["0"] }
+    - { address: 35, mnemonic: retv, operands: [] }
+"#,
+        )
+        .unwrap();
+        let mut server = DapServer::new(make_runner(), Vec::new(), symbols, 16);
+
+        let out = request(&mut server, "initialize", json!({}));
+        assert_eq!(out[0]["success"], true);
+        assert_eq!(out[1]["event"], "initialized");
+
+        let out = request(
+            &mut server,
+            "setFunctionBreakpoints",
+            json!({ "breakpoints": [{ "name": "func_0000001a" }, { "name": "nowhere" }] }),
+        );
+        let breakpoints = &out[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[0]["line"], 2);
+        assert_eq!(breakpoints[1]["verified"], false);
+        request(&mut server, "configurationDone", json!({}));
+
+        server.run_frame().unwrap();
+        let out = messages(&mut server);
+        assert_eq!(out[0]["event"], "stopped");
+        assert_eq!(out[0]["body"]["reason"], "breakpoint");
+        assert_eq!(out[0]["body"]["threadId"], 1);
+
+        let out = request(&mut server, "stackTrace", json!({ "threadId": 1 }));
+        let frames = &out[0]["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "func_0000001a");
+        assert_eq!(frames[0]["instructionPointerReference"], "0x0000001a");
+        assert_eq!(frames[1]["name"], "func_00000004");
+
+        let out = request(&mut server, "scopes", json!({ "frameId": frames[0]["id"] }));
+        let arguments = out[0]["body"]["scopes"][0]["variablesReference"].clone();
+        let out = request(
+            &mut server,
+            "variables",
+            json!({ "variablesReference": arguments }),
+        );
+        assert_eq!(out[0]["body"]["variables"][0]["name"], "arg0");
+        assert_eq!(out[0]["body"]["variables"][0]["value"], "7");
+
+        // run the routine up to retv
+        for _ in 0..4 {
+            let out = request(&mut server, "next", json!({ "threadId": 1 }));
+            assert_eq!(out[0]["success"], true);
+            server.run_frame().unwrap();
+            assert_eq!(messages(&mut server)[0]["body"]["reason"], "step");
+        }
+        let out = request(&mut server, "evaluate", json!({ "expression": "l0" }));
+        assert_eq!(out[0]["body"]["result"], "3");
+        let out = request(&mut server, "evaluate", json!({ "expression": "pc" }));
+        assert_eq!(out[0]["body"]["result"], "0x00000023");
+        let out = request(&mut server, "evaluate", json!({ "expression": "g1[2]" }));
+        assert_eq!(out[0]["success"], false);
+
+        request(
+            &mut server,
+            "setFunctionBreakpoints",
+            json!({ "breakpoints": [] }),
+        );
+        request(&mut server, "continue", json!({ "threadId": 1 }));
+        server.run_frame().unwrap();
+        assert!(messages(&mut server).is_empty());
+        let out = request(&mut server, "evaluate", json!({ "expression": "g1" }));
+        assert_eq!(out[0]["body"]["result"], "3");
+    }
+}
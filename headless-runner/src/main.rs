@@ -0,0 +1,430 @@
+use anyhow::{Context as _, Result};
+use bytes::Bytes;
+use clap::{Parser as ClapParser, ValueEnum};
+use rfvp_core::{
+    format::scenario::{Nls, Scenario},
+    vm::{
+        command::CommandResult,
+        coverage::Coverage,
+        persist::NonVolatileStore,
+        profiler::Weight,
+        replay::{Recorder, Recording, Replayer},
+        syscall::{SyscallRegistry, UnknownSyscallPolicy},
+        Scripter,
+    },
+};
+use std::path::{Path, PathBuf};
+
+use dap::DapServer;
+use stub::{StubConfig, StubHost};
+use symbols::{function_label, Symbols};
+use trace::{TraceRecord, TraceWriter};
+
+mod dap;
+mod stub;
+mod symbols;
+mod trace;
+
+/// Runs a scenario without a window or an audio device
+pub struct Runner {
+    scenario: Scenario,
+    scripter: Scripter,
+    host: StubHost,
+    trace: TraceWriter,
+    /// where the non-volatile globals are kept between runs
+    store: Option<NonVolatileStore>,
+    /// log script errors and keep running the other threads instead of failing
+    keep_going: bool,
+    /// records the frame times and the command results of the session
+    recorder: Option<Recorder>,
+    /// answers the nondeterministic commands from a recording and checks the others against it
+    replayer: Option<Replayer>,
+    frame: u64,
+}
+
+impl Runner {
+    pub fn from_scenario(
+        scenario: Scenario,
+        registry: &SyscallRegistry,
+        host: StubHost,
+        trace: TraceWriter,
+        store: Option<NonVolatileStore>,
+    ) -> Result<Self> {
+        let mut scripter = Scripter::new();
+        scripter.init_globals(&scenario);
+        if let Some(store) = &store {
+            if store.load(scripter.globals_mut())? {
+                log::info!(
+                    "restored the non-volatile globals from {}",
+                    store.path().display()
+                );
+            }
+        }
+        scripter.load_syscalls(&scenario, registry)?;
+        scripter.start_main(scenario.get_entry_point());
+
+        Ok(Self {
+            scenario,
+            scripter,
+            host,
+            trace,
+            store,
+            keep_going: false,
+            recorder: None,
+            replayer: None,
+            frame: 0,
+        })
+    }
+
+    pub fn set_keep_going(&mut self, keep_going: bool) {
+        self.keep_going = keep_going;
+    }
+
+    /// record the session, see [`Runner::take_recording`]
+    pub fn record(&mut self) {
+        self.recorder = Some(Recorder::new(&self.scenario, self.scripter.globals()));
+    }
+
+    /// the session recorded so far
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.recorder.take().map(Recorder::finish)
+    }
+
+    /// Replay a recorded session, the frames are run with [`Runner::next_replay_frame`]
+    ///
+    /// The session starts again from the recorded non-volatile globals, which are not written
+    /// back to the store.
+    pub fn replay(&mut self, recording: Recording) -> Result<()> {
+        let replayer = Replayer::new(recording, &self.scenario)?;
+        replayer.restore_globals(self.scripter.globals_mut());
+        self.scripter.globals_mut().take_non_volatile_changed();
+        self.store = None;
+        self.replayer = Some(replayer);
+        Ok(())
+    }
+
+    /// the frame time of the next recorded frame, `None` once the replay is over
+    pub fn next_replay_frame(&mut self) -> Option<u64> {
+        self.replayer.as_mut()?.next_frame()
+    }
+
+    /// the main thread has exited, nothing is left to run
+    pub fn is_finished(&self) -> bool {
+        self.scripter.get_should_break()
+    }
+
+    pub fn scenario(&self) -> &Scenario {
+        &self.scenario
+    }
+
+    pub fn scripter(&self) -> &Scripter {
+        &self.scripter
+    }
+
+    pub fn scripter_mut(&mut self) -> &mut Scripter {
+        &mut self.scripter
+    }
+
+    /// run a single simulated frame, answering every command with the stub host
+    ///
+    /// If the debugger stops the VM, the frame is left unfinished and the next call continues it.
+    pub fn run_frame(&mut self, frame_time: u64) -> Result<()> {
+        let mut command = if self.scripter.is_suspended() {
+            self.scripter.resume(&self.scenario, CommandResult::None)
+        } else {
+            self.host.begin_frame(self.frame, frame_time);
+            if let Some(recorder) = &mut self.recorder {
+                recorder.begin_frame(frame_time);
+            }
+            self.scripter.run(&self.scenario, frame_time)
+        };
+        loop {
+            let cmd = match command {
+                Ok(Some(cmd)) => cmd,
+                Ok(None) => break,
+                Err(e) if self.keep_going => {
+                    log::error!("{}", e);
+                    command = self.scripter.resume(&self.scenario, CommandResult::None);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let id = self.scripter.get_current_id();
+            let pc = self.scripter.get_thread(id).get_last_pc();
+            self.trace.record(&TraceRecord {
+                frame: self.frame,
+                thread: id,
+                pc: pc as u32,
+                syscall: cmd.name(),
+                args: cmd.args(),
+            })?;
+
+            let replayed = match &mut self.replayer {
+                Some(replayer) => replayer.replay(id, pc as u32, &cmd)?,
+                None => None,
+            };
+            let result = match replayed {
+                Some(result) => result,
+                None => self.host.execute(&mut self.scripter, &cmd),
+            };
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(id, pc as u32, &cmd, &result);
+            }
+            command = self.scripter.resume(&self.scenario, result);
+        }
+
+        if self.scripter.is_stopped() {
+            return Ok(());
+        }
+        if let Some(replayer) = &self.replayer {
+            replayer.end_frame()?;
+        }
+        if let Some(store) = &self.store {
+            store.store_if_changed(self.scripter.globals_mut())?;
+        }
+
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// write the folded stacks of the profiler to `path` and its summary to stderr
+    pub fn write_profile(&self, path: &Path, weight: Weight) -> Result<()> {
+        let Some(profiler) = self.scripter.profiler() else {
+            return Ok(());
+        };
+
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        profiler.write_folded(&mut writer, weight, function_label)?;
+        std::io::Write::flush(&mut writer)?;
+        profiler.write_summary(&mut std::io::stderr().lock(), function_label)?;
+        Ok(())
+    }
+
+    /// flush the trace and the non-volatile globals
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.trace.flush()?;
+        if let Some(store) = &self.store {
+            store.store(self.scripter.globals())?;
+        }
+        Ok(())
+    }
+}
+
+/// What to do with syscalls the engine does not know
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum UnknownSyscalls {
+    /// stop the run
+    Error,
+    /// log a warning and return nil
+    Warn,
+}
+
+/// What the profile attributes to the call stacks
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ProfileWeight {
+    /// executed instructions
+    Instructions,
+    /// syscalls made
+    Syscalls,
+    /// wall time spent in the VM, in microseconds
+    Time,
+}
+
+#[derive(ClapParser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Path to the scenario, usually ending with `.hcb`
+    #[arg(short, long, required = true)]
+    input: PathBuf,
+
+    /// Text encoding of the scenario: sjis, gbk, utf8, big5, euckr, cp1252 or auto
+    #[arg(short, long, default_value = "sjis")]
+    lang: Nls,
+
+    /// Stub configuration (yaml): default return values and scripted inputs
+    #[arg(short, long)]
+    stub: Option<PathBuf>,
+
+    /// Where to write the JSON-lines syscall trace, stdout if not set
+    #[arg(short, long)]
+    trace: Option<PathBuf>,
+
+    /// How many frames to simulate
+    #[arg(long, default_value_t = 600)]
+    frames: u64,
+
+    /// Simulated duration of a frame in ms
+    #[arg(long, default_value_t = 16)]
+    frame_time: u64,
+
+    #[arg(long, value_enum, default_value_t = UnknownSyscalls::Warn)]
+    unknown_syscalls: UnknownSyscalls,
+
+    /// Log script errors and keep running the other threads instead of stopping
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Load and write the non-volatile globals, to the given file or the one of the game in the
+    /// user data directory. Without it every run starts from the initial values
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    persist: Option<Option<PathBuf>>,
+
+    /// Compare the trace against a golden one and fail on the first mismatch
+    #[arg(long)]
+    compare: Option<PathBuf>,
+
+    /// Wait for a Debug Adapter Protocol client on this address, e.g. `127.0.0.1:4711`
+    #[arg(long, conflicts_with = "dap_stdio")]
+    dap: Option<String>,
+
+    /// Serve a Debug Adapter Protocol client on stdin and stdout
+    #[arg(long)]
+    dap_stdio: bool,
+
+    /// The `disassembly.yaml` of the scenario, gives the debugger labels and a listing
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+
+    /// Profile the script functions, write the folded stacks for flamegraph tools to this file
+    /// and a per-function summary to stderr
+    #[arg(long, conflicts_with_all = ["dap", "dap_stdio"])]
+    profile: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = ProfileWeight::Instructions)]
+    profile_weight: ProfileWeight,
+
+    /// Record the executed instructions into this coverage file, adding to what it holds
+    #[arg(long, conflicts_with_all = ["dap", "dap_stdio"])]
+    coverage: Option<PathBuf>,
+
+    /// Record the frame times and the results of the input, timer and random syscalls to this
+    /// file
+    #[arg(long, conflicts_with_all = ["dap", "dap_stdio", "replay"])]
+    record: Option<PathBuf>,
+
+    /// Replay a recorded session and fail on the first syscall that doesn't match it, the
+    /// frames and their duration come from the recording
+    #[arg(long, conflicts_with_all = ["dap", "dap_stdio"])]
+    replay: Option<PathBuf>,
+}
+
+/// hand the runner to a debug client until it disconnects, on stdio if `addr` is not set
+fn serve_dap(
+    runner: Runner,
+    addr: Option<&str>,
+    symbols: Option<&Path>,
+    frame_time: u64,
+) -> Result<()> {
+    let symbols = match symbols {
+        Some(path) => Symbols::new(path)?,
+        None => Symbols::default(),
+    };
+
+    if let Some(addr) = addr {
+        let listener = std::net::TcpListener::bind(addr)?;
+        log::info!("waiting for a debug client on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        log::info!("debug client connected from {}", peer);
+
+        let requests = dap::spawn_reader(std::io::BufReader::new(stream.try_clone()?));
+        DapServer::new(runner, stream, symbols, frame_time).serve(requests)
+    } else {
+        let requests = dap::spawn_reader(std::io::BufReader::new(std::io::stdin()));
+        DapServer::new(runner, std::io::stdout(), symbols, frame_time).serve(requests)
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+
+    let config = match &args.stub {
+        Some(path) => StubConfig::new(path).context("failed to load the stub configuration")?,
+        None => StubConfig::default(),
+    };
+
+    let debugging = args.dap.is_some() || args.dap_stdio;
+    let writer: Box<dyn std::io::Write> = match &args.trace {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        // stdout may be the channel of the debug client
+        None if debugging => Box::new(std::io::sink()),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let trace = TraceWriter::new(writer, args.compare.is_some());
+
+    let mut registry = SyscallRegistry::default();
+    registry.set_unknown_policy(match args.unknown_syscalls {
+        UnknownSyscalls::Error => UnknownSyscallPolicy::Error,
+        UnknownSyscalls::Warn => UnknownSyscallPolicy::WarnAndReturnNil,
+    });
+
+    let data = Bytes::from(std::fs::read(&args.input)?);
+    let scenario = Scenario::new(data, Some(args.lang))?;
+    let store = match args.persist {
+        None => None,
+        Some(Some(path)) => Some(NonVolatileStore::new(path)),
+        Some(None) => Some(NonVolatileStore::for_game(&scenario.get_title())?),
+    };
+    let mut runner =
+        Runner::from_scenario(scenario, &registry, StubHost::new(config), trace, store)?;
+    runner.set_keep_going(args.keep_going);
+    if args.profile.is_some() {
+        runner.scripter_mut().profiler_mut();
+    }
+    if debugging {
+        return serve_dap(
+            runner,
+            args.dap.as_deref(),
+            args.symbols.as_deref(),
+            args.frame_time,
+        );
+    }
+
+    if let Some(path) = &args.coverage {
+        let coverage = Coverage::load_or_new(path, runner.scenario())?;
+        runner.scripter_mut().set_coverage(coverage);
+    }
+
+    if args.record.is_some() {
+        runner.record();
+    }
+
+    if let Some(path) = &args.replay {
+        runner.replay(Recording::load(path)?)?;
+        while let Some(frame_time) = runner.next_replay_frame() {
+            runner.run_frame(frame_time)?;
+        }
+        log::info!("replayed {} frames of {}", runner.frame, path.display());
+    } else {
+        for _ in 0..args.frames {
+            if runner.is_finished() {
+                break;
+            }
+            runner.run_frame(args.frame_time)?;
+        }
+    }
+    runner.shutdown()?;
+    if let (Some(path), Some(recording)) = (&args.record, runner.take_recording()) {
+        recording.save(path)?;
+    }
+    if let Some(path) = &args.profile {
+        let weight = match args.profile_weight {
+            ProfileWeight::Instructions => Weight::Instructions,
+            ProfileWeight::Syscalls => Weight::Syscalls,
+            ProfileWeight::Time => Weight::Time,
+        };
+        runner.write_profile(path, weight)?;
+    }
+    if let (Some(path), Some(coverage)) = (&args.coverage, runner.scripter().coverage()) {
+        coverage.save(path)?;
+    }
+
+    if let Some(golden) = &args.compare {
+        let golden = std::fs::read_to_string(golden)?;
+        runner.trace.compare(&golden)?;
+        log::info!("trace matches {}", args.compare.as_ref().unwrap().display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use rfvp_core::{
+    format::scenario::variant::Variant,
+    vm::{
+        command::{args, Command, CommandResult},
+        Scripter,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+/// A value that can be returned by a stubbed syscall
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StubValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+impl StubValue {
+    pub fn to_variant(&self) -> Variant {
+        match self {
+            StubValue::Bool(true) => Variant::True,
+            StubValue::Bool(false) => Variant::Nil,
+            StubValue::Int(v) => Variant::Int(*v),
+            StubValue::Float(v) => Variant::Float(*v),
+            StubValue::String(v) => Variant::String(v.clone()),
+        }
+    }
+}
+
+/// A return value of a syscall for a single frame, used to script input events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptedReturn {
+    /// the frame the value is returned at
+    pub frame: u64,
+    /// name of the syscall, e.g. `InputGetEvent`
+    pub syscall: String,
+    pub value: Option<StubValue>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StubConfig {
+    /// return values of the syscalls that are not emulated, by syscall name
+    /// syscalls that are not listed here return nil
+    pub returns: HashMap<String, Option<StubValue>>,
+    /// per-frame return values, take precedence over everything else
+    pub inputs: Vec<ScriptedReturn>,
+    /// seed of the Rand syscall
+    pub rand_seed: u32,
+}
+
+impl StubConfig {
+    pub fn new(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let config_str = std::fs::read_to_string(path.as_ref())?;
+        let config: StubConfig = serde_yaml::from_str(&config_str)?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct FakeTimer {
+    /// the simulated time the timer was (re)started at
+    base: u64,
+    /// the value of the suspended timer
+    suspended: Option<u64>,
+}
+
+/// A syscall host without any window, audio device or file system access
+///
+/// Thread control syscalls are forwarded to the [`Scripter`], a few side-effect free syscalls
+/// (`Rand`, `FloatToInt`, `IntToText`, timers) are emulated and everything else is answered
+/// with the configured stub values.
+pub struct StubHost {
+    config: StubConfig,
+    frame: u64,
+    /// simulated time since the start of the scenario, in ms
+    elapsed: u64,
+    timers: HashMap<i32, FakeTimer>,
+    rand_state: u32,
+}
+
+fn arg_int(args: &[Variant], idx: usize) -> Option<i32> {
+    args.get(idx).and_then(|v| v.as_int())
+}
+
+impl StubHost {
+    pub fn new(config: StubConfig) -> Self {
+        let rand_state = config.rand_seed;
+        Self {
+            config,
+            frame: 0,
+            elapsed: 0,
+            timers: HashMap::new(),
+            rand_state,
+        }
+    }
+
+    pub fn get_frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn get_elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    /// advance the simulated clock to the given frame
+    pub fn begin_frame(&mut self, frame: u64, frame_time: u64) {
+        self.frame = frame;
+        self.elapsed += frame_time;
+    }
+
+    fn scripted(&self, name: &str) -> Option<Variant> {
+        self.config
+            .inputs
+            .iter()
+            .find(|e| e.frame == self.frame && e.syscall == name)
+            .map(|e| e.value.as_ref().map_or(Variant::Nil, |v| v.to_variant()))
+    }
+
+    fn stubbed(&self, name: &str) -> Variant {
+        match self.config.returns.get(name) {
+            Some(Some(value)) => value.to_variant(),
+            _ => Variant::Nil,
+        }
+    }
+
+    fn rand(&mut self) -> i32 {
+        // the classic ANSI C LCG, good enough to be reproducible
+        self.rand_state = self.rand_state.wrapping_mul(1103515245).wrapping_add(12345);
+        ((self.rand_state >> 16) & 0x7fff) as i32
+    }
+
+    fn timer_get(&self, id: i32) -> i32 {
+        match self.timers.get(&id) {
+            Some(FakeTimer {
+                suspended: Some(value),
+                ..
+            }) => *value as i32,
+            Some(timer) => (self.elapsed - timer.base) as i32,
+            None => self.elapsed as i32,
+        }
+    }
+
+    /// execute the command and produce the value written back to the script
+    pub fn execute(&mut self, scripter: &mut Scripter, command: &Command) -> CommandResult {
+        let name = command.name();
+        let args = command.args();
+
+        if let Some(value) = self.scripted(name) {
+            return CommandResult::WriteR0(value);
+        }
+
+        let value = match command {
+            Command::ThreadStart { .. } => {
+                match command.args_as::<args::ThreadStart>() {
+                    Ok(args::ThreadStart { id, addr }) if (0..32).contains(&id) => {
+                        scripter.thread_start(id as u32, addr as u32)
+                    }
+                    _ => log::warn!("ThreadStart: invalid arguments {:?}", args),
+                }
+                Variant::Nil
+            }
+            Command::ThreadWait { .. } => {
+                let time = command.args_as::<args::ThreadWait>().map_or(0, |a| a.time);
+                scripter.thread_wait(time as u32);
+                Variant::Nil
+            }
+            Command::ThreadSleep { .. } => {
+                let time = command.args_as::<args::ThreadSleep>().map_or(0, |a| a.time);
+                scripter.thread_sleep(time as u32);
+                Variant::Nil
+            }
+            Command::ThreadRaise { .. } => {
+                let time = command.args_as::<args::ThreadRaise>().map_or(0, |a| a.time);
+                scripter.thread_raise(time as u32);
+                Variant::Nil
+            }
+            Command::ThreadNext { .. } => {
+                scripter.thread_next();
+                Variant::Nil
+            }
+            Command::ThreadExit { .. } => {
+                let id = command.args_as::<args::ThreadExit>().ok().and_then(|a| a.id);
+                scripter.thread_exit(id.map(|id| id as u32));
+                Variant::Nil
+            }
+            Command::Rand { .. } => Variant::Int(self.rand()),
+            Command::FloatToInt { .. } => match command.args_as::<args::FloatToInt>() {
+                Ok(args) => Variant::Int(args.value as i32),
+                Err(_) => Variant::Nil,
+            },
+            Command::IntToText { .. } => match command.args_as::<args::IntToText>() {
+                Ok(args) => {
+                    let width = args.width.unwrap_or(0).max(0) as usize;
+                    Variant::String(format!("{:0width$}", args.value, width = width))
+                }
+                Err(_) => Variant::Nil,
+            },
+            Command::TimerSet { .. } => {
+                let (id, value) = match command.args_as::<args::TimerSet>() {
+                    Ok(args) => (args.id, args.value.max(0) as u64),
+                    Err(_) => (0, 0),
+                };
+                self.timers.insert(
+                    id,
+                    FakeTimer {
+                        base: self.elapsed.saturating_sub(value),
+                        suspended: None,
+                    },
+                );
+                Variant::Nil
+            }
+            Command::TimerGet { .. } => Variant::Int(self.timer_get(arg_int(args, 0).unwrap_or(0))),
+            Command::TimerSuspend { .. } => {
+                let id = arg_int(args, 0).unwrap_or(0);
+                let suspend = args.get(1).is_none_or(|v| v.canbe_true());
+                let value = self.timer_get(id) as u64;
+                let elapsed = self.elapsed;
+                let timer = self.timers.entry(id).or_default();
+                match (suspend, timer.suspended) {
+                    (true, None) => timer.suspended = Some(value),
+                    (false, Some(value)) => {
+                        timer.base = elapsed.saturating_sub(value);
+                        timer.suspended = None;
+                    }
+                    _ => {}
+                }
+                Variant::Nil
+            }
+            _ => self.stubbed(name),
+        };
+
+        match value {
+            Variant::Nil => CommandResult::None,
+            value => CommandResult::WriteR0(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_returns_take_precedence() {
+        let config: StubConfig = serde_yaml::from_str(
+            r#"
+returns:
+  InputGetEvent: 0
+  FlagGet: true
+inputs:
+  - frame: 2
+    syscall: InputGetEvent
+    value: 5
+"#,
+        )
+        .unwrap();
+        let mut host = StubHost::new(config);
+        let mut scripter = Scripter::new();
+        let command = || Command::InputGetEvent { args: vec![] };
+
+        host.begin_frame(1, 16);
+        let result = host.execute(&mut scripter, &command());
+        assert!(matches!(result, CommandResult::WriteR0(Variant::Int(0))));
+
+        host.begin_frame(2, 16);
+        let result = host.execute(&mut scripter, &command());
+        assert!(matches!(result, CommandResult::WriteR0(Variant::Int(5))));
+
+        let result = host.execute(&mut scripter, &Command::FlagGet { args: vec![] });
+        assert!(matches!(result, CommandResult::WriteR0(Variant::True)));
+
+        let result = host.execute(&mut scripter, &Command::TextPrint { args: vec![] });
+        assert!(matches!(result, CommandResult::None));
+    }
+
+    #[test]
+    fn fake_timer() {
+        let mut host = StubHost::new(StubConfig::default());
+        let mut scripter = Scripter::new();
+
+        host.begin_frame(0, 16);
+        host.execute(
+            &mut scripter,
+            &Command::TimerSet {
+                args: vec![Variant::Int(1), Variant::Int(0)],
+            },
+        );
+        host.begin_frame(1, 16);
+        host.begin_frame(2, 16);
+        let result = host.execute(
+            &mut scripter,
+            &Command::TimerGet {
+                args: vec![Variant::Int(1)],
+            },
+        );
+        assert!(matches!(result, CommandResult::WriteR0(Variant::Int(32))));
+    }
+}
@@ -0,0 +1,188 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context as _, Result};
+use serde::Deserialize;
+
+/// A routine of `disassembly.yaml`, as written by the disassembler
+#[derive(Debug, Deserialize)]
+struct Function {
+    address: u32,
+    #[serde(default)]
+    name: Option<String>,
+    args_count: u8,
+    locals_count: u8,
+    insts: Vec<Inst>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Inst {
+    address: u32,
+    #[serde(default)]
+    label: Option<String>,
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+/// Labels and a text listing of the scenario, built from the disassembler output
+///
+/// The listing is what the debug client shows as the source of the script, one instruction
+/// per line.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    /// names of the routines, their `func_xxxxxxxx` labels and the instruction labels
+    labels: HashMap<String, u32>,
+    /// routine entries and names, sorted by address
+    functions: Vec<(u32, String)>,
+    lines: Vec<String>,
+    /// address of the instruction at each line, `None` for the routine headers
+    line_addrs: Vec<Option<u32>>,
+    addr_lines: HashMap<u32, usize>,
+}
+
+impl Symbols {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let yaml = std::fs::read_to_string(path.as_ref())
+            .with_context(|| format!("failed to read {}", path.as_ref().display()))?;
+        Self::from_yaml(&yaml)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let mut functions: Vec<Function> = serde_yaml::from_str(yaml)?;
+        functions.sort_by_key(|f| f.address);
+
+        let mut symbols = Self::default();
+        for function in &functions {
+            let label = function_label(function.address);
+            let name = function.name.clone().unwrap_or_else(|| label.clone());
+            symbols.push_line(
+                format!(
+                    "{}: ; args: {}, locals: {}",
+                    name, function.args_count, function.locals_count
+                ),
+                None,
+            );
+            symbols.labels.insert(label, function.address);
+            symbols.labels.insert(name.clone(), function.address);
+            symbols.functions.push((function.address, name));
+
+            for inst in &function.insts {
+                if let Some(label) = &inst.label {
+                    symbols.push_line(format!("{}:", label), None);
+                    symbols.labels.insert(label.clone(), inst.address);
+                }
+                let line = if inst.operands.is_empty() {
+                    format!("    0x{:08x}  {}", inst.address, inst.mnemonic)
+                } else {
+                    format!(
+                        "    0x{:08x}  {} {}",
+                        inst.address,
+                        inst.mnemonic,
+                        inst.operands.join(", ")
+                    )
+                };
+                symbols.push_line(line, Some(inst.address));
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    fn push_line(&mut self, line: String, addr: Option<u32>) {
+        if let Some(addr) = addr {
+            self.addr_lines.insert(addr, self.lines.len());
+        }
+        self.lines.push(line);
+        self.line_addrs.push(addr);
+    }
+
+    /// resolve a label, or an address written in hex (`0x` prefix) or decimal
+    pub fn resolve(&self, name: &str) -> Option<u32> {
+        let name = name.trim();
+        if let Some(addr) = self.labels.get(name) {
+            return Some(*addr);
+        }
+        match name.strip_prefix("0x").or_else(|| name.strip_prefix("0X")) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => name.parse().ok(),
+        }
+    }
+
+    /// the name of the routine containing `addr`
+    pub fn function_name(&self, addr: u32) -> Option<String> {
+        let index = self.functions.partition_point(|(f, _)| *f <= addr);
+        let (_, name) = self.functions.get(index.checked_sub(1)?)?;
+        Some(name.clone())
+    }
+
+    /// the whole listing
+    pub fn listing(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// 0-based line of the instruction at `addr`
+    pub fn line_of(&self, addr: u32) -> Option<usize> {
+        self.addr_lines.get(&addr).copied()
+    }
+
+    /// the instruction at a 0-based line, a routine header or a label resolves to the instruction
+    /// after it
+    pub fn addr_of(&self, line: usize) -> Option<u32> {
+        self.line_addrs
+            .get(line..)?
+            .iter()
+            .take(2)
+            .find_map(|addr| *addr)
+    }
+}
+
+pub fn function_label(addr: u32) -> String {
+    format!("func_{:08x}", addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_and_lines() {
+        let symbols = Symbols::from_yaml(
+            r#"
+- address: 16
+  args_count: 1
+  locals_count: 0
+  insts:
+    - { address: 16, mnemonic: init_stack, operands: ["1", "0"] }
+    - { address: 19, mnemonic: ret, operands: [] }
+- address: 4
+  name: main
+  args_count: 0
+  locals_count: 0
+  insts:
+    - { address: 4, mnemonic: init_stack, operands: ["0", "0"] }
+    - { address: 7, label: L_00000007, mnemonic: call, operands: ["func_00000010"] }
+    - { address: 12, mnemonic: jmp, operands: ["L_00000007"] }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(symbols.resolve("func_00000010"), Some(16));
+        assert_eq!(symbols.resolve("main"), Some(4));
+        assert_eq!(symbols.resolve("func_00000004"), Some(4));
+        assert_eq!(symbols.resolve("L_00000007"), Some(7));
+        assert_eq!(symbols.resolve("0x13"), Some(0x13));
+        assert_eq!(symbols.resolve("7"), Some(7));
+        assert_eq!(symbols.resolve("func_"), None);
+        assert_eq!(symbols.function_name(19).as_deref(), Some("func_00000010"));
+        assert_eq!(symbols.function_name(12).as_deref(), Some("main"));
+        assert_eq!(symbols.function_name(2), None);
+
+        assert_eq!(symbols.line_of(7), Some(3));
+        assert_eq!(symbols.addr_of(2), Some(7));
+        assert_eq!(symbols.addr_of(4), Some(12));
+        assert_eq!(symbols.addr_of(5), Some(16));
+        assert_eq!(
+            symbols.listing().lines().nth(3),
+            Some("    0x00000007  call func_00000010")
+        );
+    }
+}
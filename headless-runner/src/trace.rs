@@ -0,0 +1,75 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use rfvp_core::format::scenario::variant::Variant;
+use serde::Serialize;
+
+/// A single syscall made by the script, serialized as one line of JSON
+#[derive(Debug, Serialize)]
+pub struct TraceRecord<'a> {
+    pub frame: u64,
+    pub thread: u32,
+    pub pc: u32,
+    pub syscall: &'a str,
+    pub args: &'a [Variant],
+}
+
+/// Writes the JSON-lines syscall trace
+pub struct TraceWriter {
+    writer: Box<dyn Write>,
+    /// keep the written lines in memory to compare them against a golden trace
+    lines: Option<Vec<String>>,
+}
+
+impl TraceWriter {
+    pub fn new(writer: Box<dyn Write>, keep_lines: bool) -> Self {
+        Self {
+            writer,
+            lines: keep_lines.then(Vec::new),
+        }
+    }
+
+    pub fn record(&mut self, record: &TraceRecord) -> Result<()> {
+        let line = serde_json::to_string(record)?;
+        writeln!(self.writer, "{}", line)?;
+        if let Some(lines) = &mut self.lines {
+            lines.push(line);
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// compare the recorded trace against a golden one, line by line
+    pub fn compare(&self, golden: &str) -> Result<()> {
+        let Some(lines) = &self.lines else {
+            bail!("the trace was not kept in memory");
+        };
+
+        let golden = golden.lines().filter(|l| !l.trim().is_empty()).collect::<Vec<_>>();
+        for (i, (actual, expected)) in lines.iter().zip(golden.iter()).enumerate() {
+            if actual != expected {
+                bail!(
+                    "trace mismatch at line {}:\n  expected: {}\n  actual:   {}",
+                    i + 1,
+                    expected,
+                    actual
+                );
+            }
+        }
+
+        if lines.len() != golden.len() {
+            bail!(
+                "trace length mismatch: expected {} syscalls, got {}",
+                golden.len(),
+                lines.len()
+            );
+        }
+
+        Ok(())
+    }
+}
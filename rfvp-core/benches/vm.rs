@@ -0,0 +1,227 @@
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rfvp_core::{
+    format::scenario::{
+        instructions::{inst::*, Inst},
+        Nls, Scenario, ScenarioBuilder,
+    },
+    vm::{
+        command::{args, Command, CommandResult},
+        Scripter,
+    },
+};
+
+/// frames run from the entry point of a full scenario
+const FRAMES: usize = 600;
+
+/// threads started by the main thread of the generated game
+const WORKERS: i8 = 4;
+
+/// rounds of the inner loop a worker runs before each ThreadNext
+const ROUNDS: i16 = 20;
+
+/// number of loop iterations the main thread runs before each ThreadNext
+const ITERATIONS: i32 = 10000;
+
+/// A scenario running an instruction-heavy loop, yielding with ThreadNext after each round
+fn make_scenario() -> Scenario {
+    let mut code = vec![
+        0x01, 0x00, 0x01, // 0x04: init_stack 0 1
+        0x0C, 0x00, // 0x07: push_i8 0
+        0x16, 0x00, // 0x09: pop_stack 0
+        0x10, 0x00, // 0x0B: push_stack 0
+        0x0A, // 0x0D: push_i32 ITERATIONS
+    ];
+    code.extend_from_slice(&ITERATIONS.to_le_bytes());
+    code.extend_from_slice(&[
+        0x23, // 0x12: set_ne
+        0x07, 0x2F, 0x00, 0x00, 0x00, // 0x13: jz 0x2F
+        0x10, 0x00, // 0x18: push_stack 0
+        0x02, 0x37, 0x00, 0x00, 0x00, // 0x1A: call 0x37
+        0x14, // 0x1F: push_return
+        0x16, 0x00, // 0x20: pop_stack 0
+        0x0E, 0x03, b'a', b'b', 0x00, // 0x22: push_string "ab"
+        0x15, 0x00, 0x00, // 0x27: pop_global 0
+        0x06, 0x0B, 0x00, 0x00, 0x00, // 0x2A: jmp 0x0B
+        0x03, 0x00, 0x00, // 0x2F: syscall ThreadNext
+        0x06, 0x07, 0x00, 0x00, 0x00, // 0x32: jmp 0x07
+        0x01, 0x01, 0x00, // 0x37: init_stack 1 0
+        0x10, 0xFE, // 0x3A: push_stack -2
+        0x0C, 0x01, // 0x3C: push_i8 1
+        0x1A, // 0x3E: add
+        0x05, // 0x3F: retv
+    ]);
+
+    ScenarioBuilder::new(Nls::ShiftJIS)
+        .code(&code)
+        .non_volatile_global_count(1)
+        .volatile_global_count(1)
+        .title("bench")
+        .syscall(0, "ThreadNext")
+        .build()
+        .unwrap()
+}
+
+fn interpreter(c: &mut Criterion) {
+    let scenario = make_scenario();
+    let mut scripter = Scripter::new();
+    scripter.start_main(scenario.get_entry_point());
+
+    c.bench_function("loop", |b| {
+        b.iter(|| {
+            let cmd = scripter.run(&scenario, 16).unwrap();
+            assert!(cmd.is_some());
+            scripter.thread_next();
+            scripter.resume(&scenario, CommandResult::None).unwrap();
+        })
+    });
+}
+
+/// Handle the thread commands, every other syscall returns nil
+fn execute(scripter: &mut Scripter, command: &Command) {
+    match command {
+        Command::ThreadStart { .. } => {
+            if let Ok(args::ThreadStart { id, addr }) = command.args_as::<args::ThreadStart>() {
+                if (0..32).contains(&id) {
+                    scripter.thread_start(id as u32, addr as u32);
+                }
+            }
+        }
+        Command::ThreadWait { .. } => {
+            let time = command.args_as::<args::ThreadWait>().map_or(0, |a| a.time);
+            scripter.thread_wait(time as u32);
+        }
+        Command::ThreadSleep { .. } => {
+            let time = command.args_as::<args::ThreadSleep>().map_or(0, |a| a.time);
+            scripter.thread_sleep(time as u32);
+        }
+        Command::ThreadRaise { .. } => {
+            let time = command.args_as::<args::ThreadRaise>().map_or(0, |a| a.time);
+            scripter.thread_raise(time as u32);
+        }
+        Command::ThreadNext { .. } => scripter.thread_next(),
+        Command::ThreadExit { .. } => {
+            let id = command
+                .args_as::<args::ThreadExit>()
+                .ok()
+                .and_then(|a| a.id);
+            scripter.thread_exit(id.map(|id| id as u32));
+        }
+        _ => {}
+    }
+}
+
+/// A game-like scenario: a main thread starting workers that call routines, format text and
+/// fill global tables, the threads waiting or yielding every frame
+fn make_game() -> Scenario {
+    let mut builder = ScenarioBuilder::new(Nls::ShiftJIS);
+    builder
+        .non_volatile_global_count(16)
+        .volatile_global_count(64)
+        .title("bench")
+        .syscall(0, "ThreadNext")
+        .syscall(2, "ThreadStart")
+        .syscall(1, "ThreadWait")
+        .syscall(2, "TextPrint")
+        .syscall(0, "Rand");
+    let mut emit = |inst: Inst| builder.inst(&inst).unwrap();
+
+    // mix(a, b) = (a + b * 3) % 1000
+    let mix = emit(Inst::InitStack(InitStackInst::new(0, 2, 0)));
+    emit(Inst::PushStack(PushStackInst::new(0, -3)));
+    emit(Inst::PushStack(PushStackInst::new(0, -2)));
+    emit(Inst::PushI8(PushI8Inst::new(0, 3)));
+    emit(Inst::Mul(MulInst::new(0)));
+    emit(Inst::Add(AddInst::new(0)));
+    emit(Inst::PushI16(PushI16Inst::new(0, 1000)));
+    emit(Inst::Mod(ModInst::new(0)));
+    emit(Inst::RetV(RetValueInst::new(0)));
+
+    // worker: local 0 is the accumulator, local 1 the round counter
+    let worker = emit(Inst::InitStack(InitStackInst::new(0, 0, 2)));
+    emit(Inst::PushI8(PushI8Inst::new(0, 0)));
+    emit(Inst::PopStack(PopStackInst::new(0, 0)));
+    let frame = emit(Inst::PushI16(PushI16Inst::new(0, ROUNDS)));
+    emit(Inst::PopStack(PopStackInst::new(0, 1)));
+    let round = emit(Inst::PushStack(PushStackInst::new(0, 0)));
+    emit(Inst::PushStack(PushStackInst::new(0, 1)));
+    emit(Inst::Call(CallInst::new(0, mix)));
+    emit(Inst::PushReturn(PushReturnInst::new(0)));
+    emit(Inst::PopStack(PopStackInst::new(0, 0)));
+    // table[acc % 64] = acc; global 17 = table[5]
+    emit(Inst::PushStack(PushStackInst::new(0, 0)));
+    emit(Inst::PushI8(PushI8Inst::new(0, 64)));
+    emit(Inst::Mod(ModInst::new(0)));
+    emit(Inst::PushStack(PushStackInst::new(0, 0)));
+    emit(Inst::PopGlobalTable(PopGlobalTableInst::new(0, 16)));
+    emit(Inst::PushI8(PushI8Inst::new(0, 5)));
+    emit(Inst::PushGlobalTable(PushGlobalTableInst::new(0, 16)));
+    emit(Inst::PopGlobal(PopGlobalInst::new(0, 17)));
+    emit(Inst::PushStack(PushStackInst::new(0, 1)));
+    emit(Inst::PushI8(PushI8Inst::new(0, 1)));
+    emit(Inst::Sub(SubInst::new(0)));
+    emit(Inst::PopStack(PopStackInst::new(0, 1)));
+    emit(Inst::PushStack(PushStackInst::new(0, 1)));
+    emit(Inst::PushI8(PushI8Inst::new(0, 0)));
+    emit(Inst::SetE(SeteInst::new(0)));
+    emit(Inst::Jz(JzInst::new(0, round)));
+    emit(Inst::PushI8(PushI8Inst::new(0, 0)));
+    emit(Inst::PushString(PushStringInst::new(0, "本日は晴天なり".to_string())));
+    emit(Inst::Syscall(SyscallInst::new(0, 3, "TextPrint".to_string())));
+    emit(Inst::Syscall(SyscallInst::new(0, 0, "ThreadNext".to_string())));
+    emit(Inst::Jmp(JmpInst::new(0, frame)));
+
+    // main: start the workers, then print, roll and wait every frame
+    let main = emit(Inst::InitStack(InitStackInst::new(0, 0, 0)));
+    for id in 1..=WORKERS {
+        emit(Inst::PushI8(PushI8Inst::new(0, id)));
+        emit(Inst::PushI32(PushI32Inst::new(0, worker as i32)));
+        emit(Inst::Syscall(SyscallInst::new(0, 1, "ThreadStart".to_string())));
+    }
+    let frame = emit(Inst::PushI8(PushI8Inst::new(0, 1)));
+    emit(Inst::PushString(PushStringInst::new(0, "メインスレッド".to_string())));
+    emit(Inst::Syscall(SyscallInst::new(0, 3, "TextPrint".to_string())));
+    emit(Inst::Syscall(SyscallInst::new(0, 4, "Rand".to_string())));
+    emit(Inst::PushReturn(PushReturnInst::new(0)));
+    emit(Inst::PopGlobal(PopGlobalInst::new(0, 0)));
+    emit(Inst::PushI8(PushI8Inst::new(0, 16)));
+    emit(Inst::Syscall(SyscallInst::new(0, 2, "ThreadWait".to_string())));
+    emit(Inst::Jmp(JmpInst::new(0, frame)));
+
+    builder.entry_point(main).build().unwrap()
+}
+
+/// Run the first frames of a full scenario, without graphics or audio
+///
+/// The scenario is read from `RFVP_BENCH_SCENARIO`, or generated by [`make_game`]. The codepage
+/// of the strings is read from `RFVP_BENCH_NLS`, Shift-JIS by default.
+fn scenario(c: &mut Criterion) {
+    let scenario = match std::env::var("RFVP_BENCH_SCENARIO") {
+        Ok(path) => {
+            let data = std::fs::read(&path)
+                .unwrap_or_else(|e| panic!("failed to read {}: {}", path, e));
+            let nls = std::env::var("RFVP_BENCH_NLS")
+                .ok()
+                .map(|nls| nls.parse::<Nls>().unwrap());
+            Scenario::new(Bytes::from(data), nls).unwrap()
+        }
+        Err(_) => make_game(),
+    };
+
+    c.bench_function("scenario", |b| {
+        b.iter(|| {
+            let mut scripter = Scripter::new();
+            scripter.start_main(scenario.get_entry_point());
+            for _ in 0..FRAMES {
+                let mut cmd = scripter.run(&scenario, 16).unwrap();
+                while let Some(command) = cmd {
+                    execute(&mut scripter, &command);
+                    cmd = scripter.resume(&scenario, CommandResult::None).unwrap();
+                }
+            }
+        })
+    });
+}
+
+criterion_group!(benches, interpreter, scenario);
+criterion_main!(benches);
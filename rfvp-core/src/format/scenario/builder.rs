@@ -0,0 +1,299 @@
+//! Writing scenarios in the HCB layout.
+//!
+//! The file starts with the offset of the header, followed by the code area. The header holds
+//! the entry point, the global counts, the game mode and title, the syscall table and the
+//! script functions registered as syscalls.
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use super::{instructions::Inst, CustomSyscall, Nls, Scenario, Syscall};
+
+/// Builds a scenario from its code area and header fields
+#[derive(Debug, Clone)]
+pub struct ScenarioBuilder {
+    nls: Nls,
+    code: Vec<u8>,
+    entry_point: u32,
+    non_volatile_global_count: u16,
+    volatile_global_count: u16,
+    game_mode: u16,
+    title: String,
+    syscalls: Vec<Syscall>,
+    custom_syscalls: Vec<CustomSyscall>,
+}
+
+impl ScenarioBuilder {
+    /// an empty scenario, strings are encoded with `nls`
+    pub fn new(nls: Nls) -> Self {
+        Self {
+            nls,
+            code: Vec::new(),
+            entry_point: 4,
+            non_volatile_global_count: 0,
+            volatile_global_count: 0,
+            game_mode: 0,
+            title: String::new(),
+            syscalls: Vec::new(),
+            custom_syscalls: Vec::new(),
+        }
+    }
+
+    /// address of the next instruction
+    pub fn offset(&self) -> u32 {
+        4 + self.code.len() as u32
+    }
+
+    /// append raw bytes to the code area
+    pub fn code(&mut self, code: &[u8]) -> &mut Self {
+        self.code.extend_from_slice(code);
+        self
+    }
+
+    /// append an instruction to the code area, returns its address
+    pub fn inst(&mut self, inst: &Inst) -> Result<u32> {
+        let addr = self.offset();
+        let blob = inst.encode(&self.nls)?;
+        self.code.extend_from_slice(&blob);
+        Ok(addr)
+    }
+
+    pub fn entry_point(&mut self, entry_point: u32) -> &mut Self {
+        self.entry_point = entry_point;
+        self
+    }
+
+    pub fn non_volatile_global_count(&mut self, count: u16) -> &mut Self {
+        self.non_volatile_global_count = count;
+        self
+    }
+
+    pub fn volatile_global_count(&mut self, count: u16) -> &mut Self {
+        self.volatile_global_count = count;
+        self
+    }
+
+    pub fn game_mode(&mut self, game_mode: u16) -> &mut Self {
+        self.game_mode = game_mode;
+        self
+    }
+
+    pub fn title(&mut self, title: &str) -> &mut Self {
+        self.title = title.to_string();
+        self
+    }
+
+    /// append a syscall to the table, its id is its position in the table
+    pub fn syscall(&mut self, args: u8, name: &str) -> &mut Self {
+        self.syscalls.push(Syscall {
+            args,
+            name: name.to_string(),
+        });
+        self
+    }
+
+    /// register the function at `address` as a syscall, its id follows the syscall table
+    pub fn custom_syscall(&mut self, address: u32, args: u8, name: &str) -> &mut Self {
+        self.custom_syscalls.push(CustomSyscall {
+            address,
+            args,
+            name: name.to_string(),
+        });
+        self
+    }
+
+    /// serialize the scenario
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let Ok(header_offset) = u32::try_from(4 + self.code.len()) else {
+            bail!("code area too large: {} bytes", self.code.len());
+        };
+        let Ok(syscall_count) = u16::try_from(self.syscalls.len()) else {
+            bail!("too many syscalls: {}", self.syscalls.len());
+        };
+        let Ok(custom_syscall_count) = u16::try_from(self.custom_syscalls.len()) else {
+            bail!("too many custom syscalls: {}", self.custom_syscalls.len());
+        };
+        if syscall_count.checked_add(custom_syscall_count).is_none() {
+            bail!(
+                "too many syscalls: {}",
+                self.syscalls.len() + self.custom_syscalls.len()
+            );
+        }
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&header_offset.to_le_bytes());
+        data.extend_from_slice(&self.code);
+
+        data.extend_from_slice(&self.entry_point.to_le_bytes());
+        data.extend_from_slice(&self.non_volatile_global_count.to_le_bytes());
+        data.extend_from_slice(&self.volatile_global_count.to_le_bytes());
+        data.extend_from_slice(&self.game_mode.to_le_bytes());
+        self.put_string(&self.title, &mut data)?;
+
+        data.extend_from_slice(&syscall_count.to_le_bytes());
+        for syscall in &self.syscalls {
+            data.push(syscall.args);
+            self.put_string(&syscall.name, &mut data)?;
+        }
+        data.extend_from_slice(&custom_syscall_count.to_le_bytes());
+        for syscall in &self.custom_syscalls {
+            data.extend_from_slice(&syscall.address.to_le_bytes());
+            data.push(syscall.args);
+            self.put_string(&syscall.name, &mut data)?;
+        }
+
+        Ok(data)
+    }
+
+    /// serialize the scenario and parse it back
+    pub fn build(&self) -> Result<Scenario> {
+        let data = self.to_bytes()?;
+        Scenario::new(Bytes::from(data), Some(self.nls.clone()))
+    }
+
+    /// a length prefixed, NUL terminated string
+    fn put_string(&self, value: &str, data: &mut Vec<u8>) -> Result<()> {
+        let blob = self.nls.encode(value)?;
+        let Ok(len) = u8::try_from(blob.len() + 1) else {
+            bail!("string too long: {}", value);
+        };
+        data.push(len);
+        data.extend_from_slice(&blob);
+        data.push(0);
+        Ok(())
+    }
+}
+
+impl Scenario {
+    /// a builder holding the code area and the header of this scenario
+    pub fn to_builder(&self) -> ScenarioBuilder {
+        let code_end = (self.sys_desc_offset as usize).clamp(4, self.raw().len().max(4));
+        let mut builder = ScenarioBuilder::new(self.nls.clone());
+        builder
+            .code(self.raw().get(4..code_end).unwrap_or_default())
+            .entry_point(self.entry_point)
+            .non_volatile_global_count(self.non_volatile_global_count)
+            .volatile_global_count(self.volatile_global_count)
+            .game_mode(self.game_mode)
+            .title(&self.game_title);
+        for id in 0..self.syscall_count as usize {
+            if let Some(syscall) = self.syscalls.get(&id) {
+                builder.syscall(syscall.args, &syscall.name);
+            }
+        }
+        for syscall in &self.custom_syscalls {
+            builder.custom_syscall(syscall.address, syscall.args, &syscall.name);
+        }
+        builder
+    }
+
+    /// serialize the scenario again
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.to_builder().to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::scenario::instructions::inst::{
+        InitStackInst, PushStringInst, RetInst, SyscallInst,
+    };
+
+    #[test]
+    fn build_and_reserialize() {
+        let mut builder = ScenarioBuilder::new(Nls::ShiftJIS);
+        let entry = builder
+            .inst(&Inst::InitStack(InitStackInst::new(0, 0, 0)))
+            .unwrap();
+        builder
+            .inst(&Inst::PushString(PushStringInst::new(
+                0,
+                "こんにちは".to_string(),
+            )))
+            .unwrap();
+        builder
+            .inst(&Inst::Syscall(SyscallInst::new(
+                0,
+                1,
+                "TextPrint".to_string(),
+            )))
+            .unwrap();
+        builder.inst(&Inst::Ret(RetInst::new(0))).unwrap();
+        builder
+            .entry_point(entry)
+            .non_volatile_global_count(3)
+            .volatile_global_count(2)
+            .game_mode(6)
+            .title("タイトル")
+            .syscall(0, "ThreadNext")
+            .syscall(2, "TextPrint")
+            .custom_syscall(entry, 0, "スタート");
+
+        let data = builder.to_bytes().unwrap();
+        let scenario = builder.build().unwrap();
+        assert_eq!(scenario.raw(), &data[..]);
+        assert_eq!(scenario.get_entry_point(), 4);
+        assert_eq!(scenario.get_non_volatile_global_count(), 3);
+        assert_eq!(scenario.get_volatile_global_count(), 2);
+        assert_eq!(scenario.get_screen_size(), (1024, 576));
+        assert_eq!(scenario.get_title(), "タイトル");
+        assert_eq!(scenario.get_syscall_name(1), Some("TextPrint"));
+        assert_eq!(scenario.get_syscall(1).map(|s| s.args), Some(2));
+        assert_eq!(scenario.get_syscall_name(2), Some("スタート"));
+        assert_eq!(scenario.get_custom_syscall(2).map(|s| s.address), Some(4));
+        assert!(scenario.get_custom_syscall(3).is_none());
+
+        let insts = scenario
+            .instructions()
+            .map(|inst| inst.map(|(_, inst)| inst))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(insts.len(), 4);
+
+        assert_eq!(scenario.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn string_too_long() {
+        let mut builder = ScenarioBuilder::new(Nls::UTF8);
+        builder.title(&"a".repeat(255));
+        assert!(builder.to_bytes().is_err());
+        builder.title(&"a".repeat(254));
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn translation_codepages() {
+        for (nls, text) in [
+            (Nls::Big5, "早安，今天天氣很好。"),
+            (Nls::EUCKR, "안녕하세요, 오늘 날씨가 좋네요."),
+            (Nls::Windows1252, "Ça va? Très bien, merci… «déjà vu»"),
+        ] {
+            let mut builder = ScenarioBuilder::new(nls.clone());
+            builder.title(text);
+            builder
+                .inst(&Inst::PushString(PushStringInst::new(0, text.to_string())))
+                .unwrap();
+            let scenario = builder.build().unwrap();
+            assert_eq!(scenario.get_title(), text);
+
+            let (_, inst) = scenario.instructions().next().unwrap().unwrap();
+            assert_eq!(
+                inst,
+                Inst::PushString(PushStringInst::new(4, text.to_string()))
+            );
+            assert_eq!(nls.to_string().parse::<Nls>().unwrap(), nls);
+        }
+    }
+
+    #[test]
+    fn unrepresentable_string() {
+        let mut builder = ScenarioBuilder::new(Nls::ShiftJIS);
+        let push = Inst::PushString(PushStringInst::new(0, "안녕".to_string()));
+        let error = builder.inst(&push).unwrap_err();
+        assert_eq!(error.to_string(), "string is not representable as sjis: 안녕");
+
+        builder.title("안녕");
+        assert!(builder.to_bytes().is_err());
+    }
+}
@@ -1,12 +1,18 @@
 
-use std::mem::size_of;
-
-use crate::{format::scenario::global::GLOBAL, vm::command::Command};
+use crate::{
+    format::scenario::global::Global,
+    vm::{
+        command::Command,
+        program::{Op, Program},
+        syscall::{SyscallResult, SyscallTable},
+    },
+};
 use crate::format::scenario::Scenario;
 use crate::format::scenario::variant::Variant;
 use crate::format::scenario::instructions::Opcode;
 
 use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
 
 static MAX_STACK_SIZE: usize = 0x100;
 
@@ -16,6 +22,18 @@ pub struct StackFrame {
     pub locals_count: u16,
 }
 
+/// A routine on the call stack of a context, reconstructed from its saved stack info
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallFrame {
+    /// entry address of the routine, `None` if the call instruction can't be read
+    pub function: Option<u32>,
+    /// the current instruction of the innermost frame, the call instruction of the others
+    pub pc: usize,
+    /// absolute stack index of the first local, the saved stack info is right below it
+    pub stack_base: usize,
+    pub args: usize,
+}
+
 /// implementation of the virtual machine
 /// stack layout:
 /// |-----------------|
@@ -26,12 +44,14 @@ pub struct StackFrame {
 /// |-----------------|
 /// | local(0)        | <- cur_stack_base
 ///
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Context {
     /// the context id
     id: u64,
     stack: Vec<Variant>,
     cursor: usize,
+    /// address of the last dispatched instruction
+    last_pc: usize,
     /// absolute position of the current stack pointer
     /// start from 0 if the context is just created
     cur_stack_pos: usize,
@@ -58,6 +78,7 @@ impl Context {
             id: 0,
             stack: vec![Variant::Nil; MAX_STACK_SIZE],
             cursor: start_addr as usize,
+            last_pc: start_addr as usize,
             cur_stack_pos: 0,
             cur_stack_base: 0,
             start_addr,
@@ -75,6 +96,8 @@ impl Context {
                 stack_pos: 0, 
                 return_addr: 0,
                 args: 0,
+                call_addr: 0,
+                function: start_addr,
             }
         )).unwrap();
 
@@ -135,9 +158,7 @@ impl Context {
                 let msg = format!("pop: stack pointer out of bounds: {:x}", self.cursor);
                 bail!(msg);
             }
-            let r = self.stack[pos].clone();
-            self.stack[pos].set_nil();
-            r
+            std::mem::take(&mut self.stack[pos])
         }
         else {
             bail!("stack pointer out of bounds");
@@ -224,6 +245,145 @@ impl Context {
         Ok(())
     }
 
+    /// the value on the top of the stack, if any
+    pub fn peek(&self) -> Option<&Variant> {
+        if self.cur_stack_pos == 0 {
+            return None;
+        }
+        let pos = self.to_global_offset().ok()?;
+        self.stack.get(pos.checked_sub(1)?)
+    }
+
+    pub fn get_start_addr(&self) -> u32 {
+        self.start_addr
+    }
+
+    /// Move the context to a reassembled scenario
+    ///
+    /// `relocate` gives the new address of an instruction of the original scenario. Only the
+    /// frames on the call stack are moved, the stale ones above it are left as they are.
+    pub fn relocate(&mut self, relocate: impl Fn(usize) -> Result<usize>) -> Result<()> {
+        if self.state == CONTEXT_STATUS_NONE {
+            return Ok(());
+        }
+        self.cursor = relocate(self.cursor)?;
+        self.last_pc = relocate(self.last_pc)?;
+        self.start_addr = relocate(self.start_addr as usize)? as u32;
+
+        let mut base = self.cur_stack_base;
+        let mut depth = 0;
+        while let Some(info) = base
+            .checked_sub(1)
+            .and_then(|pos| self.stack.get_mut(pos))
+            .and_then(|v| v.as_saved_stack_info_mut())
+        {
+            depth += 1;
+            if info.return_addr == 0 {
+                break;
+            }
+            info.return_addr = relocate(info.return_addr)?;
+            info.call_addr = relocate(info.call_addr)?;
+            info.function = relocate(info.function as usize)? as u32;
+            if info.stack_base >= base || depth >= MAX_STACK_SIZE {
+                break;
+            }
+            base = info.stack_base;
+        }
+        Ok(())
+    }
+
+    /// The call stack at the last dispatched instruction, innermost routine first
+    pub fn backtrace(&self, scenario: &Scenario) -> Vec<CallFrame> {
+        self.walk_frames(scenario, self.last_pc)
+    }
+
+    /// The call stack at the next instruction, innermost routine first
+    pub fn frames(&self, scenario: &Scenario) -> Vec<CallFrame> {
+        self.walk_frames(scenario, self.cursor)
+    }
+
+    /// the number of routines on the call stack
+    pub fn call_depth(&self) -> usize {
+        let mut depth = 0;
+        let mut base = self.cur_stack_base;
+        while let Some(info) = base
+            .checked_sub(1)
+            .and_then(|pos| self.stack.get(pos))
+            .and_then(|v| v.as_saved_stack_info())
+        {
+            depth += 1;
+            if info.return_addr == 0 || info.stack_base >= base || depth >= MAX_STACK_SIZE {
+                break;
+            }
+            base = info.stack_base;
+        }
+        depth
+    }
+
+    /// the arguments of the routine, in the order they were pushed by the caller
+    pub fn frame_args(&self, frame: &CallFrame) -> Vec<Variant> {
+        // the arguments are right below the saved stack info
+        let end = frame.stack_base.saturating_sub(1);
+        let start = end.saturating_sub(frame.args);
+        self.stack.get(start..end).map_or_else(Vec::new, |args| args.to_vec())
+    }
+
+    /// the locals of the routine, as declared by the init_stack instruction at its entry
+    pub fn frame_locals(&self, scenario: &Scenario, frame: &CallFrame) -> Vec<Variant> {
+        let locals_count = frame
+            .function
+            .and_then(|function| declared_stack(scenario, function))
+            .map_or(0, |(_, locals)| locals);
+
+        let end = (frame.stack_base + locals_count).min(self.stack.len());
+        self.stack.get(frame.stack_base..end).map_or_else(Vec::new, |locals| locals.to_vec())
+    }
+
+    fn walk_frames(&self, scenario: &Scenario, pc: usize) -> Vec<CallFrame> {
+        let mut frames = Vec::new();
+        let mut base = self.cur_stack_base;
+        let mut pc = pc;
+
+        while let Some(info) = base
+            .checked_sub(1)
+            .and_then(|pos| self.stack.get(pos))
+            .and_then(|v| v.as_saved_stack_info())
+        {
+            // the initial frame pushed when the context is created
+            if info.return_addr == 0 {
+                frames.push(CallFrame {
+                    function: Some(self.start_addr),
+                    pc,
+                    stack_base: base,
+                    args: info.args,
+                });
+                break;
+            }
+
+            // the arguments are recorded by init_stack, before it only the instruction knows them
+            let args = if info.function as usize == pc {
+                declared_stack(scenario, info.function).map_or(info.args, |(args, _)| args)
+            } else {
+                info.args
+            };
+            frames.push(CallFrame {
+                function: Some(info.function),
+                pc,
+                stack_base: base,
+                args,
+            });
+
+            // a corrupted stack must not loop forever
+            if info.stack_base >= base || frames.len() >= MAX_STACK_SIZE {
+                break;
+            }
+            pc = info.call_addr;
+            base = info.stack_base;
+        }
+
+        frames
+    }
+
     fn print_stack(&self) {
         log::error!("thread id : {}", self.id);
         log::error!("pc: {:x}", self.cursor);
@@ -236,26 +396,19 @@ impl Context {
     /// 0x00 nop instruction
     /// nop, no operation
     pub fn nop(&mut self) -> Result<()> {
-        self.cursor += 1;
         Ok(())
     }
 
     /// 0x01 init stack instruction
     /// initialize the local routine stack, as well as
     /// the post-phase of perforimg call instruction or launching a new routine
-    pub fn init_stack(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-
+    pub fn init_stack(&mut self, args_count: i8, locals_count: i8) -> Result<()> {
         // how many arguments are passed to the routine
-        let args_count = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
         if args_count < 0 {
             bail!("args count is negative");
         }
 
         // how many locals are declared in the routine
-        let locals_count = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
         if locals_count < 0 {
             bail!("locals count is negative");
         }
@@ -280,15 +433,8 @@ impl Context {
 
 
     /// 0x02 call instruction
-    /// call a routine
-    pub fn call(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let addr = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
-        if !scenario.is_code_area(addr) {
-            bail!("call: address is not in the code area");
-        }
-
+    /// call a routine, the cursor is already at the return address
+    pub fn call(&mut self, addr: u32) -> Result<()> {
         tracing::trace!("call: {:x}", addr);
 
         let frame = Variant::SavedStackInfo(
@@ -297,6 +443,8 @@ impl Context {
                 stack_pos: self.cur_stack_pos, 
                 return_addr: self.cursor,
                 args: 0, // the field will be updated in the init_stack instruction
+                call_addr: self.last_pc,
+                function: addr,
             }
         );
 
@@ -312,480 +460,32 @@ impl Context {
 
     /// 0x03 syscall
     /// call a system call
-    pub fn syscall(&mut self, scenario: &Scenario) -> Result<Command> {
-        self.cursor += 1;
-        let id = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
-        if let Some(syscall) = scenario.get_syscall(id) {
-            let mut args = Vec::new();
-            for _ in 0..syscall.args {
-                args.push(self.pop()?);
-            }
+    pub fn syscall(&mut self, id: u16, syscalls: &SyscallTable) -> Result<Option<Command>> {
+        let Some(syscall) = syscalls.get(id) else {
+            bail!("syscall not found, id: {}", id);
+        };
 
-            // reverse the arguments
-            args.reverse();
-
-            tracing::trace!("syscall: {} {:?}", &syscall.name, &args);
-            let proxy = match syscall.name.as_str() {
-                "AudioLoad" => {
-                    Command::AudioLoad{ args }
-                },
-                "AudioPlay" => {
-                    Command::AudioPlay{ args }
-                },
-                "AudioSilentOn" => {
-                    Command::AudioSilentOn{ args }
-                },
-                "AudioState" => {
-                    Command::AudioState{ args }
-                },
-                "AudioStop" => {
-                    Command::AudioStop{ args }
-                },
-                "AudioType" => {
-                    Command::AudioType{ args }
-                },
-                "AudioVol" => {
-                    Command::AudioVol{ args }
-                },
-                "ColorSet" => {
-                    Command::ColorSet{ args }
-                },
-                "ControlMask" => {
-                    Command::ControlMask{ args }
-                },
-                "ControlPulse" => {
-                    Command::ControlPulse{ args }
-                },
-                "CursorChange" => {
-                    Command::CursorChange{ args }
-                },
-                "CursorMove" => {
-                    Command::CursorMove{ args }
-                },
-                "CursorShow" => {
-                    Command::CursorShow{ args }
-                },
-                "Debmess" => {
-                    Command::Debmess{ args }
-                },
-                "Dissolve" => {
-                    Command::Dissolve{ args }
-                },
-                "DissolveWait" => {
-                    Command::DissolveWait{ args }
-                },
-                "ExitDialog" => {
-                    Command::ExitDialog{ args }
-                },
-                "ExitMode" => {
-                    Command::ExitMode{ args }
-                },
-                "FlagGet" => {
-                    Command::FlagGet{ args }
-                },
-                "FlagSet" => {
-                    Command::FlagSet{ args }
-                },
-                "FloatToInt" => {
-                    Command::FloatToInt{ args }
-                },
-                "GaijiLoad" => {
-                    Command::GaijiLoad{ args }
-                },
-                "GraphLoad" => {
-                    Command::GraphLoad{ args }
-                },
-                "GraphRGB" => {
-                    Command::GraphRGB{ args }
-                },
-                "IntToText" => {
-                    Command::IntToText{ args }
-                },
-                "HistoryGet" => {
-                    Command::HistoryGet{ args }
-                },
-                "HistorySet" => {
-                    Command::HistorySet{ args }
-                },
-                "InputFlash" => {
-                    Command::InputFlash{ args }
-                },
-                "InputGetCursIn" => {
-                    Command::InputGetCursIn{ args }
-                },
-                "InputGetCursX" => {
-                    Command::InputGetCursX{ args }
-                },
-                "InputGetCursY" => {
-                    Command::InputGetCursY{ args }
-                },
-                "InputGetDown" => {
-                    Command::InputGetDown{ args }
-                },
-                "InputGetEvent" => {
-                    Command::InputGetEvent{ args }
-                },
-                "InputGetRepeat" => {
-                    Command::InputGetRepeat{ args }
-                },
-                "InputGetState" => {
-                    Command::InputGetState{ args }
-                },
-                "InputGetUp" => {
-                    Command::InputGetUp{ args }
-                },
-                "InputGetWheel" => {
-                    Command::InputGetWheel{ args }
-                },
-                "InputSetClick" => {
-                    Command::InputSetClick { args }
-                },
-                "LipAnim" => {
-                    Command::LipAnim{ args }
-                },
-                "LipSync" => {
-                    Command::LipSync{ args }
-                },
-                "Load" => {
-                    Command::Load{ args }
-                },
-                "MenuMessSkip" => {
-                    Command::MenuMessSkip{ args }
-                },
-                "MotionAlpha" => {
-                    Command::MotionAlpha{ args }
-                },
-                "MotionAlphaStop" => {
-                    Command::MotionAlphaStop{ args }
-                },
-                "MotionAlphaTest" => {
-                    Command::MotionAlphaTest{ args }
-                },
-                "MotionAnim" => {
-                    Command::MotionAnim{ args }
-                },
-                "MotionAnimStop" => {
-                    Command::MotionAnimStop{ args }
-                },
-                "MotionAnimTest" => {
-                    Command::MotionAnimTest{ args }
-                },
-                "MotionMove" => {
-                    Command::MotionMove{ args }
-                },
-                "MotionMoveStop" => {
-                    Command::MotionMoveStop{ args }
-                },
-                "MotionMoveTest" => {
-                    Command::MotionMoveTest{ args }
-                },
-                "MotionMoveR" => {
-                    Command::MotionMoveR{ args }
-                },
-                "MotionMoveRStop" => {
-                    Command::MotionMoveRStop{ args }
-                },
-                "MotionMoveRTest" => {
-                    Command::MotionMoveRTest{ args }
-                },
-                "MotionMoveS2" => {
-                    Command::MotionMoveS2{ args }
-                },
-                "MotionMoveS2Stop" => {
-                    Command::MotionMoveS2Stop{ args }
-                },
-                "MotionMoveS2Test" => {
-                    Command::MotionMoveS2Test{ args }
-                },
-                "MotionMoveZ" => {
-                    Command::MotionMoveZ{ args }
-                },
-                "MotionMoveZStop" => {
-                    Command::MotionMoveZStop{ args }
-                },
-                "MotionMoveZTest" => {
-                    Command::MotionMoveZTest{ args }
-                },
-                "MotionPause" => {
-                    Command::MotionPause{ args }
-                },
-                "Movie" => {
-                    Command::Movie{ args }
-                },
-                "MovieState" => {
-                    Command::MovieState{ args }
-                },
-                "MovieStop" => {
-                    Command::MovieStop{ args }
-                },
-                "PartsAssign" => {
-                    Command::PartsAssign{ args }
-                },
-                "PartsLoad" => {
-                    Command::PartsLoad{ args }
-                },
-                "PartsMotion" => {
-                    Command::PartsMotion{ args }
-                },
-                "PartsMotionPause" => {
-                    Command::PartsMotionPause{ args }
-                },
-                "PartsMotionStop" => {
-                    Command::PartsMotionStop{ args }
-                },
-                "PartsMotionTest" => {
-                    Command::PartsMotionTest{ args }
-                },
-                "PartsRGB" => {
-                    Command::PartsRGB{ args }
-                },
-                "PartsSelect" => {
-                    Command::PartsSelect{ args }
-                },
-                "PrimExitGroup" => {
-                    Command::PrimExitGroup{ args }
-                },
-                "PrimGroupIn" => {
-                    Command::PrimGroupIn{ args }
-                },
-                "PrimGroupMove" => {
-                    Command::PrimGroupMove{ args }
-                },
-                "PrimGroupOut" => {
-                    Command::PrimGroupOut{ args }
-                },
-                "PrimHit" => {
-                    Command::PrimHit{ args }
-                },
-                "PrimSetAlpha" => {
-                    Command::PrimSetAlpha{ args }
-                },
-                "PrimSetBlend" => {
-                    Command::PrimSetBlend{ args }
-                },
-                "PrimSetDraw" => {
-                    Command::PrimSetDraw{ args }
-                },
-                "PrimSetNull" => {
-                    Command::PrimSetNull{ args }
-                },
-                "PrimSetOP" => {
-                    Command::PrimSetOP{ args }
-                },
-                "PrimSetRS" => {
-                    Command::PrimSetRS{ args }
-                },
-                "PrimSetRS2" => {
-                    Command::PrimSetRS2{ args }
-                },
-                "PrimSetSnow" => {
-                    Command::PrimSetSnow{ args }
-                },
-                "PrimSetSprt" => {
-                    Command::PrimSetSprt{ args }
-                },
-                "PrimSetText" => {
-                    Command::PrimSetText{ args }
-                },
-                "PrimSetTile" => {
-                    Command::PrimSetTile{ args }
-                },
-                "PrimSetUV" => {
-                    Command::PrimSetUV{ args }
-                },
-                "PrimSetWH" => {
-                    Command::PrimSetWH{ args }
-                },
-                "PrimSetXY" => {
-                    Command::PrimSetXY{ args }
-                },
-                "PrimSetZ" => {
-                    Command::PrimSetZ{ args }
-                },
-                "Rand" => {
-                    Command::Rand{ args }
-                },
-                "SaveCreate" => {
-                    Command::SaveCreate{ args }
-                },
-                "SaveThumbSize" => {
-                    Command::SaveThumbSize{ args }
-                },
-                "SaveData" => {
-                    Command::SaveData{ args }
-                },
-                "SaveWrite" => {
-                    Command::SaveWrite{ args }
-                },
-                "Snow" => {
-                    Command::Snow{ args }
-                },
-                "SnowStart" => {
-                    Command::SnowStart{ args }
-                },
-                "SnowStop" => {
-                    Command::SnowStop{ args }
-                },
-                "SoundLoad" => {
-                    Command::SoundLoad{ args }
-                },
-                "SoundMasterVol" => {
-                    Command::SoundMasterVol{ args }
-                },
-                "SoundPlay" => {
-                    Command::SoundPlay{ args }
-                },
-                "SoundSilentOn" => {
-                    Command::SoundSilentOn{ args }
-                },
-                "SoundStop" => {
-                    Command::SoundStop{ args }
-                },
-                "SoundType" => {
-                    Command::SoundType{ args }
-                },
-                "SoundTypeVol" => {
-                    Command::SoundTypeVol{ args }
-                },
-                "SoundVol" => {
-                    Command::SoundVol{ args }
-                },
-                "SysAtSkipName" => {
-                    Command::SysAtSkipName{ args }
-                },
-                "SysProjFolder" => {
-                    Command::SysProjFolder{ args }
-                },
-                "TextBuff" => {
-                    Command::TextBuff{ args }
-                },
-                "TextClear" => {
-                    Command::TextClear{ args }
-                },
-                "TextColor" => {
-                    Command::TextColor{ args }
-                },
-                "TextFont" => {
-                    Command::TextFont{ args }
-                },
-                "TextFontCount" => {
-                    Command::TextFontCount{ args }
-                },
-                "TextFontGet" => {
-                    Command::TextFontGet{ args }
-                },
-                "TextFontName" => {
-                    Command::TextFontName{ args }
-                },
-                "TextFontSet" => {
-                    Command::TextFontSet{ args }
-                },
-                "TextFormat" => {
-                    Command::TextFormat{ args }
-                },
-                "TextFunction" => {
-                    Command::TextFunction{ args }
-                },
-                "TextOutSize" => {
-                    Command::TextOutSize{ args }
-                },
-                "TextPause" => {
-                    Command::TextPause{ args }
-                },
-                "TextPos" => {
-                    Command::TextPos{ args }
-                },
-                "TextPrint" => {
-                    Command::TextPrint{ args }
-                },
-                "TextRepaint" => {
-                    Command::TextRepaint{ args }
-                },
-                "TextShadowDist" => {
-                    Command::TextShadowDist{ args }
-                },
-                "TextSize" => {
-                    Command::TextSize{ args }
-                },
-                "TextSkip" => {
-                    Command::TextSkip{ args }
-                },
-                "TextSpace" => {
-                    Command::TextSpace{ args }
-                },
-                "TextSpeed" => {
-                    Command::TextSpeed{ args }
-                },
-                "TextSuspendChr" => {
-                    Command::TextSuspendChr{ args }
-                },
-                "TextTest" => {
-                    Command::TextTest{ args }
-                },
-                "ThreadExit" => {
-                    Command::ThreadExit{ args }
-                },
-                "ThreadNext" => {
-                    Command::ThreadNext{ args }
-                },
-                "ThreadRaise" => {
-                    Command::ThreadRaise{ args }
-                },
-                "ThreadSleep" => {
-                    Command::ThreadSleep{ args }
-                },
-                "ThreadStart" => {
-                    Command::ThreadStart{ args }
-                },
-                "ThreadWait" => {
-                    Command::ThreadWait{ args }
-                },
-                "TimerGet" => {
-                    Command::TimerGet{ args }
-                },
-                "TimerSet" => {
-                    Command::TimerSet{ args }
-                },
-                "TimerSuspend" => {
-                    Command::TimerSuspend{ args }
-                },
-                "TitleMenu" => {
-                    Command::TitleMenu{ args }
-                },
-                "V3DMotion" => {
-                    Command::V3DMotion{ args }
-                },
-                "V3DMotionPause" => {
-                    Command::V3DMotionPause{ args }
-                },
-                "V3DMotionStop" => {
-                    Command::V3DMotionStop{ args }
-                },
-                "V3DMotionTest" => {
-                    Command::V3DMotionTest{ args }
-                },
-                "V3DSet" => {
-                    Command::V3DSet{ args }
-                },
-                "WindowMode" => {
-                    Command::WindowMode{ args }
-                },
-                _ => {
-                    bail!("syscall not found: {}", &syscall.name);
-                }
-            };
-            return Ok(proxy);
+        let mut args = Vec::new();
+        for _ in 0..syscall.args {
+            args.push(self.pop()?);
         }
 
-        panic!("syscall should not reach here, id: {}", id);
+        // reverse the arguments
+        args.reverse();
+
+        tracing::trace!("syscall: {} {:?}", &syscall.name, &args);
+        match syscall.call(args)? {
+            SyscallResult::Command(command) => Ok(Some(command)),
+            SyscallResult::Value(value) => {
+                self.return_value = value;
+                Ok(None)
+            }
+        }
     }
 
     /// 0x04 ret instruction
     /// return from a routine
     pub fn ret(&mut self) -> Result<()> {
-        self.cursor += 1;
         self.return_value = Variant::Nil;
         let frame = self.get_local(-1)?;
         if let Some(frame) = frame.as_saved_stack_info() {
@@ -807,7 +507,6 @@ impl Context {
     /// 0x05 retv instruction
     /// return from a routine with a value
     pub fn retv(&mut self) -> Result<()> {
-        self.cursor += 1;
         self.return_value = self.pop()?;
         let frame = self.get_local(-1)?;
         if let Some(frame) = frame.as_saved_stack_info() {
@@ -828,10 +527,7 @@ impl Context {
 
     /// 0x06 jmp instruction
     /// jump to the address
-    pub fn jmp(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let addr = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
+    pub fn jmp(&mut self, addr: u32) -> Result<()> {
         tracing::trace!("jmp: {:x}", addr);
 
         self.cursor = addr as usize;
@@ -840,10 +536,7 @@ impl Context {
 
     /// 0x07 jz instruction
     /// jump to the address if the top of the stack is zero
-    pub fn jz(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let addr = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
+    pub fn jz(&mut self, addr: u32) -> Result<()> {
 
         let top = self.pop()?;
         tracing::trace!("jz: {:?}", &top);
@@ -857,7 +550,6 @@ impl Context {
     /// 0x08 push nil
     /// push a nil value onto the stack
     pub fn push_nil(&mut self) -> Result<()> {
-        self.cursor += 1;
         self.push(Variant::Nil)?;
 
         tracing::trace!("push_nil");
@@ -867,7 +559,6 @@ impl Context {
     /// 0x09 push true
     /// push a true value onto the stack
     pub fn push_true(&mut self) -> Result<()> {
-        self.cursor += 1;
         self.push(Variant::True)?;
 
         tracing::trace!("push_true");
@@ -876,10 +567,7 @@ impl Context {
 
     /// 0x0A push i32
     /// push an i32 value onto the stack
-    pub fn push_i32(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let value = scenario.read_i32(self.cursor)?;
-        self.cursor += size_of::<i32>();
+    pub fn push_i32(&mut self, value: i32) -> Result<()> {
 
         tracing::trace!("push_i32: {}", value);
 
@@ -889,10 +577,7 @@ impl Context {
 
     /// 0x0B push i16
     /// push an i16 value onto the stack
-    pub fn push_i16(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let value = scenario.read_i16(self.cursor)?;
-        self.cursor += size_of::<i16>();
+    pub fn push_i16(&mut self, value: i16) -> Result<()> {
 
         tracing::trace!("push_i16: {}", value);
 
@@ -902,10 +587,7 @@ impl Context {
 
     /// 0x0C push i8
     /// push an i8 value onto the stack
-    pub fn push_i8(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let value = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
+    pub fn push_i8(&mut self, value: i8) -> Result<()> {
 
         tracing::trace!("push_i8: {}", value);
 
@@ -915,10 +597,7 @@ impl Context {
 
     /// 0x0D push f32
     /// push an f32 value onto the stack
-    pub fn push_f32(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let value = scenario.read_f32(self.cursor)?;
-        self.cursor += size_of::<f32>();
+    pub fn push_f32(&mut self, value: f32) -> Result<()> {
 
         tracing::trace!("push_f32: {}", value);
 
@@ -928,44 +607,28 @@ impl Context {
 
     /// 0x0E push string
     /// push a string onto the stack
-    pub fn push_string(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let len = scenario.read_u8(self.cursor)? as usize;
-        self.cursor += size_of::<u8>();
-
-        let s = scenario.read_cstring(self.cursor, len)?;
-        self.cursor += len;
-
+    pub fn push_string(&mut self, s: &str) -> Result<()> {
         tracing::trace!("push_string: {}", &s);
 
-        self.push(Variant::String(s))?;
+        self.push(Variant::String(s.to_string()))?;
         Ok(())
     }
 
     /// 0x0F push global
     /// push a global variable onto the stack
-    pub fn push_global(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
+    pub fn push_global(&mut self, key: u16, globals: &Global) -> Result<()> {
 
         tracing::trace!("push_global: {:x}", key);
 
-        if let Some(value) = GLOBAL.lock().unwrap().get(key) {
-            self.push(value.clone())?;
-            tracing::trace!("global: {:?}", &value);
-        } else {
-            bail!("global variable not found");
-        }
+        let value = globals.read(key)?;
+        tracing::trace!("global: {:?}", &value);
+        self.push(value.clone())?;
         Ok(())
     }
 
     /// 0x10 push stack
     /// push a stack variable onto the stack
-    pub fn push_stack(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let offset = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
+    pub fn push_stack(&mut self, offset: i8) -> Result<()> {
 
         let local = self.get_local(offset)?;
         tracing::trace!("push stack: {} {:?}", offset, &local);
@@ -978,15 +641,12 @@ impl Context {
     /// push a value than stored in the global table by immediate key onto the stack
     /// we assume that if any failure occurs, such as the key not found, 
     /// we will push a nil value onto the stack for compatibility reasons.
-    pub fn push_global_table(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
+    pub fn push_global_table(&mut self, key: u16, globals: &Global) -> Result<()> {
 
         let top = self.pop()?;
         tracing::trace!("push_global_table: {:x} {:?}", key, &top);
-        if let Some(table) = GLOBAL.lock().unwrap().get_mut(key) {
-            if let Some(table) = table.as_table() {
+        if let Some(value) = globals.get(key) {
+            if let Variant::Table(table) = value {
                 if let Some(table_key) = top.as_int() {
                     if let Some(value) = table.get(table_key as u32) {
                         self.push(value.clone())?;
@@ -1013,10 +673,7 @@ impl Context {
 
     /// 0x12 push local table
     /// push a value than stored in the local table by key onto the stack
-    pub fn push_local_table(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
+    pub fn push_local_table(&mut self, idx: i8) -> Result<()> {
 
         let key = self.pop()?.as_int();
 
@@ -1043,7 +700,6 @@ impl Context {
     /// 0x13 push top
     /// push the top of the stack onto the stack
     pub fn push_top(&mut self) -> Result<()> {
-        self.cursor += 1;
         let top = self.top()?;
         self.push(top)?;
         Ok(())
@@ -1052,7 +708,6 @@ impl Context {
     /// 0x14 push return value
     /// push the return value onto the stack
     pub fn push_return_value(&mut self) -> Result<()> {
-        self.cursor += 1;
         self.push(self.return_value.clone())?;
         self.return_value.set_nil();
         Ok(())
@@ -1060,22 +715,16 @@ impl Context {
 
     /// 0x15 pop global
     /// pop the top of the stack and store it in the global table
-    pub fn pop_global(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
+    pub fn pop_global(&mut self, key: u16, globals: &mut Global) -> Result<()> {
 
         let value = self.pop()?;
-        GLOBAL.lock().unwrap().set(key, value);
+        globals.write(key, value)?;
         Ok(())
     }
 
     /// 0x16 local copy
     /// copy the top of the stack to the local variable
-    pub fn local_copy(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
+    pub fn local_copy(&mut self, idx: i8) -> Result<()> {
 
         let value = self.pop()?;
         tracing::trace!("local_copy: {} {:?}", idx, &value);
@@ -1085,15 +734,12 @@ impl Context {
 
     /// 0x17 pop global table
     /// pop the top of the stack and store it in the global table by key
-    pub fn pop_global_table(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
+    pub fn pop_global_table(&mut self, key: u16, globals: &mut Global) -> Result<()> {
 
         let value = self.pop()?;
         let mkey = self.pop()?;
 
-        if let Some(table) = GLOBAL.lock().unwrap().get_mut(key) {
+        if let Some(table) = globals.get_mut(key) {
             // cast to table if it is not
             if !table.is_table() {
                 table.cast_table();
@@ -1116,10 +762,7 @@ impl Context {
 
     /// 0x18 pop local table 
     /// pop the top of the stack and store it in the local table by key
-    pub fn pop_local_table(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
+    pub fn pop_local_table(&mut self, idx: i8) -> Result<()> {
 
         let value = self.pop()?;
         let key = self.pop()?.as_int();
@@ -1143,7 +786,6 @@ impl Context {
     /// 0x19 neg 
     /// negate the top of the stack, only works for integers and floats
     pub fn neg(&mut self) -> Result<()> {
-        self.cursor += 1;
         let mut top = self.pop()?;
 
         tracing::trace!("neg: {:?}", &top);
@@ -1156,7 +798,6 @@ impl Context {
     /// 0x1A add
     /// add the top two values on the stack
     pub fn add(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1169,7 +810,6 @@ impl Context {
     /// 0x1B sub
     /// subtract the top two values on the stack
     pub fn sub(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1182,7 +822,6 @@ impl Context {
     /// 0x1C mul
     /// multiply the top two values on the stack
     pub fn mul(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1195,7 +834,6 @@ impl Context {
     /// 0x1D div
     /// divide the top two values on the stack
     pub fn div(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1208,7 +846,6 @@ impl Context {
     /// 0x1E modulo
     /// modulo the top two values on the stack
     pub fn modulo(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1221,7 +858,6 @@ impl Context {
     /// 0x1F bittest
     /// test with the top two values on the stack
     pub fn bittest(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let a = self.pop()?;
 
@@ -1238,7 +874,6 @@ impl Context {
     /// 0x20 and
     /// push true if both the top two values on the stack are none-nil
     pub fn and(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1251,7 +886,6 @@ impl Context {
     /// 0x21 or
     /// push true if either of the top two values on the stack is none-nil
     pub fn or(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1264,7 +898,6 @@ impl Context {
     /// 0x22 sete
     /// set the top of the stack to true if the top two values on the stack are equal
     pub fn sete(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1277,7 +910,6 @@ impl Context {
     /// 0x23 setne
     /// set the top of the stack to true if the top two values on the stack are not equal
     pub fn setne(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1290,7 +922,6 @@ impl Context {
     /// 0x24 setg
     /// set the top of the stack to true if the top two values on the stack are greater
     pub fn setg(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1303,7 +934,6 @@ impl Context {
     /// 0x25 setle
     /// set the top of the stack to true if the top two values on the stack are less or equal
     pub fn setle(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1316,7 +946,6 @@ impl Context {
     /// 0x26 setl
     /// set the top of the stack to true if the top two values on the stack are less
     pub fn setl(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1329,7 +958,6 @@ impl Context {
     /// 0x27 setge
     /// set the top of the stack to true if the top two values on the stack are greater or equal
     pub fn setge(&mut self) -> Result<()> {
-        self.cursor += 1;
         let b = self.pop()?;
         let mut a = self.pop()?;
 
@@ -1344,6 +972,11 @@ impl Context {
         self.cursor
     }
 
+    /// get the address of the last dispatched instruction
+    pub fn get_last_pc(&self) -> usize {
+        self.last_pc
+    }
+
     /// get waiting time for the context in ms
     pub fn get_waiting_time(&self) -> u64 {
         self.wait_ms
@@ -1375,138 +1008,98 @@ impl Context {
         self.should_exit
     }
 
+    /// get the return value (R0) of the context
+    pub fn get_return_value(&self) -> &Variant {
+        &self.return_value
+    }
+
+    /// set the return value (R0) of the context, used to deliver the result of a syscall
+    pub fn set_return_value(&mut self, value: Variant) {
+        self.return_value = value;
+    }
+
+    /// execute the instruction `index` of the program, it must be the one at the cursor
+    /// returns the command produced by a syscall instruction, if any
     #[inline]
-    pub fn dispatch_opcode(&mut self, scenario: &Scenario) -> Result<()> {
-        let opcode = scenario.read_u8(self.get_pc())? as i32;
-        
-        match opcode.try_into() {
-            Ok(Opcode::Nop) => {
-                self.nop()?;
-            }
-            Ok(Opcode::InitStack) => {
-                self.init_stack(scenario)?;
-            }
-            Ok(Opcode::Call) => {
-                self.call(scenario)?;
-            }
-            Ok(Opcode::Syscall) => {
-                self.syscall(scenario)?;
-            }
-            Ok(Opcode::Ret) => {
-                self.ret()?;
-            }
-            Ok(Opcode::RetV) => {
-                self.retv()?;
-            }
-            Ok(Opcode::Jmp) => {
-                self.jmp(scenario)?;
-            }
-            Ok(Opcode::Jz) => {
-                self.jz(scenario)?;
-            }
-            Ok(Opcode::PushNil) => {
-                self.push_nil()?;
-            }
-            Ok(Opcode::PushTrue) => {
-                self.push_true()?;
-            }
-            Ok(Opcode::PushI32) => {
-                self.push_i32(scenario)?;
-            }
-            Ok(Opcode::PushI16) => {
-                self.push_i16(scenario)?;
-            }
-            Ok(Opcode::PushI8) => {
-                self.push_i8(scenario)?;
-            }
-            Ok(Opcode::PushF32) => {
-                self.push_f32(scenario)?;
-            }
-            Ok(Opcode::PushString) => {
-                self.push_string(scenario)?;
-            }
-            Ok(Opcode::PushGlobal) => {
-                self.push_global(scenario)?;
-            }
-            Ok(Opcode::PushStack) => {
-                self.push_stack(scenario)?;
-            }
-            Ok(Opcode::PushGlobalTable) => {
-                self.push_global_table(scenario)?;
-            }
-            Ok(Opcode::PushLocalTable) => {
-                self.push_local_table(scenario)?;
-            }
-            Ok(Opcode::PushTop) => {
-                self.push_top()?;
-            }
-            Ok(Opcode::PushReturn) => {
-                self.push_return_value()?;
-            }
-            Ok(Opcode::PopGlobal) => {
-                self.pop_global(scenario)?;
-            }
-            Ok(Opcode::PopStack) => {
-                self.local_copy(scenario)?;
-            }
-            Ok(Opcode::PopGlobalTable) => {
-                self.pop_global_table(scenario)?;
-            }
-            Ok(Opcode::PopLocalTable) => {
-                self.pop_local_table(scenario)?;
-            }
-            Ok(Opcode::Neg) => {
-                self.neg()?;
-            }
-            Ok(Opcode::Add) => {
-                self.add()?;
-            }
-            Ok(Opcode::Sub) => {
-                self.sub()?;
-            }
-            Ok(Opcode::Mul) => {
-                self.mul()?;
-            }
-            Ok(Opcode::Div) => {
-                self.div()?;
-            }
-            Ok(Opcode::Mod) => {
-                self.modulo()?;
-            }
-            Ok(Opcode::BitTest) => {
-                self.bittest()?;
-            }
-            Ok(Opcode::And) => {
-                self.and()?;
-            }
-            Ok(Opcode::Or) => {
-                self.or()?;
-            }
-            Ok(Opcode::SetE) => {
-                self.sete()?;
-            }
-            Ok(Opcode::SetNE) => {
-                self.setne()?;
-            }
-            Ok(Opcode::SetG) => {
-                self.setg()?;
-            }
-            Ok(Opcode::SetLE) => {
-                self.setle()?;
-            }
-            Ok(Opcode::SetL) => {
-                self.setl()?;
-            }
-            Ok(Opcode::SetGE) => {
-                self.setge()?;
+    pub fn execute(
+        &mut self,
+        program: &Program,
+        index: Option<usize>,
+        syscalls: &SyscallTable,
+        globals: &mut Global,
+    ) -> Result<Option<Command>> {
+        self.last_pc = self.cursor;
+        let Some((op, next)) = index.and_then(|i| Some((program.get(i)?, program.address(i + 1)?)))
+        else {
+            bail!("no instruction at 0x{:08x}", self.cursor);
+        };
+        self.cursor = next as usize;
+
+        match op {
+            Op::Nop => self.nop()?,
+            Op::InitStack { args, locals } => self.init_stack(*args, *locals)?,
+            Op::Call(target) => {
+                if target.index.is_none() {
+                    bail!("call: address is not in the code area");
+                }
+                self.call(target.addr)?
             }
-            _ => {
+            Op::Syscall(id) => return self.syscall(*id, syscalls),
+            Op::Ret => self.ret()?,
+            Op::RetV => self.retv()?,
+            Op::Jmp(target) => self.jmp(target.addr)?,
+            Op::Jz(target) => self.jz(target.addr)?,
+            Op::PushNil => self.push_nil()?,
+            Op::PushTrue => self.push_true()?,
+            Op::PushI32(value) => self.push_i32(*value)?,
+            Op::PushI16(value) => self.push_i16(*value)?,
+            Op::PushI8(value) => self.push_i8(*value)?,
+            Op::PushF32(value) => self.push_f32(*value)?,
+            Op::PushString(value) => self.push_string(value)?,
+            Op::PushGlobal(key) => self.push_global(*key, globals)?,
+            Op::PushStack(offset) => self.push_stack(*offset)?,
+            Op::PushGlobalTable(key) => self.push_global_table(*key, globals)?,
+            Op::PushLocalTable(idx) => self.push_local_table(*idx)?,
+            Op::PushTop => self.push_top()?,
+            Op::PushReturn => self.push_return_value()?,
+            Op::PopGlobal(key) => self.pop_global(*key, globals)?,
+            Op::PopStack(idx) => self.local_copy(*idx)?,
+            Op::PopGlobalTable(key) => self.pop_global_table(*key, globals)?,
+            Op::PopLocalTable(idx) => self.pop_local_table(*idx)?,
+            Op::Neg => self.neg()?,
+            Op::Add => self.add()?,
+            Op::Sub => self.sub()?,
+            Op::Mul => self.mul()?,
+            Op::Div => self.div()?,
+            Op::Mod => self.modulo()?,
+            Op::BitTest => self.bittest()?,
+            Op::And => self.and()?,
+            Op::Or => self.or()?,
+            Op::SetE => self.sete()?,
+            Op::SetNE => self.setne()?,
+            Op::SetG => self.setg()?,
+            Op::SetLE => self.setle()?,
+            Op::SetL => self.setl()?,
+            Op::SetGE => self.setge()?,
+            Op::Unknown(opcode) => {
                 self.nop()?;
                 log::error!("unknown opcode: {}", opcode);
             }
+            Op::Invalid(error) => bail!("{}", error),
         };
 
-        Ok(())
+        Ok(None)
     }
 
 }
+
+/// the arguments and locals declared by the init_stack instruction at the entry of a routine
+fn declared_stack(scenario: &Scenario, function: u32) -> Option<(usize, usize)> {
+    let function = function as usize;
+    if scenario.read_u8(function).ok()? != Opcode::InitStack as u8 {
+        return None;
+    }
+    let args = scenario.read_i8(function + 1).ok()?.max(0) as usize;
+    let locals = scenario.read_i8(function + 2).ok()?.max(0) as usize;
+    Some((args, locals))
+}
@@ -0,0 +1,254 @@
+//! Guessing the codepage of the strings.
+//!
+//! Every candidate decodes the samples, a sample that is malformed in a codepage counts against
+//! it. Well-formed samples are scored by the characters they decode to: Japanese text read as
+//! GBK lands in the GBK extension instead of GB2312, and Chinese text read as Shift-JIS turns
+//! into half-width katakana, both are rare in real scripts.
+use std::fmt;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{Nls, Scenario};
+
+/// the candidates, in order of preference when they score the same
+///
+/// Big5 and EUC-KR share most of their byte ranges with GBK and any byte string is valid
+/// Windows-1252, they have to be chosen explicitly.
+const CANDIDATES: [Nls; 3] = [Nls::ShiftJIS, Nls::GBK, Nls::UTF8];
+
+/// The most plausible codepage of a set of strings
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub nls: Nls,
+    /// how far ahead of the runner-up the codepage is, from 0 to 1
+    pub confidence: f32,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:.0}% confidence)",
+            self.nls,
+            self.confidence * 100.0
+        )
+    }
+}
+
+/// pick the codepage the samples decode best with, Shift-JIS if they are plain ASCII
+pub fn detect<'a>(samples: impl IntoIterator<Item = &'a [u8]>) -> Detection {
+    let mut scores = [0f32; CANDIDATES.len()];
+    let mut total = 0usize;
+    for sample in samples {
+        let weight = sample.iter().filter(|b| !b.is_ascii()).count();
+        if weight == 0 {
+            continue;
+        }
+        total += weight;
+        for (score, nls) in scores.iter_mut().zip(&CANDIDATES) {
+            *score += weight as f32 * plausibility(nls, sample);
+        }
+    }
+
+    if total == 0 {
+        return Detection {
+            nls: Nls::ShiftJIS,
+            confidence: 0.0,
+        };
+    }
+
+    let mut ranked: Vec<_> = scores
+        .iter()
+        .map(|s| s / total as f32)
+        .enumerate()
+        .collect();
+    // stable, ties keep the order of the candidates
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Detection {
+        nls: CANDIDATES[ranked[0].0].clone(),
+        confidence: (ranked[0].1 - ranked[1].1).clamp(0.0, 1.0),
+    }
+}
+
+/// share of the non-ASCII characters of the sample that are likely in a script, 0 if the
+/// sample is malformed
+fn plausibility(nls: &Nls, sample: &[u8]) -> f32 {
+    let encoding = nls.encoding();
+    let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(sample) else {
+        return 0.0;
+    };
+
+    let mut chars = 0;
+    let mut score = 0.0;
+    match nls {
+        Nls::UTF8 | Nls::Big5 | Nls::EUCKR | Nls::Windows1252 => {
+            for c in text.chars().filter(|c| !c.is_ascii()) {
+                chars += 1;
+                if !c.is_control() && !('\u{E000}'..='\u{F8FF}').contains(&c) {
+                    score += 1.0;
+                }
+            }
+        }
+        Nls::GBK => {
+            let mut i = 0;
+            while i < sample.len() {
+                let lead = sample[i];
+                if lead.is_ascii() {
+                    i += 1;
+                    continue;
+                }
+                chars += 1;
+                match sample.get(i + 1) {
+                    // four byte GB18030 sequence
+                    Some(0x30..=0x39) => i += 4,
+                    Some(&trail) => {
+                        // the GB2312 area, everything else is the extension
+                        if (0xA1..=0xF7).contains(&lead) && trail >= 0xA1 {
+                            score += 1.0;
+                        }
+                        i += 2;
+                    }
+                    None => i += 1,
+                }
+            }
+        }
+        Nls::ShiftJIS | Nls::Auto => {
+            let mut i = 0;
+            while i < sample.len() {
+                let lead = sample[i];
+                if lead.is_ascii() {
+                    i += 1;
+                    continue;
+                }
+                chars += 1;
+                match lead {
+                    // half-width katakana
+                    0xA1..=0xDF => i += 1,
+                    // symbols, kana and the first level kanji
+                    0x81..=0x9F => {
+                        score += 1.0;
+                        i += 2;
+                    }
+                    // second level kanji
+                    0xE0..=0xEA => {
+                        score += 0.5;
+                        i += 2;
+                    }
+                    _ => i += 2,
+                }
+            }
+        }
+    }
+
+    if chars == 0 {
+        return 1.0;
+    }
+    score / chars as f32
+}
+
+impl Scenario {
+    /// detect the codepage from the title, the syscall names and the string literals
+    pub fn detect_nls(data: &Bytes) -> Result<Detection> {
+        let mut scenario = Scenario::empty(data.clone(), Nls::ShiftJIS);
+        scenario.sys_desc_offset = scenario.read_u32(0)?;
+        let mut samples = scenario.header_strings()?;
+        samples.extend(scenario.string_literals());
+        Ok(detect(samples))
+    }
+
+    /// the undecoded title, syscall and custom syscall names
+    fn header_strings(&self) -> Result<Vec<&[u8]>> {
+        let mut samples = Vec::new();
+        // entry point, global counts and game mode
+        let mut off = self.sys_desc_offset as usize + 10;
+
+        let title_len = self.read_u8(off)? as usize;
+        samples.push(self.read_cbytes(off + 1, title_len)?);
+        off += 1 + title_len;
+
+        let syscall_count = self.read_u16(off)?;
+        off += 2;
+        for _ in 0..syscall_count {
+            let name_len = self.read_u8(off + 1)? as usize;
+            samples.push(self.read_cbytes(off + 2, name_len)?);
+            off += 2 + name_len;
+        }
+
+        let custom_syscall_count = self.read_u16(off)?;
+        off += 2;
+        for _ in 0..custom_syscall_count {
+            // address and argument count
+            let name_len = self.read_u8(off + 5)? as usize;
+            samples.push(self.read_cbytes(off + 6, name_len)?);
+            off += 6 + name_len;
+        }
+
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::scenario::{
+        instructions::{inst::PushStringInst, Inst},
+        ScenarioBuilder,
+    };
+
+    const JAPANESE: [&str; 3] = [
+        "アストラエアの白き永遠",
+        "おはようございます。今日もいい天気ですね。",
+        "「……それで、どうするつもり？」",
+    ];
+
+    const CHINESE: [&str; 3] = [
+        "早上好，今天天气很好。",
+        "我们一起去学校吧！",
+        "“……那么，你打算怎么办？”",
+    ];
+
+    fn encode(nls: &Nls, texts: &[&str]) -> Vec<Vec<u8>> {
+        texts
+            .iter()
+            .map(|text| nls.encoding().encode(text).0.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn detect_codepages() {
+        for (nls, texts) in [
+            (Nls::ShiftJIS, &JAPANESE),
+            (Nls::GBK, &CHINESE),
+            (Nls::UTF8, &JAPANESE),
+            (Nls::UTF8, &CHINESE),
+        ] {
+            let samples = encode(&nls, texts);
+            let detection = detect(samples.iter().map(|s| s.as_slice()));
+            assert_eq!(detection.nls, nls);
+            assert!(detection.confidence > 0.5, "{}", detection);
+        }
+
+        let detection = detect([b"ThreadNext".as_slice()]);
+        assert_eq!(detection.nls, Nls::ShiftJIS);
+        assert_eq!(detection.confidence, 0.0);
+    }
+
+    #[test]
+    fn auto_scenario() {
+        let mut builder = ScenarioBuilder::new(Nls::GBK);
+        builder.title(CHINESE[0]).syscall(0, "ThreadNext");
+        for text in CHINESE {
+            let inst = Inst::PushString(PushStringInst::new(0, text.to_string()));
+            builder.inst(&inst).unwrap();
+        }
+        let data = Bytes::from(builder.to_bytes().unwrap());
+
+        let detection = Scenario::detect_nls(&data).unwrap();
+        assert_eq!(detection.nls, Nls::GBK);
+
+        let scenario = Scenario::new(data, Some(Nls::Auto)).unwrap();
+        assert_eq!(scenario.nls, Nls::GBK);
+        assert_eq!(scenario.get_title(), CHINESE[0]);
+    }
+}
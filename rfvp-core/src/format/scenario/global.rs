@@ -1,14 +1,20 @@
-use std::{collections::HashMap, sync::Mutex};
+use std::collections::BTreeMap;
 
-use crate::format::scenario::variant::Variant;
+use anyhow::{bail, Result};
+use crate::format::scenario::{variant::Variant, Scenario};
 use serde::{Serialize, Deserialize};
 
-/// Global variables
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// Global variables of a VM instance
+/// slots [0, none_volatile_count) are non-volatile, the volatile ones follow them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Global {
-    global_table: HashMap<u16, Variant>,
+    /// ordered, so the same globals always serialize to the same bytes
+    global_table: BTreeMap<u16, Variant>,
     none_volatile_count: u16, 
-    volatile_count: u16
+    volatile_count: u16,
+    /// a non-volatile slot was written since the last call of `take_non_volatile_changed`
+    #[serde(skip)]
+    non_volatile_changed: bool,
 }
 
 
@@ -16,21 +22,34 @@ impl Global {
     pub fn new() -> Self {
 
         Global {
-            global_table: HashMap::new(),
+            global_table: BTreeMap::new(),
             none_volatile_count: 0,
-            volatile_count: 0
+            volatile_count: 0,
+            non_volatile_changed: false,
         }
     }
 
+    /// the globals declared by the scenario, all set to nil
+    pub fn for_scenario(scenario: &Scenario) -> Self {
+        let mut global = Self::new();
+        global.init_with(
+            scenario.get_non_volatile_global_count(),
+            scenario.get_volatile_global_count(),
+        );
+        global
+    }
+
     pub fn get(&self, key: u16) -> Option<&Variant> {
         self.global_table.get(&key)
     }
 
     pub fn get_mut(&mut self, key: u16) -> Option<&mut Variant> {
+        self.non_volatile_changed |= self.is_non_volatile(key);
         self.global_table.get_mut(&key)
     }
 
     pub fn set(&mut self, key: u16, value: Variant) {
+        self.non_volatile_changed |= self.is_non_volatile(key);
         self.global_table.insert(key, value);
     }
     
@@ -39,6 +58,7 @@ impl Global {
         self.none_volatile_count = none_volatile;
         self.volatile_count = volatile;
 
+        self.global_table.clear();
         for i in 0..none_volatile + volatile {
             self.global_table.insert(i, Variant::Nil);
         }
@@ -51,14 +71,88 @@ impl Global {
         }
         0
     }
-}
 
-lazy_static::lazy_static! {
-    pub static ref GLOBAL: Mutex<Global> =  Mutex::new(Global::new());
-}
+    /// the number of slots
+    pub fn len(&self) -> usize {
+        self.none_volatile_count as usize + self.volatile_count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_non_volatile_count(&self) -> u16 {
+        self.none_volatile_count
+    }
+
+    pub fn get_volatile_count(&self) -> u16 {
+        self.volatile_count
+    }
+
+    pub fn is_non_volatile(&self, slot: u16) -> bool {
+        slot < self.none_volatile_count
+    }
+
+    /// read a slot, fails if the scenario does not declare it
+    pub fn read(&self, slot: u16) -> Result<&Variant> {
+        match self.global_table.get(&slot) {
+            Some(value) if (slot as usize) < self.len() => Ok(value),
+            _ => bail!("global variable out of range: {}, count: {}", slot, self.len()),
+        }
+    }
+
+    /// write a slot, fails if the scenario does not declare it
+    pub fn write(&mut self, slot: u16, value: Variant) -> Result<()> {
+        if slot as usize >= self.len() {
+            bail!("global variable out of range: {}, count: {}", slot, self.len());
+        }
+        self.non_volatile_changed |= self.is_non_volatile(slot);
+        self.global_table.insert(slot, value);
+        Ok(())
+    }
 
+    /// whether a non-volatile slot was written since the last call, resets the flag
+    pub fn take_non_volatile_changed(&mut self) -> bool {
+        std::mem::take(&mut self.non_volatile_changed)
+    }
+
+    /// the values of the non-volatile slots in order
+    pub fn non_volatile_values(&self) -> Vec<Variant> {
+        (0..self.none_volatile_count)
+            .map(|slot| self.global_table.get(&slot).cloned().unwrap_or_default())
+            .collect()
+    }
 
-pub fn get_int_var(key: u16) -> i32 {
-    GLOBAL.lock().unwrap().get_int_var(key)
+    /// restore the non-volatile slots, extra values are ignored and missing ones are left as is
+    pub fn set_non_volatile_values(&mut self, values: Vec<Variant>) {
+        for (slot, value) in (0..self.none_volatile_count).zip(values) {
+            self.global_table.insert(slot, value);
+        }
+    }
+
+    /// all slots in order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Variant)> {
+        (0..self.len() as u16).filter_map(|slot| self.global_table.get(&slot).map(|v| (slot, v)))
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots() {
+        let mut global = Global::new();
+        global.init_with(2, 1);
+        assert_eq!(global.len(), 3);
+        assert!(global.is_non_volatile(1));
+        assert!(!global.is_non_volatile(2));
+
+        global.write(2, Variant::Int(7)).unwrap();
+        assert_eq!(global.read(2).unwrap().as_int(), Some(7));
+        assert_eq!(global.get_int_var(0), 7);
+        assert!(global.write(3, Variant::Nil).is_err());
+        assert!(global.read(3).is_err());
+        assert_eq!(global.iter().count(), 3);
+    }
+}
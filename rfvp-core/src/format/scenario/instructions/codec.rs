@@ -0,0 +1,510 @@
+use anyhow::{bail, Result};
+
+use super::{inst::*, Opcode, OpcodeBase};
+use crate::format::scenario::{Nls, Scenario};
+
+/// A decoded instruction of the code area
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Nop(NopInst),
+    InitStack(InitStackInst),
+    Call(CallInst),
+    Syscall(SyscallInst),
+    Ret(RetInst),
+    RetV(RetValueInst),
+    Jmp(JmpInst),
+    Jz(JzInst),
+    PushNil(PushNilInst),
+    PushTrue(PushTrueInst),
+    PushI32(PushI32Inst),
+    PushI16(PushI16Inst),
+    PushI8(PushI8Inst),
+    PushF32(PushF32Inst),
+    PushString(PushStringInst),
+    PushGlobal(PushGlobalInst),
+    PushStack(PushStackInst),
+    PushGlobalTable(PushGlobalTableInst),
+    PushLocalTable(PushLocalTableInst),
+    PushTop(PushTopInst),
+    PushReturn(PushReturnInst),
+    PopGlobal(PopGlobalInst),
+    PopStack(PopStackInst),
+    PopGlobalTable(PopGlobalTableInst),
+    PopLocalTable(PopLocalTableInst),
+    Neg(NegInst),
+    Add(AddInst),
+    Sub(SubInst),
+    Mul(MulInst),
+    Div(DivInst),
+    Mod(ModInst),
+    BitTest(BitTestInst),
+    And(AndInst),
+    Or(OrInst),
+    SetE(SeteInst),
+    SetNE(SetneInst),
+    SetG(SetgInst),
+    SetLE(SetleInst),
+    SetL(SetlInst),
+    SetGE(SetgeInst),
+}
+
+/// call `$body` with the instruction struct of any variant
+macro_rules! with_inst {
+    ($inst:expr, $i:ident => $body:expr) => {
+        match $inst {
+            Inst::Nop($i) => $body,
+            Inst::InitStack($i) => $body,
+            Inst::Call($i) => $body,
+            Inst::Syscall($i) => $body,
+            Inst::Ret($i) => $body,
+            Inst::RetV($i) => $body,
+            Inst::Jmp($i) => $body,
+            Inst::Jz($i) => $body,
+            Inst::PushNil($i) => $body,
+            Inst::PushTrue($i) => $body,
+            Inst::PushI32($i) => $body,
+            Inst::PushI16($i) => $body,
+            Inst::PushI8($i) => $body,
+            Inst::PushF32($i) => $body,
+            Inst::PushString($i) => $body,
+            Inst::PushGlobal($i) => $body,
+            Inst::PushStack($i) => $body,
+            Inst::PushGlobalTable($i) => $body,
+            Inst::PushLocalTable($i) => $body,
+            Inst::PushTop($i) => $body,
+            Inst::PushReturn($i) => $body,
+            Inst::PopGlobal($i) => $body,
+            Inst::PopStack($i) => $body,
+            Inst::PopGlobalTable($i) => $body,
+            Inst::PopLocalTable($i) => $body,
+            Inst::Neg($i) => $body,
+            Inst::Add($i) => $body,
+            Inst::Sub($i) => $body,
+            Inst::Mul($i) => $body,
+            Inst::Div($i) => $body,
+            Inst::Mod($i) => $body,
+            Inst::BitTest($i) => $body,
+            Inst::And($i) => $body,
+            Inst::Or($i) => $body,
+            Inst::SetE($i) => $body,
+            Inst::SetNE($i) => $body,
+            Inst::SetG($i) => $body,
+            Inst::SetLE($i) => $body,
+            Inst::SetL($i) => $body,
+            Inst::SetGE($i) => $body,
+        }
+    };
+}
+
+impl OpcodeBase for Inst {
+    fn opcode(&self) -> Opcode {
+        with_inst!(self, inst => inst.opcode())
+    }
+
+    fn address(&self) -> u32 {
+        with_inst!(self, inst => inst.address())
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        with_inst!(self, inst => inst.mnemonic())
+    }
+
+    fn disassemble(&self) -> String {
+        with_inst!(self, inst => inst.disassemble())
+    }
+}
+
+impl Inst {
+    /// decode the instruction at `offset`
+    ///
+    /// Strings are decoded with the codepage of the scenario and syscall ids are resolved to
+    /// their names, an undeclared syscall is an error.
+    pub fn decode(scenario: &Scenario, offset: usize) -> Result<Self> {
+        let address = offset as u32;
+        let opcode = scenario.read_u8(offset)?;
+        let operand = offset + 1;
+
+        let inst = match Opcode::try_from(opcode as i32) {
+            Ok(Opcode::Nop) => Inst::Nop(NopInst::new(address)),
+            Ok(Opcode::InitStack) => {
+                let args_count = scenario.read_i8(operand)?;
+                let locals_count = scenario.read_i8(operand + 1)?;
+                Inst::InitStack(InitStackInst::new(
+                    address,
+                    args_count as u8,
+                    locals_count as u8,
+                ))
+            }
+            Ok(Opcode::Call) => Inst::Call(CallInst::new(address, scenario.read_u32(operand)?)),
+            Ok(Opcode::Syscall) => {
+                let id = scenario.read_u16(operand)?;
+                let Some(name) = scenario.get_syscall_name(id) else {
+                    bail!("syscall not found: {}", id);
+                };
+                Inst::Syscall(SyscallInst::new(address, id, name.to_string()))
+            }
+            Ok(Opcode::Ret) => Inst::Ret(RetInst::new(address)),
+            Ok(Opcode::RetV) => Inst::RetV(RetValueInst::new(address)),
+            Ok(Opcode::Jmp) => Inst::Jmp(JmpInst::new(address, scenario.read_u32(operand)?)),
+            Ok(Opcode::Jz) => Inst::Jz(JzInst::new(address, scenario.read_u32(operand)?)),
+            Ok(Opcode::PushNil) => Inst::PushNil(PushNilInst::new(address)),
+            Ok(Opcode::PushTrue) => Inst::PushTrue(PushTrueInst::new(address)),
+            Ok(Opcode::PushI32) => {
+                Inst::PushI32(PushI32Inst::new(address, scenario.read_i32(operand)?))
+            }
+            Ok(Opcode::PushI16) => {
+                Inst::PushI16(PushI16Inst::new(address, scenario.read_i16(operand)?))
+            }
+            Ok(Opcode::PushI8) => {
+                Inst::PushI8(PushI8Inst::new(address, scenario.read_i8(operand)?))
+            }
+            Ok(Opcode::PushF32) => {
+                Inst::PushF32(PushF32Inst::new(address, scenario.read_f32(operand)?))
+            }
+            Ok(Opcode::PushString) => {
+                let len = scenario.read_u8(operand)? as usize;
+                let value = scenario.read_cstring(operand + 1, len)?;
+                Inst::PushString(PushStringInst::new(address, value))
+            }
+            Ok(Opcode::PushGlobal) => {
+                let key = scenario.read_u16(operand)?;
+                Inst::PushGlobal(PushGlobalInst::new(address, key as u32))
+            }
+            Ok(Opcode::PushStack) => {
+                Inst::PushStack(PushStackInst::new(address, scenario.read_i8(operand)?))
+            }
+            Ok(Opcode::PushGlobalTable) => {
+                let key = scenario.read_u16(operand)?;
+                Inst::PushGlobalTable(PushGlobalTableInst::new(address, key as u32))
+            }
+            Ok(Opcode::PushLocalTable) => {
+                let idx = scenario.read_i8(operand)?;
+                Inst::PushLocalTable(PushLocalTableInst::new(address, idx))
+            }
+            Ok(Opcode::PushTop) => Inst::PushTop(PushTopInst::new(address)),
+            Ok(Opcode::PushReturn) => Inst::PushReturn(PushReturnInst::new(address)),
+            Ok(Opcode::PopGlobal) => {
+                let key = scenario.read_u16(operand)?;
+                Inst::PopGlobal(PopGlobalInst::new(address, key as u32))
+            }
+            Ok(Opcode::PopStack) => {
+                Inst::PopStack(PopStackInst::new(address, scenario.read_i8(operand)?))
+            }
+            Ok(Opcode::PopGlobalTable) => {
+                let key = scenario.read_u16(operand)?;
+                Inst::PopGlobalTable(PopGlobalTableInst::new(address, key as u32))
+            }
+            Ok(Opcode::PopLocalTable) => {
+                let idx = scenario.read_i8(operand)?;
+                Inst::PopLocalTable(PopLocalTableInst::new(address, idx))
+            }
+            Ok(Opcode::Neg) => Inst::Neg(NegInst::new(address)),
+            Ok(Opcode::Add) => Inst::Add(AddInst::new(address)),
+            Ok(Opcode::Sub) => Inst::Sub(SubInst::new(address)),
+            Ok(Opcode::Mul) => Inst::Mul(MulInst::new(address)),
+            Ok(Opcode::Div) => Inst::Div(DivInst::new(address)),
+            Ok(Opcode::Mod) => Inst::Mod(ModInst::new(address)),
+            Ok(Opcode::BitTest) => Inst::BitTest(BitTestInst::new(address)),
+            Ok(Opcode::And) => Inst::And(AndInst::new(address)),
+            Ok(Opcode::Or) => Inst::Or(OrInst::new(address)),
+            Ok(Opcode::SetE) => Inst::SetE(SeteInst::new(address)),
+            Ok(Opcode::SetNE) => Inst::SetNE(SetneInst::new(address)),
+            Ok(Opcode::SetG) => Inst::SetG(SetgInst::new(address)),
+            Ok(Opcode::SetLE) => Inst::SetLE(SetleInst::new(address)),
+            Ok(Opcode::SetL) => Inst::SetL(SetlInst::new(address)),
+            Ok(Opcode::SetGE) => Inst::SetGE(SetgeInst::new(address)),
+            Err(_) => bail!("unknown opcode: {}", opcode),
+        };
+
+        Ok(inst)
+    }
+
+    /// encode the instruction, strings are encoded with `nls`
+    pub fn encode(&self, nls: &Nls) -> Result<Vec<u8>> {
+        let mut bytes = vec![self.opcode() as u8];
+        match self {
+            Inst::InitStack(inst) => {
+                bytes.push(inst.get_arg_count());
+                bytes.push(inst.get_local_count());
+            }
+            Inst::Call(inst) => bytes.extend_from_slice(&inst.get_target().to_le_bytes()),
+            Inst::Syscall(inst) => bytes.extend_from_slice(&inst.get_id().to_le_bytes()),
+            Inst::Jmp(inst) => bytes.extend_from_slice(&inst.get_target().to_le_bytes()),
+            Inst::Jz(inst) => bytes.extend_from_slice(&inst.get_target().to_le_bytes()),
+            Inst::PushI32(inst) => bytes.extend_from_slice(&inst.get_value().to_le_bytes()),
+            Inst::PushI16(inst) => bytes.extend_from_slice(&inst.get_value().to_le_bytes()),
+            Inst::PushI8(inst) => bytes.extend_from_slice(&inst.get_value().to_le_bytes()),
+            Inst::PushF32(inst) => bytes.extend_from_slice(&inst.get_value().to_le_bytes()),
+            Inst::PushString(inst) => {
+                let mut blob = nls.encode(inst.get_value())?.into_owned();
+                blob.push(0);
+                if blob.len() > u8::MAX as usize {
+                    bail!("push_string: string is too long: {}", inst.get_value());
+                }
+                bytes.push(blob.len() as u8);
+                bytes.extend_from_slice(&blob);
+            }
+            Inst::PushGlobal(inst) => bytes.extend_from_slice(&global_key(self, inst.get_idx())?),
+            Inst::PushStack(inst) => bytes.extend_from_slice(&inst.get_idx().to_le_bytes()),
+            Inst::PushGlobalTable(inst) => {
+                bytes.extend_from_slice(&global_key(self, inst.get_idx())?)
+            }
+            Inst::PushLocalTable(inst) => bytes.extend_from_slice(&inst.get_idx().to_le_bytes()),
+            Inst::PopGlobal(inst) => bytes.extend_from_slice(&global_key(self, inst.get_idx())?),
+            Inst::PopStack(inst) => bytes.extend_from_slice(&inst.get_idx().to_le_bytes()),
+            Inst::PopGlobalTable(inst) => {
+                bytes.extend_from_slice(&global_key(self, inst.get_idx())?)
+            }
+            Inst::PopLocalTable(inst) => bytes.extend_from_slice(&inst.get_idx().to_le_bytes()),
+            _ => {}
+        }
+
+        Ok(bytes)
+    }
+
+    /// the jump or call target
+    pub fn target(&self) -> Option<u32> {
+        match self {
+            Inst::Call(inst) => Some(inst.get_target()),
+            Inst::Jmp(inst) => Some(inst.get_target()),
+            Inst::Jz(inst) => Some(inst.get_target()),
+            _ => None,
+        }
+    }
+
+    /// change the jump or call target, other instructions are left untouched
+    pub fn set_target(&mut self, target: u32) {
+        match self {
+            Inst::Call(inst) => inst.set_target(target),
+            Inst::Jmp(inst) => inst.set_target(target),
+            Inst::Jz(inst) => inst.set_target(target),
+            _ => {}
+        }
+    }
+}
+
+fn global_key(inst: &Inst, key: u32) -> Result<[u8; 2]> {
+    match u16::try_from(key) {
+        Ok(key) => Ok(key.to_le_bytes()),
+        Err(_) => bail!("{}: global key out of range: {}", inst.mnemonic(), key),
+    }
+}
+
+/// Iterator over the instructions of the code area, see [`Scenario::instructions`]
+pub struct Instructions<'a> {
+    scenario: &'a Scenario,
+    offset: usize,
+    end: usize,
+}
+
+impl Instructions<'_> {
+    /// offset of the next instruction
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<(u32, Inst)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.end {
+            return None;
+        }
+
+        let address = self.offset;
+        let result = Inst::decode(self.scenario, address).and_then(|inst| {
+            let size = inst.encoded_len(self.scenario, address)?;
+            if address + size > self.end {
+                bail!("truncated instruction");
+            }
+            Ok((inst, size))
+        });
+
+        match result {
+            Ok((inst, size)) => {
+                self.offset += size;
+                Some(Ok((address as u32, inst)))
+            }
+            Err(e) => {
+                // an instruction that can be skipped is reported and decoding goes on with
+                // the next one, anything else ends the code area
+                self.offset = match skip_len(self.scenario, address) {
+                    Some(len) if address + len <= self.end => address + len,
+                    _ => self.end,
+                };
+                Some(Err(e.context(format!(
+                    "invalid instruction at 0x{:08x}",
+                    address
+                ))))
+            }
+        }
+    }
+}
+
+impl Inst {
+    /// size of the instruction in the scenario, strings may not round-trip through the codepage
+    fn encoded_len(&self, scenario: &Scenario, offset: usize) -> Result<usize> {
+        let len = match self {
+            Inst::PushString(_) => 2 + scenario.read_u8(offset + 1)? as usize,
+            _ => operand_len(self.opcode()) + 1,
+        };
+        Ok(len)
+    }
+}
+
+/// size of the operands of everything but strings
+fn operand_len(opcode: Opcode) -> usize {
+    match opcode {
+        Opcode::InitStack => 2,
+        Opcode::Call | Opcode::Jmp | Opcode::Jz => 4,
+        Opcode::PushI32 | Opcode::PushF32 => 4,
+        Opcode::Syscall | Opcode::PushI16 => 2,
+        Opcode::PushGlobal | Opcode::PushGlobalTable => 2,
+        Opcode::PopGlobal | Opcode::PopGlobalTable => 2,
+        Opcode::PushI8 | Opcode::PushStack | Opcode::PushLocalTable => 1,
+        Opcode::PopStack | Opcode::PopLocalTable => 1,
+        Opcode::PushString => 1,
+        _ => 0,
+    }
+}
+
+/// how many bytes an undecodable instruction takes, unknown opcodes are a single byte
+fn skip_len(scenario: &Scenario, offset: usize) -> Option<usize> {
+    let opcode = scenario.read_u8(offset).ok()?;
+    match Opcode::try_from(opcode as i32) {
+        Ok(Opcode::PushString) => Some(2 + scenario.read_u8(offset + 1).ok()? as usize),
+        Ok(opcode) => Some(1 + operand_len(opcode)),
+        Err(_) => Some(1),
+    }
+}
+
+impl Scenario {
+    /// decode the code area, from the first instruction up to the system description
+    ///
+    /// An invalid instruction is reported as an error, decoding goes on after it unless the
+    /// code area is truncated.
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions {
+            scenario: self,
+            offset: 4,
+            end: (self.get_sys_desc_offset() as usize).min(self.raw().len()),
+        }
+    }
+
+    /// the undecoded bytes of the string literals in the code area
+    pub(crate) fn string_literals(&self) -> Vec<&[u8]> {
+        let end = (self.get_sys_desc_offset() as usize).min(self.raw().len());
+        let mut literals = Vec::new();
+        let mut offset = 4;
+        while let Some(len) = skip_len(self, offset).filter(|len| offset + len <= end) {
+            if self.raw()[offset] == Opcode::PushString as u8 {
+                if let Ok(literal) = self.read_cbytes(offset + 2, len - 2) {
+                    literals.push(literal);
+                }
+            }
+            offset += len;
+        }
+        literals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::scenario::ScenarioBuilder;
+
+    fn make_scenario(code: &[u8], nls: Nls) -> Scenario {
+        ScenarioBuilder::new(nls)
+            .code(code)
+            .non_volatile_global_count(1)
+            .volatile_global_count(1)
+            .title("test")
+            .syscall(1, "Rand")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let code: &[u8] = &[
+            0x01, 0x02, 0x01, // init_stack 2 1
+            0x00, // nop
+            0x02, 0x04, 0x00, 0x00, 0x00, // call 0x04
+            0x03, 0x00, 0x00, // syscall Rand
+            0x04, 0x05, // ret, retv
+            0x06, 0x04, 0x00, 0x00, 0x00, // jmp 0x04
+            0x07, 0x0E, 0x00, 0x00, 0x00, // jz 0x0e
+            0x08, 0x09, // push_nil, push_true
+            0x0A, 0x78, 0x56, 0x34, 0x12, // push_i32
+            0x0B, 0xFE, 0xFF, // push_i16 -2
+            0x0C, 0x80, // push_i8 -128
+            0x0D, 0x00, 0x00, 0xC0, 0x3F, // push_f32 1.5
+            0x0E, 0x05, 0x82, 0xA0, b'a', b'b', 0x00, // push_string "あab"
+            0x0F, 0x01, 0x00, // push_global 1
+            0x10, 0xFE, // push_stack -2
+            0x11, 0x02, 0x00, // push_global_table 2
+            0x12, 0x00, // push_local_table 0
+            0x13, 0x14, // push_top, push_return
+            0x15, 0x03, 0x00, // pop_global 3
+            0x16, 0x00, // pop_stack 0
+            0x17, 0x04, 0x00, // pop_global_table 4
+            0x18, 0x01, // pop_local_table 1
+            0x19, 0x1A, 0x1B, 0x1C, 0x1D, 0x1E, 0x1F, 0x20, 0x21, // neg .. or
+            0x22, 0x23, 0x24, 0x25, 0x26, 0x27, // set_e .. set_ge
+        ];
+        let scenario = make_scenario(code, Nls::ShiftJIS);
+
+        let insts = scenario.instructions().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(insts.len(), 40);
+        assert_eq!(
+            insts[3],
+            (
+                0x0D,
+                Inst::Syscall(SyscallInst::new(0x0D, 0, "Rand".to_string()))
+            )
+        );
+        assert_eq!(
+            insts[14],
+            (
+                0x2D,
+                Inst::PushString(PushStringInst::new(0x2D, "あab".to_string()))
+            )
+        );
+
+        let mut encoded = Vec::new();
+        for (address, inst) in &insts {
+            assert_eq!(inst.address(), *address);
+            assert_eq!(address - 4, encoded.len() as u32);
+            encoded.extend(inst.encode(&Nls::ShiftJIS).unwrap());
+        }
+        assert_eq!(encoded, code);
+    }
+
+    #[test]
+    fn invalid_instructions() {
+        let code: &[u8] = &[
+            0xFF, // unknown
+            0x03, 0x01, 0x00, // syscall 1, not declared
+            0x00, // nop
+            0x0A, 0x01, // truncated push_i32
+        ];
+        let scenario = make_scenario(code, Nls::UTF8);
+
+        let mut iter = scenario.instructions();
+        assert!(iter.next().unwrap().is_err());
+        assert_eq!(iter.offset(), 5);
+        assert!(iter.next().unwrap().is_err());
+        assert_eq!(iter.offset(), 8);
+        assert!(matches!(iter.next(), Some(Ok((8, Inst::Nop(_))))));
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+
+        let mut inst = Inst::PushGlobal(PushGlobalInst::new(0, 0x10000));
+        assert!(inst.encode(&Nls::UTF8).is_err());
+        inst = Inst::Jz(JzInst::new(0, 0x10));
+        inst.set_target(0x20);
+        assert_eq!(inst.target(), Some(0x20));
+        assert_eq!(inst.encode(&Nls::UTF8).unwrap(), [0x07, 0x20, 0, 0, 0]);
+    }
+}
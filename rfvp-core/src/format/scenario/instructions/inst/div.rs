@@ -1,6 +1,7 @@
 use crate::format::scenario::instructions::OpcodeBase;
 use crate::format::scenario::instructions::Opcode;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct DivInst {
     address: u32,
 }
@@ -1,6 +1,7 @@
 use crate::format::scenario::instructions::OpcodeBase;
 use crate::format::scenario::instructions::Opcode;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct JmpInst {
     address: u32,
     target: u32,
@@ -17,6 +18,10 @@ impl JmpInst {
     pub fn get_target(&self) -> u32 {
         self.target
     }
+
+    pub fn set_target(&mut self, target: u32) {
+        self.target = target;
+    }
 }
 
 impl OpcodeBase for JmpInst {
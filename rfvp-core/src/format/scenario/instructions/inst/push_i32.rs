@@ -1,6 +1,7 @@
 use crate::format::scenario::instructions::OpcodeBase;
 use crate::format::scenario::instructions::Opcode;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct PushI32Inst {
     address: u32,
     value: i32,
@@ -17,6 +18,10 @@ impl PushI32Inst {
     pub fn get_value(&self) -> i32 {
         self.value
     }
+
+    pub fn set_value(&mut self, value: i32) {
+        self.value = value;
+    }
 }
 
 impl OpcodeBase for PushI32Inst {
@@ -2,6 +2,7 @@ use crate::format::scenario::instructions::OpcodeBase;
 use crate::format::scenario::instructions::Opcode;
 
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct PushTopInst {
     address: u32,
 }
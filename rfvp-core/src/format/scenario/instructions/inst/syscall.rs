@@ -1,20 +1,27 @@
 use crate::format::scenario::instructions::OpcodeBase;
 use crate::format::scenario::instructions::Opcode;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct SyscallInst {
     address: u32,
+    id: u16,
     syscall_name: String,
 }
 
 
 impl SyscallInst {
-    pub fn new(address: u32, syscall_name: String) -> Self {
+    pub fn new(address: u32, id: u16, syscall_name: String) -> Self {
         Self {
             address,
+            id,
             syscall_name,
         }
     }
 
+    pub fn get_id(&self) -> u16 {
+        self.id
+    }
+
     pub fn get_syscall_name(&self) -> &String {
         &self.syscall_name
     }
@@ -1,6 +1,9 @@
 pub mod inst;
+mod codec;
 
+pub use codec::{Inst, Instructions};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     Nop = 0,
     InitStack = 1,
@@ -1,24 +1,66 @@
+pub mod builder;
 pub mod context;
+pub mod detect;
 pub mod instructions;
 pub mod global;
 pub mod variant;
+pub mod verify;
 
-use std::{collections::HashMap, io::Cursor, str::FromStr};
+use std::{borrow::Cow, collections::HashMap, fmt, io::Cursor, str::FromStr};
 
 use anyhow::{bail, Result};
 use binrw::{BinRead, BinWrite};
 use bytes::Bytes;
 
+pub use builder::ScenarioBuilder;
 
-#[derive(Debug, Clone, Default)]
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum Nls {
     #[default]
     ShiftJIS = 0,
     GBK = 1,
     UTF8 = 2,
+    /// Traditional Chinese
+    Big5 = 3,
+    /// Korean, with the UHC extension
+    EUCKR = 4,
+    /// Western European
+    Windows1252 = 5,
+    /// guessed from the strings when the scenario or the VFS is opened
+    Auto = 6,
 }
 
 
+impl Nls {
+    /// the codepage of the strings, Shift-JIS while `Auto` is not resolved
+    pub fn encoding(&self) -> &'static encoding_rs::Encoding {
+        match self {
+            Nls::ShiftJIS | Nls::Auto => encoding_rs::SHIFT_JIS,
+            Nls::GBK => encoding_rs::GBK,
+            Nls::UTF8 => encoding_rs::UTF_8,
+            Nls::Big5 => encoding_rs::BIG5,
+            Nls::EUCKR => encoding_rs::EUC_KR,
+            Nls::Windows1252 => encoding_rs::WINDOWS_1252,
+        }
+    }
+
+    /// decode a string, malformed sequences are replaced and reported by the flag
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> (Cow<'a, str>, bool) {
+        let (s, _, malformed) = self.encoding().decode(bytes);
+        (s, malformed)
+    }
+
+    /// encode a string, a character missing from the codepage is an error
+    pub fn encode<'a>(&self, s: &'a str) -> Result<Cow<'a, [u8]>> {
+        let (bytes, _, unmappable) = self.encoding().encode(s);
+        if unmappable {
+            bail!("string is not representable as {}: {}", self, s);
+        }
+        Ok(bytes)
+    }
+}
+
 impl FromStr for Nls {
     type Err = anyhow::Error;
 
@@ -27,11 +69,30 @@ impl FromStr for Nls {
             "sjis" => Ok(Nls::ShiftJIS),
             "gbk" => Ok(Nls::GBK),
             "utf8" => Ok(Nls::UTF8),
+            "big5" => Ok(Nls::Big5),
+            "euckr" | "uhc" | "cp949" => Ok(Nls::EUCKR),
+            "cp1252" | "windows1252" => Ok(Nls::Windows1252),
+            "auto" => Ok(Nls::Auto),
             _ => Err(anyhow::anyhow!("unknown NLS")),
         }
     }
 }
 
+impl fmt::Display for Nls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Nls::ShiftJIS => "sjis",
+            Nls::GBK => "gbk",
+            Nls::UTF8 => "utf8",
+            Nls::Big5 => "big5",
+            Nls::EUCKR => "euckr",
+            Nls::Windows1252 => "cp1252",
+            Nls::Auto => "auto",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Syscall {
     /// how many arguments the syscall takes from the stack
@@ -40,6 +101,19 @@ pub struct Syscall {
     pub name: String,
 }
 
+/// A script function registered as a syscall
+///
+/// Custom syscalls take the ids following the syscall table, the syscall instruction calls the
+/// function instead of the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomSyscall {
+    /// address of the function
+    pub address: u32,
+    /// how many arguments the function takes from the stack
+    pub args: u8,
+    pub name: String,
+}
+
 #[allow(unused)]
 #[derive(Debug, Clone)]
 pub struct Scenario {
@@ -50,20 +124,41 @@ pub struct Scenario {
     pub entry_point: u32,
     pub non_volatile_global_count: u16,
     pub volatile_global_count: u16,
-    // register a script function as syscall, never use?
+    /// number of script functions registered as syscalls
     pub custom_syscall_count: u16,
     /// Game resolution for the window mode
     game_mode: u16,
     game_title: String,
     pub syscall_count: u16,
     pub syscalls: HashMap<usize, Syscall>,
+    pub custom_syscalls: Vec<CustomSyscall>,
 }
 
 impl Scenario {
+    /// parse a scenario, `Nls::Auto` picks the codepage the strings decode best with
     pub fn new(data: Bytes, nls: Option<Nls>) -> Result<Self> {
-        let mut scenario = Scenario {
+        let mut nls = nls.unwrap_or(Nls::ShiftJIS);
+        if let Nls::Auto = nls {
+            let detection = Self::detect_nls(&data)?;
+            if detection.confidence < 0.5 {
+                log::warn!("text encoding detected as {}", detection);
+            } else {
+                log::info!("text encoding detected as {}", detection);
+            }
+            nls = detection.nls;
+        }
+
+        let mut scenario = Self::empty(data, nls);
+        scenario.parser()?;
+
+        Ok(scenario)
+    }
+
+    /// the raw data without the parsed header
+    fn empty(data: Bytes, nls: Nls) -> Self {
+        Scenario {
             raw_data: data,
-            nls: nls.unwrap_or(Nls::ShiftJIS),
+            nls,
             sys_desc_offset: 0,
             entry_point: 0,
             non_volatile_global_count: 0,
@@ -73,11 +168,8 @@ impl Scenario {
             game_title: String::new(),
             syscall_count: 0,
             syscalls: HashMap::new(),
-        };
-
-        scenario.parser()?;
-
-        Ok(scenario)
+            custom_syscalls: Vec::new(),
+        }
     }
 
     #[inline]
@@ -156,48 +248,26 @@ impl Scenario {
         ]))
     }
 
+    /// safely read the bytes of a c-style string, up to the null terminator
+    pub fn read_cbytes(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        if offset + len > self.raw().len() {
+            return Err(anyhow::anyhow!("offset out of bounds"));
+        }
+        let bytes = &self.raw()[offset..offset + len];
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(len);
+        Ok(&bytes[..end])
+    }
+
     /// safe read a c-style string from the buffer with string length
     /// (with null terminator)
     /// then convert it to a UTF-8 string due to the NLS
     pub fn read_cstring(&self, offset: usize, len: usize) -> Result<String> {
-        if offset + len >= self.raw().len() {
-            return Err(anyhow::anyhow!("offset out of bounds"));
-        }
-        let mut string = Vec::new();
-        for i in 0..len {
-            if self.raw()[offset + i] == 0 {
-                break;
-            }
-            string.push(self.raw()[offset + i]);
-        }
+        let string = self.read_cbytes(offset, len)?;
 
-        if string.ends_with(&[0]) {
-            string.pop();
+        let (s, malformed) = self.nls.decode(string);
+        if malformed {
+            log::error!("failed to decode string as {}", self.nls);
         }
-        
-        let s = match self.nls {
-            Nls::ShiftJIS => {
-                let (s, _, e) = encoding_rs::SHIFT_JIS.decode(&string);
-                if e {
-                    log::error!("failed to decode string as ShiftJIS");
-                }
-                s
-            }
-            Nls::GBK => {
-                let (s, _, e) = encoding_rs::GBK.decode(&string);
-                if e {
-                    log::error!("failed to decode string as GBK");
-                }
-                s
-            }
-            Nls::UTF8 => {
-                let (s, _, e) = encoding_rs::UTF_8.decode(&string);
-                if e {
-                    log::error!("failed to decode string as UTF-8");
-                }
-                s
-            }
-        };
 
         Ok(s.to_string())
     }
@@ -242,15 +312,45 @@ impl Scenario {
         }
 
         self.custom_syscall_count = self.read_u16(off)?;
-        if self.custom_syscall_count > 0 {
-            log::warn!("custom syscall count: {}", self.custom_syscall_count);
+        off += size_of::<u16>();
+
+        for _ in 0..self.custom_syscall_count {
+            let address = self.read_u32(off)?;
+            off += size_of::<u32>();
+
+            let args = self.read_u8(off)?;
+            off += size_of::<u8>();
+
+            let name_len = self.read_u8(off)?;
+            off += size_of::<u8>();
+
+            let name = self.read_cstring(off, name_len as usize)?;
+            off += name_len as usize;
+
+            self.custom_syscalls.push(CustomSyscall {
+                address,
+                args,
+                name,
+            });
         }
 
         Ok(())
     }
 
+    /// the name of a syscall or a custom syscall
     pub fn get_syscall_name(&self, id: u16) -> Option<&str> {
-        self.syscalls.get(&(id as usize)).map(|s| s.name.as_str())
+        match self.get_syscall(id) {
+            Some(syscall) => Some(syscall.name.as_str()),
+            None => self.get_custom_syscall(id).map(|s| s.name.as_str()),
+        }
+    }
+
+    /// the argument count of a syscall or a custom syscall
+    pub fn get_syscall_args(&self, id: u16) -> Option<u8> {
+        match self.get_syscall(id) {
+            Some(syscall) => Some(syscall.args),
+            None => self.get_custom_syscall(id).map(|s| s.args),
+        }
     }
 
     pub fn is_code_area(&self, addr: u32) -> bool {
@@ -314,6 +414,16 @@ impl Scenario {
         self.custom_syscall_count
     }
 
+    /// the custom syscall with the given syscall id
+    pub fn get_custom_syscall(&self, id: u16) -> Option<&CustomSyscall> {
+        let index = id.checked_sub(self.syscall_count)?;
+        self.custom_syscalls.get(index as usize)
+    }
+
+    pub fn get_custom_syscalls(&self) -> &[CustomSyscall] {
+        &self.custom_syscalls
+    }
+
     // the upper bound of the code area
     pub fn get_sys_desc_offset(&self) -> u32 {
         self.sys_desc_offset
@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 use twofloat::TwoFloat;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -9,12 +9,17 @@ pub(crate) struct SavedStackInfo {
     pub stack_pos: usize,
     pub return_addr: usize,
     pub args: usize,
+    /// the instruction that made the call, a `call` or a custom syscall
+    pub call_addr: usize,
+    /// the routine that was called
+    pub function: u32,
 }
 
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Table {
-    table: HashMap<u32, Variant>,
+    /// ordered, so the same table always serializes to the same bytes
+    table: BTreeMap<u32, Variant>,
     count: u32,
     next_index: u32,
 }
@@ -22,7 +27,7 @@ pub struct Table {
 impl Table {
     pub fn new() -> Self {
         Table {
-            table: HashMap::new(),
+            table: BTreeMap::new(),
             count: 0,
             next_index: 0,
         }
@@ -44,6 +49,11 @@ impl Table {
     pub fn get(&self, key: u32) -> Option<&Variant> {
         self.table.get(&key)
     }
+
+    /// the entries of the table, ordered by key
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &Variant)> {
+        self.table.iter().map(|(k, v)| (*k, v))
+    }
 }
 
 /// Represents a value that can be stored in the VM
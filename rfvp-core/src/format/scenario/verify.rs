@@ -0,0 +1,534 @@
+//! Static checks over the code area of a scenario.
+//!
+//! Nothing is executed, the instructions are decoded once and every function (the code from an
+//! init_stack instruction to the next one) is walked along its jumps to track the height of the
+//! operand stack. Patched or reassembled scenarios are expected to come out clean, scenarios
+//! shipped with the games may still trigger a few warnings.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use super::{
+    instructions::{Inst, Opcode, OpcodeBase},
+    Scenario,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// suspicious, the VM copes with it
+    Warning,
+    /// the script fails or misbehaves when it gets there
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// the instruction the diagnostic is about
+    pub address: u32,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at 0x{:08x}: {}",
+            self.severity, self.address, self.message
+        )
+    }
+}
+
+/// Check the scenario, the diagnostics are sorted by address
+pub fn verify(scenario: &Scenario) -> Vec<Diagnostic> {
+    let mut verifier = Verifier::new(scenario);
+    verifier.decode();
+    verifier.check_instructions();
+    verifier.check_custom_syscalls();
+    verifier.check_functions();
+
+    let mut diagnostics = verifier.diagnostics;
+    diagnostics.sort_by_key(|d| d.address);
+    diagnostics
+}
+
+/// A function, the instructions `start..end`
+struct Function {
+    start: usize,
+    end: usize,
+    args: i32,
+    locals: i32,
+}
+
+struct Verifier<'a> {
+    scenario: &'a Scenario,
+    insts: Vec<Inst>,
+    /// address to instruction index
+    index: BTreeMap<u32, usize>,
+    functions: Vec<Function>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(scenario: &'a Scenario) -> Self {
+        Self {
+            scenario,
+            insts: Vec::new(),
+            index: BTreeMap::new(),
+            functions: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, address: u32, severity: Severity, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            address,
+            severity,
+            message: message.into(),
+        });
+    }
+
+    fn decode(&mut self) {
+        let scenario = self.scenario;
+        let mut iter = scenario.instructions();
+        loop {
+            let offset = iter.offset();
+            match iter.next() {
+                None => break,
+                Some(Ok((address, inst))) => {
+                    self.index.insert(address, self.insts.len());
+                    self.insts.push(inst);
+                }
+                Some(Err(e)) => {
+                    let message = match scenario.read_u8(offset).map(|b| Opcode::try_from(b as i32))
+                    {
+                        Ok(Ok(Opcode::Syscall)) => {
+                            let id = scenario.read_u16(offset + 1).unwrap_or_default();
+                            format!(
+                                "syscall id {} is out of range, {} syscalls are declared",
+                                id,
+                                scenario.get_all_syscalls().len()
+                                    + scenario.get_custom_syscalls().len()
+                            )
+                        }
+                        _ => format!("{:#}", e),
+                    };
+                    self.report(offset as u32, Severity::Error, message);
+                }
+            }
+        }
+
+        let mut functions: Vec<Function> = Vec::new();
+        for (i, inst) in self.insts.iter().enumerate() {
+            if let Inst::InitStack(init) = inst {
+                if let Some(last) = functions.last_mut() {
+                    last.end = i;
+                }
+                functions.push(Function {
+                    start: i,
+                    end: self.insts.len(),
+                    args: init.get_arg_count() as i8 as i32,
+                    locals: init.get_local_count() as i8 as i32,
+                });
+            }
+        }
+        self.functions = functions;
+
+        let first = self.functions.first().map_or(self.insts.len(), |f| f.start);
+        if first > 0 {
+            let address = self.insts[0].address();
+            self.report(address, Severity::Warning, "code outside of any function");
+        }
+    }
+
+    /// operands that can be checked one instruction at a time
+    fn check_instructions(&mut self) {
+        let scenario = self.scenario;
+        let mut diagnostics = Vec::new();
+
+        for inst in &self.insts {
+            let address = inst.address();
+            if let Some(target) = inst.target() {
+                let message = if !scenario.is_code_area(target) {
+                    Some(format!(
+                        "target 0x{:08x} is outside of the code area",
+                        target
+                    ))
+                } else {
+                    match self.index.get(&target).map(|i| &self.insts[*i]) {
+                        None => Some(format!(
+                            "target 0x{:08x} is not on an instruction boundary",
+                            target
+                        )),
+                        Some(Inst::InitStack(_)) => None,
+                        Some(_) if inst.opcode() == Opcode::Call => Some(format!(
+                            "call target 0x{:08x} does not start with init_stack",
+                            target
+                        )),
+                        Some(_) => None,
+                    }
+                };
+                if let Some(message) = message {
+                    diagnostics.push((address, Severity::Error, message));
+                }
+            }
+
+            if let Inst::PushString(push) = inst {
+                if !self.decodes(address) {
+                    let message = format!(
+                        "string {:?} is not valid {}",
+                        push.get_value(),
+                        scenario.nls.encoding().name()
+                    );
+                    diagnostics.push((address, Severity::Warning, message));
+                }
+            }
+        }
+
+        for (address, severity, message) in diagnostics {
+            self.report(address, severity, message);
+        }
+    }
+
+    /// custom syscalls are called like functions, their address has to start one
+    fn check_custom_syscalls(&mut self) {
+        let scenario = self.scenario;
+        for syscall in scenario.get_custom_syscalls() {
+            match self.index.get(&syscall.address).map(|i| &self.insts[*i]) {
+                Some(Inst::InitStack(init)) => {
+                    if init.get_arg_count() != syscall.args {
+                        let message = format!(
+                            "custom syscall {} is declared with {} arguments, the function takes {}",
+                            syscall.name,
+                            syscall.args,
+                            init.get_arg_count()
+                        );
+                        self.report(syscall.address, Severity::Warning, message);
+                    }
+                }
+                _ => {
+                    let message = format!(
+                        "custom syscall {} does not point at a function",
+                        syscall.name
+                    );
+                    self.report(syscall.address, Severity::Error, message);
+                }
+            }
+        }
+    }
+
+    /// whether the string pushed at `address` decodes without replacement characters
+    fn decodes(&self, address: u32) -> bool {
+        let offset = address as usize + 1;
+        let Ok(len) = self.scenario.read_u8(offset) else {
+            return false;
+        };
+        let Some(bytes) = self
+            .scenario
+            .raw()
+            .get(offset + 1..offset + 1 + len as usize)
+        else {
+            return false;
+        };
+        let bytes = match bytes.iter().position(|b| *b == 0) {
+            Some(nul) => &bytes[..nul],
+            None => bytes,
+        };
+
+        self.scenario
+            .nls
+            .encoding()
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .is_some()
+    }
+
+    fn check_functions(&mut self) {
+        let mut called = BTreeSet::new();
+        called.insert(self.scenario.get_entry_point());
+        for syscall in self.scenario.get_custom_syscalls() {
+            called.insert(syscall.address);
+        }
+        for inst in &self.insts {
+            match inst {
+                Inst::Call(call) => {
+                    called.insert(call.get_target());
+                }
+                // threads are started from a function address pushed as an integer
+                Inst::PushI32(push) => {
+                    called.insert(push.get_value() as u32);
+                }
+                _ => {}
+            }
+        }
+
+        for i in 0..self.functions.len() {
+            let start = self.insts[self.functions[i].start].address();
+            if !called.contains(&start) {
+                self.report(start, Severity::Warning, "function is never called");
+            }
+            self.check_function(i);
+        }
+    }
+
+    fn check_function(&mut self, function: usize) {
+        let Function {
+            start,
+            end,
+            args,
+            locals,
+        } = self.functions[function];
+        let mut diagnostics = Vec::new();
+
+        // the operand stack height before each instruction, above the locals
+        let mut heights: Vec<Option<i32>> = vec![None; end - start];
+        let mut pending = vec![(start, 0)];
+        let mut mismatched = BTreeSet::new();
+
+        while let Some((i, height)) = pending.pop() {
+            match heights[i - start] {
+                Some(known) => {
+                    if known != height && mismatched.insert(i) {
+                        let message = format!(
+                            "stack height is {} on one path and {} on another",
+                            known, height
+                        );
+                        diagnostics.push((self.insts[i].address(), Severity::Warning, message));
+                    }
+                    continue;
+                }
+                None => heights[i - start] = Some(height),
+            }
+
+            let inst = &self.insts[i];
+            let address = inst.address();
+
+            if let Some(offset) = local_offset(inst) {
+                let offset = offset as i32;
+                if !((-args - 1..=-2).contains(&offset) || (0..locals).contains(&offset)) {
+                    let message = format!(
+                        "stack offset {} is outside of the {} arguments and {} locals",
+                        offset, args, locals
+                    );
+                    diagnostics.push((address, Severity::Error, message));
+                }
+            }
+
+            let (pops, pushes) = self.stack_effect(inst);
+            if height < pops {
+                let message = format!(
+                    "{} pops {} values but the stack only holds {}",
+                    inst.opcode().to_string(),
+                    pops,
+                    height
+                );
+                diagnostics.push((address, Severity::Error, message));
+            }
+            let next = (height - pops).max(0) + pushes;
+
+            match inst {
+                Inst::Ret(_) | Inst::RetV(_) => {
+                    let expected = if let Inst::RetV(_) = inst { 1 } else { 0 };
+                    if height != expected {
+                        let message = format!(
+                            "{} with a stack height of {}, expected {}",
+                            inst.opcode().to_string(),
+                            height,
+                            expected
+                        );
+                        diagnostics.push((address, Severity::Warning, message));
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            if let Some(target) = inst.target().filter(|_| inst.opcode() != Opcode::Call) {
+                match self.index.get(&target) {
+                    Some(&j) if (start..end).contains(&j) => pending.push((j, next)),
+                    Some(_) => {
+                        let message = format!("jump to 0x{:08x} leaves the function", target);
+                        diagnostics.push((address, Severity::Warning, message));
+                    }
+                    // reported with the other targets
+                    None => {}
+                }
+            }
+
+            if let Inst::Jmp(_) = inst {
+                continue;
+            }
+            if i + 1 < end {
+                pending.push((i + 1, next));
+            } else {
+                let message = "control reaches the end of the function";
+                diagnostics.push((address, Severity::Warning, message.to_string()));
+            }
+        }
+
+        // trailing ret and nop emitted after the last jump or return are not worth a warning
+        let mut i = start;
+        while i < end {
+            if heights[i - start].is_some() {
+                i += 1;
+                continue;
+            }
+            let first = i;
+            let mut only_padding = true;
+            while i < end && heights[i - start].is_none() {
+                only_padding &=
+                    matches!(self.insts[i], Inst::Ret(_) | Inst::RetV(_) | Inst::Nop(_));
+                i += 1;
+            }
+            if !only_padding {
+                let message = "unreachable code";
+                diagnostics.push((
+                    self.insts[first].address(),
+                    Severity::Warning,
+                    message.to_string(),
+                ));
+            }
+        }
+
+        for (address, severity, message) in diagnostics {
+            self.report(address, severity, message);
+        }
+    }
+
+    /// how many values the instruction pops from and pushes onto the operand stack
+    fn stack_effect(&self, inst: &Inst) -> (i32, i32) {
+        match inst {
+            Inst::Nop(_) | Inst::InitStack(_) | Inst::Ret(_) | Inst::Jmp(_) => (0, 0),
+            Inst::Call(call) => {
+                let args = self
+                    .index
+                    .get(&call.get_target())
+                    .and_then(|i| match &self.insts[*i] {
+                        Inst::InitStack(init) => Some(init.get_arg_count() as i8 as i32),
+                        _ => None,
+                    })
+                    .unwrap_or(0);
+                (args, 0)
+            }
+            Inst::Syscall(syscall) => {
+                let args = self
+                    .scenario
+                    .get_syscall_args(syscall.get_id())
+                    .map_or(0, |args| args as i32);
+                (args, 0)
+            }
+            Inst::RetV(_) | Inst::Jz(_) => (1, 0),
+            Inst::PushNil(_)
+            | Inst::PushTrue(_)
+            | Inst::PushI32(_)
+            | Inst::PushI16(_)
+            | Inst::PushI8(_)
+            | Inst::PushF32(_)
+            | Inst::PushString(_)
+            | Inst::PushGlobal(_)
+            | Inst::PushStack(_)
+            | Inst::PushReturn(_) => (0, 1),
+            Inst::PushGlobalTable(_) | Inst::PushLocalTable(_) | Inst::Neg(_) => (1, 1),
+            Inst::PushTop(_) => (1, 2),
+            Inst::PopGlobal(_) | Inst::PopStack(_) => (1, 0),
+            Inst::PopGlobalTable(_) | Inst::PopLocalTable(_) => (2, 0),
+            Inst::Add(_)
+            | Inst::Sub(_)
+            | Inst::Mul(_)
+            | Inst::Div(_)
+            | Inst::Mod(_)
+            | Inst::BitTest(_)
+            | Inst::And(_)
+            | Inst::Or(_)
+            | Inst::SetE(_)
+            | Inst::SetNE(_)
+            | Inst::SetG(_)
+            | Inst::SetLE(_)
+            | Inst::SetL(_)
+            | Inst::SetGE(_) => (2, 1),
+        }
+    }
+}
+
+/// the frame offset of the instructions addressing arguments and locals
+fn local_offset(inst: &Inst) -> Option<i8> {
+    match inst {
+        Inst::PushStack(inst) => Some(inst.get_idx()),
+        Inst::PopStack(inst) => Some(inst.get_idx()),
+        Inst::PushLocalTable(inst) => Some(inst.get_idx()),
+        Inst::PopLocalTable(inst) => Some(inst.get_idx()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::tests::make_scenario;
+
+    #[test]
+    fn clean_scenario() {
+        let code = [
+            0x01, 0x00, 0x01, // 0x04: init_stack 0 1
+            0x0C, 0x07, // 0x07: push_i8 7
+            0x02, 0x19, 0x00, 0x00, 0x00, // 0x09: call 0x19
+            0x14, // 0x0E: push_return
+            0x16, 0x00, // 0x0F: pop_stack 0
+            0x03, 0x00, 0x00, // 0x11: syscall ThreadNext
+            0x06, 0x07, 0x00, 0x00, 0x00, // 0x14: jmp 0x07
+            0x01, 0x01, 0x00, // 0x19: init_stack 1 0
+            0x10, 0xFE, // 0x1C: push_stack -2
+            0x07, 0x26, 0x00, 0x00, 0x00, // 0x1E: jz 0x26
+            0x0C, 0x01, // 0x23: push_i8 1
+            0x05, // 0x25: retv
+            0x0C, 0x00, // 0x26: push_i8 0
+            0x05, // 0x28: retv
+            0x04, // 0x29: ret, never reached
+        ];
+        let scenario = make_scenario(&code, &[(0, "ThreadNext")]);
+
+        assert_eq!(verify(&scenario), vec![]);
+    }
+
+    #[test]
+    fn broken_scenario() {
+        let code = [
+            0x01, 0x01, 0x00, // 0x04: init_stack 1 0
+            0x10, 0x00, // 0x07: push_stack 0, no locals
+            0x1A, // 0x09: add, a single value on the stack
+            0x07, 0x08, 0x00, 0x00, 0x00, // 0x0A: jz 0x08
+            0x03, 0x04, 0x00, // 0x0F: syscall 4
+            0x0E, 0x03, 0xFF, 0xFF, 0x00, // 0x12: push_string, invalid Shift-JIS
+            0x15, 0x00, 0x00, // 0x17: pop_global 0
+            0x04, // 0x1A: ret
+            0x0C, 0x01, // 0x1B: push_i8 1, unreachable
+            0x04, // 0x1D: ret
+        ];
+        let scenario = make_scenario(&code, &[(0, "ThreadNext")]);
+
+        let diagnostics = verify(&scenario)
+            .into_iter()
+            .map(|d| (d.address, d.severity))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (0x07, Severity::Error),
+                (0x09, Severity::Error),
+                (0x0A, Severity::Error),
+                (0x0F, Severity::Error),
+                (0x12, Severity::Warning),
+                (0x1B, Severity::Warning),
+            ]
+        );
+    }
+}
@@ -6,7 +6,10 @@ use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
 
-use super::scenario::Nls;
+use super::scenario::{
+    detect::{detect, Detection},
+    Nls,
+};
 
 #[derive(Debug, Clone)]
 pub struct VfsEntry {
@@ -24,7 +27,12 @@ pub struct VfsFile {
 }
 
 impl VfsFile {
+    /// open an archive, `Nls::Auto` picks the codepage of its filename table
     pub fn new(path: impl AsRef<Path>, folder_name: &str, nls: Nls) -> Result<Self> {
+        let nls = match nls {
+            Nls::Auto => Self::detect_nls(&path)?.nls,
+            nls => nls,
+        };
         let entries = Self::parse(&path, nls.clone())?;
 
         let vf = Self {
@@ -45,36 +53,54 @@ impl VfsFile {
         Ok(u32::from_le_bytes(buffer))
     }
 
-    /// read c-style strings from buffer
-    fn read_filename_table(
+    /// read the undecoded filename table
+    fn read_filename_blob(
         reader: &mut (impl Read + Seek),
         offset: u64,
         size: u64,
-        nls: Nls,
-    ) -> Result<HashMap<u64, String>> {
+    ) -> Result<Vec<u8>> {
         let mut buffer = vec![];
         reader.seek(std::io::SeekFrom::Start(offset))?;
         let mut chunk = reader.take(size);
         chunk.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// the undecoded filename table of an archive
+    fn filename_blob(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+        let mut file = File::open(path.as_ref())?;
+        let file_count = Self::read_u32le(&mut file, 0)?;
+        let filename_table_size = Self::read_u32le(&mut file, 4)?;
+        // each entry is 12 bytes
+        let filename_table_offset = 8 + file_count as u64 * 12;
+        Self::read_filename_blob(
+            &mut file,
+            filename_table_offset,
+            filename_table_size.into(),
+        )
+    }
+
+    /// detect the codepage of the filename table
+    pub fn detect_nls(path: impl AsRef<Path>) -> Result<Detection> {
+        let blob = Self::filename_blob(path)?;
+        Ok(detect(blob.split(|b| *b == 0)))
+    }
+
+    /// read c-style strings from buffer
+    fn read_filename_table(
+        reader: &mut (impl Read + Seek),
+        offset: u64,
+        size: u64,
+        nls: Nls,
+    ) -> Result<HashMap<u64, String>> {
+        let buffer = Self::read_filename_blob(reader, offset, size)?;
 
         let mut results = HashMap::new();
         let mut start = 0;
         for (i, &b) in buffer.iter().enumerate() {
             if b == 0 {
-                match nls {
-                    Nls::ShiftJIS => {
-                        let (s, _, _) = encoding_rs::SHIFT_JIS.decode(&buffer[start..i]);
-                        results.insert(start as u64, s.to_string());
-                    }
-                    Nls::GBK => {
-                        let (s, _, _) = encoding_rs::GBK.decode(&buffer[start..i]);
-                        results.insert(start as u64, s.to_string());
-                    }
-                    Nls::UTF8 => {
-                        let s = String::from_utf8_lossy(&buffer[start..i]);
-                        results.insert(start as u64, s.to_string());
-                    }
-                };
+                let (s, _) = nls.decode(&buffer[start..i]);
+                results.insert(start as u64, s.to_string());
                 start = i + 1;
             }
         }
@@ -183,6 +209,7 @@ pub struct Vfs {
 }
 
 impl Vfs {
+    /// open every archive in the directory, `Nls::Auto` picks one codepage for all of them
     pub fn new(nls: Nls, base_path: impl AsRef<Path>) -> Result<Self> {
         let path = base_path.as_ref();
         let mut path = path.to_path_buf();
@@ -190,6 +217,19 @@ impl Vfs {
 
         let macthes: Vec<_> = glob::glob(&path.to_string_lossy())?.flatten().collect();
 
+        let nls = match nls {
+            Nls::Auto => {
+                let blobs: Vec<_> = macthes
+                    .iter()
+                    .filter_map(|path| VfsFile::filename_blob(path).ok())
+                    .collect();
+                let detection = detect(blobs.iter().flat_map(|blob| blob.split(|b| *b == 0)));
+                log::info!("VFS filename encoding detected as {}", detection);
+                detection.nls
+            }
+            nls => nls,
+        };
+
         let mut files = HashMap::new();
         for path in &macthes {
             if let Some(file_name) = path.file_name() {
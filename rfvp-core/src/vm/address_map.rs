@@ -0,0 +1,94 @@
+//! The address every instruction moved to when a scenario was reassembled.
+//!
+//! The assembler writes the map next to the scenario it builds. It moves the snapshots taken
+//! with the original scenario to the new one: the thread PCs, routine starts and saved return
+//! addresses are all instruction addresses of the original scenario.
+//!
+//! The file starts with a magic, the format version and the checksums of both scenarios.
+use std::{collections::BTreeMap, mem::size_of, path::Path};
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{format::scenario::Scenario, vm::snapshot::scenario_checksum};
+
+const ADDRESS_MAP_MAGIC: &[u8; 8] = b"RFVPAMAP";
+const ADDRESS_MAP_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressMap {
+    /// checksum of the disassembled scenario, the one the old addresses are in
+    original_checksum: u64,
+    /// checksum of the reassembled scenario
+    scenario_checksum: u64,
+    addresses: BTreeMap<u32, u32>,
+}
+
+impl AddressMap {
+    /// an empty map from the scenario with the `original_checksum` to the reassembled `scenario`
+    pub fn new(original_checksum: u64, scenario: &Scenario) -> Self {
+        Self {
+            original_checksum,
+            scenario_checksum: scenario_checksum(scenario),
+            addresses: BTreeMap::new(),
+        }
+    }
+
+    pub fn get_original_checksum(&self) -> u64 {
+        self.original_checksum
+    }
+
+    pub fn get_scenario_checksum(&self) -> u64 {
+        self.scenario_checksum
+    }
+
+    /// the instruction at `old` in the original scenario is at `new`
+    pub fn insert(&mut self, old: u32, new: u32) {
+        self.addresses.insert(old, new);
+    }
+
+    /// the new address of the instruction at `old`, `None` if it was removed
+    pub fn get(&self, old: u32) -> Option<u32> {
+        self.addresses.get(&old).copied()
+    }
+
+    /// every moved address, ordered by the original address
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.addresses.iter().map(|(old, new)| (*old, *new))
+    }
+
+    /// encode the map, prefixed with a magic and the format version
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.extend_from_slice(ADDRESS_MAP_MAGIC);
+        data.extend_from_slice(&ADDRESS_MAP_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut data, self)?;
+        Ok(data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let header_len = ADDRESS_MAP_MAGIC.len() + size_of::<u32>();
+        if data.len() < header_len || &data[..ADDRESS_MAP_MAGIC.len()] != ADDRESS_MAP_MAGIC {
+            bail!("not an address map");
+        }
+
+        let version = u32::from_le_bytes(data[ADDRESS_MAP_MAGIC.len()..header_len].try_into()?);
+        if version != ADDRESS_MAP_VERSION {
+            bail!("unsupported address map version {}", version);
+        }
+
+        Ok(bincode::deserialize(&data[header_len..])?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_bytes(&data).with_context(|| format!("failed to load {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path.as_ref(), self.to_bytes()?)
+            .with_context(|| format!("failed to write {}", path.as_ref().display()))
+    }
+}
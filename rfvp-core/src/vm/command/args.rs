@@ -0,0 +1,318 @@
+//! Typed arguments of the syscalls.
+//!
+//! The structs here are generated with `#[derive(SyscallArgs)]`, the fields are converted from the
+//! arguments of the syscall in declaration order.
+use anyhow::bail;
+use rfvp_derive::SyscallArgs;
+
+use crate::format::scenario::variant::Variant;
+
+pub type ArgsError = anyhow::Error;
+pub type ArgsResult<T> = Result<T, ArgsError>;
+
+/// A typed signature of a syscall
+pub trait SyscallArgs: Sized {
+    /// name of the syscall in the scenario
+    const NAME: &'static str;
+    /// the number of arguments the syscall is declared with
+    const ARG_COUNT: usize;
+
+    fn from_args(args: &[Variant]) -> ArgsResult<Self>;
+}
+
+/// Conversion of a single syscall argument
+pub trait FromVariant: Sized {
+    /// what the argument is expected to be, used in error messages
+    const EXPECTED: &'static str;
+
+    fn from_variant(value: &Variant) -> Option<Self>;
+}
+
+impl FromVariant for Variant {
+    const EXPECTED: &'static str = "any value";
+
+    fn from_variant(value: &Variant) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl FromVariant for i32 {
+    const EXPECTED: &'static str = "int";
+
+    fn from_variant(value: &Variant) -> Option<Self> {
+        value.as_int()
+    }
+}
+
+impl FromVariant for f32 {
+    const EXPECTED: &'static str = "float";
+
+    fn from_variant(value: &Variant) -> Option<Self> {
+        match value {
+            Variant::Float(v) => Some(*v),
+            Variant::Int(v) => Some(*v as f32),
+            _ => None,
+        }
+    }
+}
+
+/// nil is false, everything else is true
+impl FromVariant for bool {
+    const EXPECTED: &'static str = "bool";
+
+    fn from_variant(value: &Variant) -> Option<Self> {
+        Some(value.canbe_true())
+    }
+}
+
+impl FromVariant for String {
+    const EXPECTED: &'static str = "string";
+
+    fn from_variant(value: &Variant) -> Option<Self> {
+        value.as_string().cloned()
+    }
+}
+
+/// nil is `None`
+impl<T: FromVariant> FromVariant for Option<T> {
+    const EXPECTED: &'static str = T::EXPECTED;
+
+    fn from_variant(value: &Variant) -> Option<Self> {
+        match value {
+            Variant::Nil => Some(None),
+            value => T::from_variant(value).map(Some),
+        }
+    }
+}
+
+pub fn check_arg_count(syscall: &str, expected: usize, actual: usize) -> ArgsResult<()> {
+    if expected != actual {
+        bail!(
+            "{}: expected {} arguments, got {}",
+            syscall,
+            expected,
+            actual
+        );
+    }
+    Ok(())
+}
+
+pub fn convert_arg<T: FromVariant>(syscall: &str, index: usize, value: &Variant) -> ArgsResult<T> {
+    match T::from_variant(value) {
+        Some(value) => Ok(value),
+        None => bail!(
+            "{}: argument {} should be {}, got {:?}",
+            syscall,
+            index,
+            T::EXPECTED,
+            value
+        ),
+    }
+}
+
+macro_rules! signatures {
+    ($($ty:ident),* $(,)?) => {
+        /// name and argument count of every typed syscall
+        pub const SIGNATURES: &[(&str, usize)] = &[
+            $(($ty::NAME, $ty::ARG_COUNT),)*
+        ];
+    };
+}
+
+signatures!(
+    ThreadStart,
+    ThreadWait,
+    ThreadSleep,
+    ThreadRaise,
+    ThreadNext,
+    ThreadExit,
+    TimerSet,
+    Rand,
+    FloatToInt,
+    IntToText,
+    FlagSet,
+    FlagGet,
+    AudioLoad,
+    AudioPlay,
+    AudioStop,
+    AudioState,
+    AudioVol,
+    PrimSetNull,
+    PrimSetXY,
+    PrimSetWH,
+    PrimSetZ,
+    PrimSetAlpha,
+    PrimSetBlend,
+    PrimSetDraw,
+    TextPrint,
+);
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct ThreadStart {
+    pub id: i32,
+    pub addr: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct ThreadWait {
+    pub time: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct ThreadSleep {
+    pub time: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct ThreadRaise {
+    pub time: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct ThreadNext;
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct ThreadExit {
+    /// the current thread if nil
+    pub id: Option<i32>,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct TimerSet {
+    pub id: i32,
+    pub value: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct Rand;
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct FloatToInt {
+    pub value: f32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct IntToText {
+    pub value: i32,
+    /// zero-padded to this width
+    pub width: Option<i32>,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct FlagSet {
+    pub id: i32,
+    pub value: bool,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct FlagGet {
+    pub id: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct AudioLoad {
+    pub channel: i32,
+    /// unloads the channel if nil
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct AudioPlay {
+    pub channel: i32,
+    pub looped: bool,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct AudioStop {
+    pub channel: i32,
+    pub fade_time: Option<i32>,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct AudioState {
+    pub channel: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct AudioVol {
+    pub channel: i32,
+    pub volume: i32,
+    pub fade_time: Option<i32>,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct PrimSetNull {
+    pub id: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct PrimSetXY {
+    pub id: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct PrimSetWH {
+    pub id: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct PrimSetZ {
+    pub id: i32,
+    pub z: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct PrimSetAlpha {
+    pub id: i32,
+    pub alpha: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct PrimSetBlend {
+    pub id: i32,
+    pub blend: i32,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct PrimSetDraw {
+    pub id: i32,
+    pub draw: bool,
+}
+
+#[derive(Debug, Clone, SyscallArgs)]
+pub struct TextPrint {
+    pub slot: i32,
+    pub text: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_args() {
+        let args = PrimSetXY::try_from(vec![Variant::Int(1), Variant::Int(2), Variant::Int(3)])
+            .unwrap();
+        assert_eq!((args.id, args.x, args.y), (1, 2, 3));
+
+        let args = TextPrint::from_args(&[
+            Variant::Int(0),
+            Variant::ConstString("hello".to_string(), 0),
+        ])
+        .unwrap();
+        assert_eq!(args.text, "hello");
+
+        let args = AudioStop::from_args(&[Variant::Int(2), Variant::Nil]).unwrap();
+        assert_eq!(args.fade_time, None);
+
+        let err = PrimSetXY::from_args(&[Variant::Int(1), Variant::Int(2)]).unwrap_err();
+        assert_eq!(err.to_string(), "PrimSetXY: expected 3 arguments, got 2");
+
+        let err = TextPrint::from_args(&[Variant::Int(0), Variant::Nil]).unwrap_err();
+        assert_eq!(err.to_string(), "TextPrint: argument 1 should be string, got Nil");
+    }
+}
@@ -1,10 +1,18 @@
 //! Defines the commands that can be produced by the VM and executed by the engine.
-use crate::format::scenario::variant::Variant;
+use std::str::FromStr;
 
+use anyhow::bail;
+
+use crate::{
+    format::scenario::variant::Variant,
+    vm::command::args::{ArgsResult, SyscallArgs},
+};
+
+pub mod args;
 pub mod types;
 
 #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
-#[derive(Debug)]
+#[derive(Debug, strum::IntoStaticStr, strum::EnumString, strum::VariantNames)]
 pub enum Command {
     AudioLoad {args: Vec<Variant>},
     AudioPlay {args: Vec<Variant>},
@@ -156,6 +164,195 @@ pub enum Command {
     WindowMode {args: Vec<Variant>},
 }
 
+/// bind `$args` to the arguments of any command and evaluate `$body`
+macro_rules! with_args {
+    ($command:expr, $args:ident => $body:expr) => {
+        match $command {
+            Command::AudioLoad { args: $args }
+            | Command::AudioPlay { args: $args }
+            | Command::AudioSilentOn { args: $args }
+            | Command::AudioState { args: $args }
+            | Command::AudioStop { args: $args }
+            | Command::AudioType { args: $args }
+            | Command::AudioVol { args: $args }
+            | Command::ColorSet { args: $args }
+            | Command::ControlMask { args: $args }
+            | Command::ControlPulse { args: $args }
+            | Command::CursorChange { args: $args }
+            | Command::CursorMove { args: $args }
+            | Command::CursorShow { args: $args }
+            | Command::Debmess { args: $args }
+            | Command::Dissolve { args: $args }
+            | Command::DissolveWait { args: $args }
+            | Command::ExitDialog { args: $args }
+            | Command::ExitMode { args: $args }
+            | Command::FlagGet { args: $args }
+            | Command::FlagSet { args: $args }
+            | Command::FloatToInt { args: $args }
+            | Command::GaijiLoad { args: $args }
+            | Command::GraphLoad { args: $args }
+            | Command::GraphRGB { args: $args }
+            | Command::IntToText { args: $args }
+            | Command::HistoryGet { args: $args }
+            | Command::HistorySet { args: $args }
+            | Command::InputFlash { args: $args }
+            | Command::InputGetCursIn { args: $args }
+            | Command::InputGetCursX { args: $args }
+            | Command::InputGetCursY { args: $args }
+            | Command::InputGetDown { args: $args }
+            | Command::InputGetEvent { args: $args }
+            | Command::InputGetRepeat { args: $args }
+            | Command::InputGetState { args: $args }
+            | Command::InputGetUp { args: $args }
+            | Command::InputGetWheel { args: $args }
+            | Command::InputSetClick { args: $args }
+            | Command::LipAnim { args: $args }
+            | Command::LipSync { args: $args }
+            | Command::Load { args: $args }
+            | Command::MenuMessSkip { args: $args }
+            | Command::MotionAlpha { args: $args }
+            | Command::MotionAlphaStop { args: $args }
+            | Command::MotionAlphaTest { args: $args }
+            | Command::MotionAnim { args: $args }
+            | Command::MotionAnimStop { args: $args }
+            | Command::MotionAnimTest { args: $args }
+            | Command::MotionMove { args: $args }
+            | Command::MotionMoveStop { args: $args }
+            | Command::MotionMoveTest { args: $args }
+            | Command::MotionMoveR { args: $args }
+            | Command::MotionMoveRStop { args: $args }
+            | Command::MotionMoveRTest { args: $args }
+            | Command::MotionMoveS2 { args: $args }
+            | Command::MotionMoveS2Stop { args: $args }
+            | Command::MotionMoveS2Test { args: $args }
+            | Command::MotionMoveZ { args: $args }
+            | Command::MotionMoveZStop { args: $args }
+            | Command::MotionMoveZTest { args: $args }
+            | Command::MotionPause { args: $args }
+            | Command::Movie { args: $args }
+            | Command::MovieState { args: $args }
+            | Command::MovieStop { args: $args }
+            | Command::PartsAssign { args: $args }
+            | Command::PartsLoad { args: $args }
+            | Command::PartsMotion { args: $args }
+            | Command::PartsMotionPause { args: $args }
+            | Command::PartsMotionStop { args: $args }
+            | Command::PartsMotionTest { args: $args }
+            | Command::PartsRGB { args: $args }
+            | Command::PartsSelect { args: $args }
+            | Command::PrimExitGroup { args: $args }
+            | Command::PrimGroupIn { args: $args }
+            | Command::PrimGroupMove { args: $args }
+            | Command::PrimGroupOut { args: $args }
+            | Command::PrimHit { args: $args }
+            | Command::PrimSetAlpha { args: $args }
+            | Command::PrimSetBlend { args: $args }
+            | Command::PrimSetDraw { args: $args }
+            | Command::PrimSetNull { args: $args }
+            | Command::PrimSetOP { args: $args }
+            | Command::PrimSetRS { args: $args }
+            | Command::PrimSetRS2 { args: $args }
+            | Command::PrimSetSnow { args: $args }
+            | Command::PrimSetSprt { args: $args }
+            | Command::PrimSetText { args: $args }
+            | Command::PrimSetTile { args: $args }
+            | Command::PrimSetUV { args: $args }
+            | Command::PrimSetWH { args: $args }
+            | Command::PrimSetXY { args: $args }
+            | Command::PrimSetZ { args: $args }
+            | Command::Rand { args: $args }
+            | Command::SaveCreate { args: $args }
+            | Command::SaveThumbSize { args: $args }
+            | Command::SaveData { args: $args }
+            | Command::SaveWrite { args: $args }
+            | Command::Snow { args: $args }
+            | Command::SnowStart { args: $args }
+            | Command::SnowStop { args: $args }
+            | Command::SoundLoad { args: $args }
+            | Command::SoundMasterVol { args: $args }
+            | Command::SoundPlay { args: $args }
+            | Command::SoundSilentOn { args: $args }
+            | Command::SoundStop { args: $args }
+            | Command::SoundType { args: $args }
+            | Command::SoundTypeVol { args: $args }
+            | Command::SoundVol { args: $args }
+            | Command::SysAtSkipName { args: $args }
+            | Command::SysProjFolder { args: $args }
+            | Command::TextBuff { args: $args }
+            | Command::TextClear { args: $args }
+            | Command::TextColor { args: $args }
+            | Command::TextFont { args: $args }
+            | Command::TextFontCount { args: $args }
+            | Command::TextFontGet { args: $args }
+            | Command::TextFontName { args: $args }
+            | Command::TextFontSet { args: $args }
+            | Command::TextFormat { args: $args }
+            | Command::TextFunction { args: $args }
+            | Command::TextOutSize { args: $args }
+            | Command::TextPause { args: $args }
+            | Command::TextPos { args: $args }
+            | Command::TextPrint { args: $args }
+            | Command::TextRepaint { args: $args }
+            | Command::TextShadowDist { args: $args }
+            | Command::TextSize { args: $args }
+            | Command::TextSkip { args: $args }
+            | Command::TextSpace { args: $args }
+            | Command::TextSpeed { args: $args }
+            | Command::TextSuspendChr { args: $args }
+            | Command::TextTest { args: $args }
+            | Command::ThreadExit { args: $args }
+            | Command::ThreadNext { args: $args }
+            | Command::ThreadRaise { args: $args }
+            | Command::ThreadSleep { args: $args }
+            | Command::ThreadStart { args: $args }
+            | Command::ThreadWait { args: $args }
+            | Command::TimerGet { args: $args }
+            | Command::TimerSet { args: $args }
+            | Command::TimerSuspend { args: $args }
+            | Command::TitleMenu { args: $args }
+            | Command::V3DMotion { args: $args }
+            | Command::V3DMotionPause { args: $args }
+            | Command::V3DMotionStop { args: $args }
+            | Command::V3DMotionTest { args: $args }
+            | Command::V3DSet { args: $args }
+            | Command::WindowMode { args: $args } => $body,
+        }
+    };
+}
+
+impl Command {
+    /// Name of the syscall that produced the command
+    pub fn name(&self) -> &'static str {
+        self.into()
+    }
+
+    /// Build the command of the engine syscall with the given name
+    /// returns `None` if the engine does not know the syscall
+    pub fn from_syscall(name: &str, args: Vec<Variant>) -> Option<Self> {
+        let mut command = Self::from_str(name).ok()?;
+        *command.args_mut() = args;
+        Some(command)
+    }
+
+    /// Arguments of the syscall, in the order they were pushed onto the stack
+    pub fn args(&self) -> &[Variant] {
+        with_args!(self, args => args)
+    }
+
+    /// Convert the arguments to the typed signature of the syscall
+    pub fn args_as<A: SyscallArgs>(&self) -> ArgsResult<A> {
+        if self.name() != A::NAME {
+            bail!("expected a {} command, got {}", A::NAME, self.name());
+        }
+        A::from_args(self.args())
+    }
+
+    /// Mutable access to the arguments of the syscall
+    fn args_mut(&mut self) -> &mut Vec<Variant> {
+        with_args!(self, args => args)
+    }
+}
+
 #[derive(Debug)]
 pub enum RuntimeCommand {
     
@@ -0,0 +1,163 @@
+//! Code coverage of a scenario.
+//!
+//! The coverage is the set of instruction addresses every thread executed. It is accumulated
+//! over sessions in a file that starts with a magic, the format version and the checksum of
+//! the scenario, coverage recorded with another scenario can't be merged.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem::size_of,
+    path::Path,
+};
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{format::scenario::Scenario, vm::snapshot::scenario_checksum};
+
+const COVERAGE_MAGIC: &[u8; 8] = b"RFVPCOVR";
+const COVERAGE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Coverage {
+    scenario_checksum: u64,
+    /// executed instruction addresses, by thread id
+    threads: BTreeMap<u32, BTreeSet<u32>>,
+}
+
+impl Coverage {
+    /// an empty coverage of the scenario
+    pub fn new(scenario: &Scenario) -> Self {
+        Self {
+            scenario_checksum: scenario_checksum(scenario),
+            threads: BTreeMap::new(),
+        }
+    }
+
+    /// mark the instruction at `addr` as executed by the thread
+    pub fn record(&mut self, thread: u32, addr: u32) {
+        self.threads.entry(thread).or_default().insert(addr);
+    }
+
+    pub fn get_scenario_checksum(&self) -> u64 {
+        self.scenario_checksum
+    }
+
+    /// was the instruction at `addr` executed by any thread
+    pub fn is_covered(&self, addr: u32) -> bool {
+        self.threads.values().any(|addrs| addrs.contains(&addr))
+    }
+
+    /// the threads that executed the instruction at `addr`
+    pub fn threads_of(&self, addr: u32) -> Vec<u32> {
+        self.threads
+            .iter()
+            .filter(|(_, addrs)| addrs.contains(&addr))
+            .map(|(thread, _)| *thread)
+            .collect()
+    }
+
+    /// the instructions executed by any thread
+    pub fn addresses(&self) -> BTreeSet<u32> {
+        self.threads.values().flatten().copied().collect()
+    }
+
+    /// add the instructions covered by `other`, it must be recorded with the same scenario
+    pub fn merge(&mut self, other: &Coverage) -> Result<()> {
+        if other.scenario_checksum != self.scenario_checksum {
+            bail!("the coverage was recorded with a different scenario");
+        }
+        for (thread, addrs) in &other.threads {
+            self.threads.entry(*thread).or_default().extend(addrs);
+        }
+        Ok(())
+    }
+
+    /// encode the coverage, prefixed with a magic and the format version
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.extend_from_slice(COVERAGE_MAGIC);
+        data.extend_from_slice(&COVERAGE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut data, self)?;
+        Ok(data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let header_len = COVERAGE_MAGIC.len() + size_of::<u32>();
+        if data.len() < header_len || &data[..COVERAGE_MAGIC.len()] != COVERAGE_MAGIC {
+            bail!("not a coverage file");
+        }
+
+        let version = u32::from_le_bytes(data[COVERAGE_MAGIC.len()..header_len].try_into()?);
+        if version != COVERAGE_VERSION {
+            bail!("unsupported coverage file version {}", version);
+        }
+
+        Ok(bincode::deserialize(&data[header_len..])?)
+    }
+
+    /// read a coverage file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_bytes(&data).with_context(|| format!("failed to load {}", path.display()))
+    }
+
+    /// the coverage accumulated in `path` for the scenario, empty if there is no file yet
+    pub fn load_or_new(path: impl AsRef<Path>, scenario: &Scenario) -> Result<Self> {
+        let mut coverage = Self::new(scenario);
+        if path.as_ref().exists() {
+            let previous = Self::load(path.as_ref())?;
+            coverage
+                .merge(&previous)
+                .with_context(|| format!("can't continue {}", path.as_ref().display()))?;
+        }
+        Ok(coverage)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path.as_ref(), self.to_bytes()?)
+            .with_context(|| format!("failed to write {}", path.as_ref().display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{tests::make_scenario, Scripter};
+
+    #[test]
+    fn record_and_merge() {
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            0x08, // 0x07: push_nil
+            0x07, 0x10, 0x00, 0x00, 0x00, // 0x08: jz 0x10
+            0x03, 0x00, 0x00, // 0x0D: syscall ThreadNext
+            0x0E, 0x01, 0x00, // 0x10: push_string ""
+            0x03, 0x00, 0x00, // 0x13: syscall ThreadNext
+        ];
+        let scenario = make_scenario(&code, &[(0, "ThreadNext")]);
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+        scripter.set_coverage(Coverage::new(&scenario));
+
+        assert!(scripter.run(&scenario, 16).unwrap().is_some());
+        let coverage = scripter.detach_coverage().unwrap();
+        assert_eq!(
+            coverage.addresses(),
+            BTreeSet::from([0x04, 0x07, 0x08, 0x10, 0x13])
+        );
+        assert!(!coverage.is_covered(0x0D));
+        assert_eq!(coverage.threads_of(0x10), [0]);
+
+        let mut merged = Coverage::new(&scenario);
+        merged.record(1, 0x0D);
+        merged.merge(&coverage).unwrap();
+        assert!(merged.is_covered(0x0D));
+        let merged = Coverage::from_bytes(&merged.to_bytes().unwrap()).unwrap();
+        assert_eq!(merged.addresses().len(), 6);
+
+        let other = Coverage::new(&make_scenario(&[0x00], &[]));
+        assert!(merged.clone().merge(&other).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Breakpoints and stepping.
+//!
+//! The debugger is checked before every instruction of every thread. When it decides to stop,
+//! the thread is suspended right before the instruction and the VM does not run anything until
+//! the host continues with [`Scripter::continue_execution`] or [`Scripter::step`] followed by
+//! [`Scripter::resume`].
+//!
+//! [`Scripter::continue_execution`]: crate::vm::Scripter::continue_execution
+//! [`Scripter::step`]: crate::vm::Scripter::step
+//! [`Scripter::resume`]: crate::vm::Scripter::resume
+use std::collections::BTreeMap;
+
+use crate::format::scenario::context::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BreakpointId(pub u32);
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub addr: u32,
+    /// only stop in this thread, any thread if `None`
+    pub thread: Option<u32>,
+    pub enabled: bool,
+    pub hit_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepMode {
+    /// stop at the next instruction of the thread, entering called routines
+    Into,
+    /// stop at the next instruction of the thread in the same routine or its caller
+    Over,
+    /// stop once the current routine returned
+    Out,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(BreakpointId),
+    Step,
+    /// requested with [`Debugger::request_pause`]
+    Pause,
+}
+
+/// Where and why the VM stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StopInfo {
+    pub thread: u32,
+    /// the instruction the thread stopped before
+    pub pc: u32,
+    pub reason: StopReason,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    thread: u32,
+    mode: StepMode,
+    /// call depth of the thread when the step started
+    depth: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<BreakpointId, Breakpoint>,
+    next_id: u32,
+    step: Option<Step>,
+    pause_requested: bool,
+    stopped: Option<StopInfo>,
+    /// the instruction the VM continues from, it must not stop there again
+    skip_once: Option<(u32, u32)>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u32, thread: Option<u32>) -> BreakpointId {
+        let id = BreakpointId(self.next_id);
+        self.next_id += 1;
+        self.breakpoints.insert(
+            id,
+            Breakpoint {
+                addr,
+                thread,
+                enabled: true,
+                hit_count: 0,
+            },
+        );
+        id
+    }
+
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> Option<Breakpoint> {
+        self.breakpoints.remove(&id)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn set_breakpoint_enabled(&mut self, id: BreakpointId, enabled: bool) {
+        if let Some(bp) = self.breakpoints.get_mut(&id) {
+            bp.enabled = enabled;
+        }
+    }
+
+    pub fn get_breakpoint(&self, id: BreakpointId) -> Option<&Breakpoint> {
+        self.breakpoints.get(&id)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint)> {
+        self.breakpoints.iter().map(|(id, bp)| (*id, bp))
+    }
+
+    /// stop before the next instruction of any thread
+    pub fn request_pause(&mut self) {
+        self.pause_requested = true;
+    }
+
+    pub fn get_stop(&self) -> Option<&StopInfo> {
+        self.stopped.as_ref()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.is_some()
+    }
+
+    /// leave the stop, the stopped instruction is executed without stopping again
+    pub(crate) fn continue_from_stop(&mut self) -> Option<StopInfo> {
+        let stop = self.stopped.take()?;
+        self.skip_once = Some((stop.thread, stop.pc));
+        Some(stop)
+    }
+
+    pub(crate) fn start_step(&mut self, thread: u32, mode: StepMode, depth: usize) {
+        self.step = Some(Step {
+            thread,
+            mode,
+            depth,
+        });
+    }
+
+    /// decide whether the thread stops before its next instruction
+    pub(crate) fn check(&mut self, thread: u32, context: &Context) -> bool {
+        let pc = context.get_pc() as u32;
+        if self.skip_once == Some((thread, pc)) {
+            self.skip_once = None;
+            return false;
+        }
+
+        let reason = self.stop_reason(thread, pc, context);
+        if let Some(reason) = reason {
+            self.stopped = Some(StopInfo { thread, pc, reason });
+        }
+        reason.is_some()
+    }
+
+    fn stop_reason(&mut self, thread: u32, pc: u32, context: &Context) -> Option<StopReason> {
+        if self.pause_requested {
+            self.pause_requested = false;
+            return Some(StopReason::Pause);
+        }
+
+        if let Some(step) = self.step.filter(|s| s.thread == thread) {
+            let depth = context.call_depth();
+            let done = match step.mode {
+                StepMode::Into => true,
+                StepMode::Over => depth <= step.depth,
+                StepMode::Out => depth < step.depth,
+            };
+            if done {
+                self.step = None;
+                return Some(StopReason::Step);
+            }
+        }
+
+        let (id, bp) = self
+            .breakpoints
+            .iter_mut()
+            .find(|(_, bp)| bp.enabled && bp.addr == pc && bp.thread.unwrap_or(thread) == thread)?;
+        bp.hit_count += 1;
+        Some(StopReason::Breakpoint(*id))
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+use crate::format::scenario::{
+    context::{CallFrame, Context},
+    instructions::Opcode,
+    variant::Variant,
+    Scenario,
+};
+
+/// An error raised while executing a thread of the script
+///
+/// Holds the state of the thread at the faulting instruction, so the host can report or dump it.
+#[derive(Debug)]
+pub struct VmError {
+    pub thread_id: u32,
+    /// address of the faulting instruction
+    pub pc: usize,
+    /// the opcode at `pc`, `None` if `pc` is outside of the scenario
+    pub opcode: Option<u8>,
+    /// the call stack, innermost routine first
+    pub frames: Vec<CallFrame>,
+    pub stack_top: Option<Variant>,
+    pub source: anyhow::Error,
+}
+
+impl VmError {
+    pub fn new(
+        thread_id: u32,
+        context: &Context,
+        scenario: &Scenario,
+        source: anyhow::Error,
+    ) -> Self {
+        let pc = context.get_last_pc();
+        Self {
+            thread_id,
+            pc,
+            opcode: scenario.read_u8(pc).ok(),
+            frames: context.backtrace(scenario),
+            stack_top: context.peek().cloned(),
+            source,
+        }
+    }
+
+    /// the mnemonic of the faulting instruction
+    pub fn mnemonic(&self) -> Option<String> {
+        let opcode = Opcode::try_from(self.opcode? as i32).ok()?;
+        Some(opcode.to_string())
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "thread {} failed at 0x{:08x}", self.thread_id, self.pc)?;
+        match (self.mnemonic(), self.opcode) {
+            (Some(mnemonic), _) => write!(f, " ({})", mnemonic)?,
+            (None, Some(opcode)) => write!(f, " (invalid opcode 0x{:02x})", opcode)?,
+            (None, None) => {}
+        }
+        write!(f, ": {:#}", self.source)?;
+
+        for (i, frame) in self.frames.iter().enumerate() {
+            match frame.function {
+                Some(function) => {
+                    write!(f, "\n  #{} 0x{:08x} in func_{:08x}", i, frame.pc, function)?
+                }
+                None => write!(f, "\n  #{} 0x{:08x} in ?", i, frame.pc)?,
+            }
+        }
+        if let Some(top) = &self.stack_top {
+            write!(f, "\n  top of the stack: {:?}", top)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
@@ -1,10 +1,20 @@
+pub mod address_map;
 pub mod command;
+pub mod coverage;
+pub mod debugger;
+pub mod error;
+pub mod persist;
+pub mod profiler;
+pub mod program;
+pub mod replay;
+pub mod snapshot;
+pub mod syscall;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use tracing::{instrument, trace};
 
 use crate::vm::command::Command;
-use std::cell::{RefCell, RefMut};
+use std::cell::{Ref, RefCell, RefMut};
 
 use crate::{
     format::scenario::{
@@ -12,9 +22,19 @@ use crate::{
             Context, CONTEXT_STATUS_NONE, CONTEXT_STATUS_RUNNING, CONTEXT_STATUS_SLEEP,
             CONTEXT_STATUS_WAIT,
         },
+        global::Global,
         Scenario,
     },
-    vm::command::CommandResult,
+    vm::{
+        command::CommandResult,
+        coverage::Coverage,
+        debugger::{Debugger, StepMode},
+        error::VmError,
+        profiler::Profiler,
+        program::Program,
+        snapshot::{scenario_checksum, VmSnapshot},
+        syscall::{SyscallRegistry, SyscallTable},
+    },
 };
 
 pub struct Scripter {
@@ -22,6 +42,24 @@ pub struct Scripter {
     pub contexts: Vec<RefCell<Context>>,
     current_id: u32,
     thread_break: bool,
+    /// the thread that produced the last command and waits for its result
+    suspended_id: Option<u32>,
+    /// the frame time of the current time slice, in ms
+    frame_time: u64,
+    /// decoded code area of the loaded scenario
+    program: Program,
+    /// syscall handlers of the loaded scenario, indexed by syscall id, `None` until loaded
+    syscalls: Option<SyscallTable>,
+    /// global variables of the loaded scenario
+    globals: Global,
+    /// whether the globals were initialized for the scenario, by the host or the first run
+    globals_loaded: bool,
+    /// created when the host first asks for it
+    debugger: Option<Debugger>,
+    /// created when the host first asks for it
+    profiler: Option<Profiler>,
+    /// the executed instructions are recorded while it is set
+    coverage: Option<Coverage>,
 }
 
 impl Scripter {
@@ -30,6 +68,15 @@ impl Scripter {
             contexts: vec![RefCell::new(Context::new(0)); 32],
             current_id: 0,
             thread_break: false,
+            suspended_id: None,
+            frame_time: 0,
+            program: Program::default(),
+            syscalls: None,
+            globals: Global::new(),
+            globals_loaded: false,
+            debugger: None,
+            profiler: None,
+            coverage: None,
         }
     }
 
@@ -144,18 +191,21 @@ impl Scripter {
         self.contexts[id as usize].borrow_mut()
     }
 
+    /// read-only access to a thread, for inspection
+    pub fn thread(&self, id: u32) -> Ref<'_, Context> {
+        self.contexts[id as usize].borrow()
+    }
+
     pub fn start_main(&mut self, entry_point: u32) {
         self.thread_start(0, entry_point);
     }
 
-    // #[instrument(skip(self), level = "trace")]
-    #[inline]
-    fn run_instructions(
-        &mut self,
-        secnario: &Scenario,
-        id: u32,
-        frame_time: u64,
-    ) -> Option<Command> {
+    /// is there a thread waiting for the result of a command
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_id.is_some()
+    }
+
+    fn update_waiting_time(&mut self, id: u32, frame_time: u64) {
         let status = self.get_thread(id).get_status();
         if status & CONTEXT_STATUS_WAIT != 0 {
             let wait_time = self.get_thread(id).get_waiting_time();
@@ -166,33 +216,656 @@ impl Scripter {
                 self.get_thread(id).set_status(status & 0xFFFFFFFD);
             }
         }
+    }
+
+    /// reset the global variables to the ones declared by the scenario
+    ///
+    /// Without this they are initialized when the VM runs for the first time.
+    pub fn init_globals(&mut self, secnario: &Scenario) {
+        self.globals = Global::for_scenario(secnario);
+        self.globals_loaded = true;
+    }
+
+    pub fn globals(&self) -> &Global {
+        &self.globals
+    }
+
+    pub fn globals_mut(&mut self) -> &mut Global {
+        &mut self.globals
+    }
+
+    /// bind the syscalls of the scenario to the handlers of the registry
+    ///
+    /// Without this the handlers of [`SyscallRegistry::default`] are used.
+    pub fn load_syscalls(&mut self, secnario: &Scenario, registry: &SyscallRegistry) -> Result<()> {
+        self.syscalls = Some(registry.resolve(secnario)?);
+        Ok(())
+    }
+
+    /// decode the code area of the scenario
+    ///
+    /// This is done on the first run, a host that switches scenarios has to call it again.
+    pub fn load_program(&mut self, secnario: &Scenario) {
+        self.program = Program::new(secnario);
+    }
+
+    /// execute the thread until it yields or produces a command
+    ///
+    /// A thread that fails is stopped, it can't continue from the middle of an instruction.
+    // #[instrument(skip(self), level = "trace")]
+    #[inline]
+    fn run_instructions(&mut self, secnario: &Scenario, id: u32) -> Result<Option<Command>, VmError> {
+        if self.syscalls.is_none() {
+            if let Err(e) = self.load_syscalls(secnario, &SyscallRegistry::default()) {
+                let context = self.contexts[id as usize].borrow();
+                return Err(VmError::new(id, &context, secnario, e));
+            }
+        }
+        if self.program.is_empty() {
+            self.load_program(secnario);
+        }
+        if !self.globals_loaded {
+            self.init_globals(secnario);
+        }
+        let Some(syscalls) = &self.syscalls else {
+            unreachable!("the syscalls are loaded above");
+        };
+
+        let mut context = self.contexts[id as usize].borrow_mut();
+        if context.get_status() & CONTEXT_STATUS_RUNNING == 0 {
+            return Ok(None);
+        }
+        log::info!("tid: {}", id);
+        if let Some(profiler) = &mut self.profiler {
+            let frames = context.frames(secnario);
+            profiler.enter(id, frames.iter().rev().filter_map(|f| f.function));
+        }
 
-        if status & CONTEXT_STATUS_RUNNING != 0 {
-            self.get_thread(id).set_should_break(false);
-            while !self.get_thread(id).should_break() {
-                log::info!("tid: {}", id);
-                let result = self.get_thread(id).dispatch_opcode(&secnario);
-                if let Err(e) = result {
-                    panic!("Error while executing the script {:?}", e);
+        let mut index = self.program.index_of(context.get_pc());
+        let result = loop {
+            if context.should_break() {
+                break Ok(None);
+            }
+            if let Some(debugger) = &mut self.debugger {
+                if debugger.check(id, &context) {
+                    break Ok(None);
+                }
+            }
+            if let Some(profiler) = &mut self.profiler {
+                if let Some(op) = index.and_then(|i| self.program.get(i)) {
+                    profiler.record(id, op);
                 }
             }
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(id, context.get_pc() as u32);
+            }
+            let result = context.execute(&self.program, index, syscalls, &mut self.globals);
+            match result {
+                Ok(Some(cmd)) => break Ok(Some(cmd)),
+                Ok(None) => index = self.program.next_index(index, context.get_pc()),
+                Err(e) => {
+                    let error = VmError::new(id, &context, secnario, e);
+                    context.set_status(CONTEXT_STATUS_NONE);
+                    context.set_should_break(true);
+                    break Err(error);
+                }
+            }
+        };
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.pause();
         }
+        result
+    }
 
-        None
+    /// run the threads starting from `start_id` until a command is encountered
+    fn run_from(&mut self, secnario: &Scenario, start_id: usize) -> Result<Option<Command>, VmError> {
+        for i in start_id..self.contexts.len() {
+            if self.get_should_break() || self.is_stopped() {
+                break;
+            }
+
+            self.set_current_id(i as u32);
+            self.update_waiting_time(i as u32, self.frame_time);
+            self.get_thread(i as u32).set_should_break(false);
+            // on error the next threads can still be run with `resume`
+            self.suspended_id = Some(i as u32);
+            if let Some(cmd) = self.run_instructions(secnario, i as u32)? {
+                return Ok(Some(cmd));
+            }
+            if self.is_stopped() {
+                // stopped by the debugger, `resume` continues from this thread
+                return Ok(None);
+            }
+            self.suspended_id = None;
+        }
+
+        Ok(None)
     }
 
-    /// Run the VM until a command is encountered
+    /// Run the VM for a new frame until a command is encountered
+    ///
+    /// The thread that produced the command is suspended, the host should execute the command
+    /// and pass its result to [`Scripter::resume`] until no more commands are produced.
+    ///
+    /// If a thread fails, it is stopped and the error is returned. The host may report it and
+    /// call [`Scripter::resume`] to run the remaining threads of the frame.
+    ///
+    /// Nothing is executed while the debugger is stopped.
     #[inline]
-    pub fn run(&mut self, secnario: &Scenario, frame_time: u64) -> Option<Command> {
-        for i in 0..self.contexts.len() {
-            if !self.get_should_break() {
-                self.set_current_id(i as u32);
-                if let Some(cmd) = self.run_instructions(secnario, i as u32, frame_time) {
-                    return Some(cmd);
-                }
+    pub fn run(&mut self, secnario: &Scenario, frame_time: u64) -> Result<Option<Command>, VmError> {
+        if self.is_stopped() {
+            return Ok(None);
+        }
+        if let Some(id) = self.suspended_id.take() {
+            log::warn!("thread {} is still waiting for a command result, dropping it", id);
+        }
+
+        self.frame_time = frame_time;
+        self.run_from(secnario, 0)
+    }
+
+    /// Deliver the result of the last command to the suspended thread and continue the frame
+    /// until the next command is encountered
+    pub fn resume(&mut self, secnario: &Scenario, result: CommandResult) -> Result<Option<Command>, VmError> {
+        if self.is_stopped() {
+            return Ok(None);
+        }
+        let Some(id) = self.suspended_id.take() else {
+            return Ok(None);
+        };
+        if let CommandResult::WriteR0(value) = result {
+            self.get_thread(id).set_return_value(value);
+        }
+
+        // the command might have changed the scheduling of the threads
+        // (ThreadWait, ThreadExit, etc), in this case the thread is no longer runnable
+        self.set_current_id(id);
+        if !self.get_should_break() {
+            self.suspended_id = Some(id);
+            if let Some(cmd) = self.run_instructions(secnario, id)? {
+                return Ok(Some(cmd));
+            }
+            if self.is_stopped() {
+                return Ok(None);
+            }
+            self.suspended_id = None;
+        }
+
+        self.run_from(secnario, id as usize + 1)
+    }
+
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
+    /// the debugger of the VM, it is attached on the first call
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        self.debugger.get_or_insert_with(Debugger::new)
+    }
+
+    pub fn detach_debugger(&mut self) -> Option<Debugger> {
+        self.debugger.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// the profiler of the VM, it records from the first call on
+    pub fn profiler_mut(&mut self) -> &mut Profiler {
+        self.profiler.get_or_insert_with(Profiler::new)
+    }
+
+    pub fn detach_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// record the executed instructions into `coverage`
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn detach_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// is the VM stopped at a breakpoint or after a step
+    pub fn is_stopped(&self) -> bool {
+        self.debugger.as_ref().is_some_and(|d| d.is_stopped())
+    }
+
+    /// Leave the current stop, the execution goes on with [`Scripter::resume`]
+    pub fn continue_execution(&mut self) {
+        if let Some(debugger) = &mut self.debugger {
+            debugger.continue_from_stop();
+        }
+    }
+
+    /// Leave the current stop and stop again after a step of the stopped thread
+    ///
+    /// The execution goes on with [`Scripter::resume`].
+    pub fn step(&mut self, mode: StepMode) -> Result<()> {
+        let Some(debugger) = &mut self.debugger else {
+            bail!("no debugger is attached");
+        };
+        let Some(stop) = debugger.continue_from_stop() else {
+            bail!("the VM is not stopped");
+        };
+
+        let depth = self.contexts[stop.thread as usize].borrow().call_depth();
+        debugger.start_step(stop.thread, mode, depth);
+        Ok(())
+    }
+
+    /// Capture the complete VM state, including the global variables
+    pub fn snapshot(&self, secnario: &Scenario) -> VmSnapshot {
+        VmSnapshot {
+            scenario_checksum: scenario_checksum(secnario),
+            contexts: self.contexts.iter().map(|c| c.borrow().clone()).collect(),
+            current_id: self.current_id,
+            thread_break: self.thread_break,
+            suspended_id: self.suspended_id,
+            frame_time: self.frame_time,
+            globals: self.globals.clone(),
+        }
+    }
+
+    /// Replace the VM state with a snapshot taken with the same scenario
+    ///
+    /// If a thread was waiting for a command result when the snapshot was taken, it is still
+    /// suspended and the result has to be passed to [`Scripter::resume`] again.
+    pub fn restore(&mut self, secnario: &Scenario, snapshot: VmSnapshot) -> Result<()> {
+        if snapshot.scenario_checksum != scenario_checksum(secnario) {
+            bail!("the snapshot was taken with a different scenario");
+        }
+        if snapshot.contexts.len() != self.contexts.len() {
+            bail!(
+                "the snapshot has {} threads, expected {}",
+                snapshot.contexts.len(),
+                self.contexts.len()
+            );
+        }
+
+        self.contexts = snapshot.contexts.into_iter().map(RefCell::new).collect();
+        self.current_id = snapshot.current_id;
+        self.thread_break = snapshot.thread_break;
+        self.suspended_id = snapshot.suspended_id;
+        self.frame_time = snapshot.frame_time;
+        self.globals = snapshot.globals;
+        self.globals_loaded = true;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::format::scenario::{context::CallFrame, variant::Variant, Nls, ScenarioBuilder};
+
+    /// assemble a scenario with the given code and syscall table by hand
+    pub(crate) fn make_scenario(code: &[u8], syscalls: &[(u8, &str)]) -> Scenario {
+        let mut builder = ScenarioBuilder::new(Nls::ShiftJIS);
+        builder
+            .code(code)
+            .non_volatile_global_count(1)
+            .volatile_global_count(1)
+            .title("test");
+        for (args, name) in syscalls {
+            builder.syscall(*args, name);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn syscall_suspends_thread() {
+        let code = [
+            0x01, 0x00, 0x00, // init_stack 0 0
+            0x0C, 0x07, // push_i8 7
+            0x03, 0x00, 0x00, // syscall FloatToInt
+            0x14, // push_return
+            0x15, 0x01, 0x00, // pop_global 1
+            0x03, 0x01, 0x00, // syscall ThreadNext
+            0x06, 0x10, 0x00, 0x00, 0x00, // jmp 0x10
+        ];
+        let scenario = make_scenario(&code, &[(1, "FloatToInt"), (0, "ThreadNext")]);
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+
+        match scripter.run(&scenario, 16).unwrap() {
+            Some(Command::FloatToInt { args }) => assert_eq!(args[0].as_int(), Some(7)),
+            cmd => panic!("unexpected command: {:?}", cmd),
+        }
+        assert!(scripter.is_suspended());
+
+        let cmd = scripter.resume(&scenario, CommandResult::WriteR0(Variant::Int(42))).unwrap();
+        assert!(matches!(cmd, Some(Command::ThreadNext { .. })));
+        assert_eq!(scripter.globals().read(1).unwrap().as_int(), Some(42));
+
+        scripter.thread_next();
+        assert!(scripter.resume(&scenario, CommandResult::None).unwrap().is_none());
+        assert!(!scripter.is_suspended());
+
+        // the next frame continues after the ThreadNext syscall
+        let cmd = scripter.run(&scenario, 16).unwrap();
+        assert!(matches!(cmd, Some(Command::ThreadNext { .. })));
+    }
+
+    #[test]
+    fn invalid_instruction_fails_when_executed() {
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            0x03, 0x00, 0x00, // 0x07: syscall ThreadNext
+            0x0A, 0x01, // 0x0A: truncated push_i32
+        ];
+        let scenario = make_scenario(&code, &[(0, "ThreadNext")]);
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+
+        // the instructions before the invalid one still run
+        let cmd = scripter.run(&scenario, 16).unwrap();
+        assert!(matches!(cmd, Some(Command::ThreadNext { .. })));
+
+        let error = scripter.resume(&scenario, CommandResult::None).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("0x0000000a"), "{}", message);
+        assert!(message.contains("truncated instruction"), "{}", message);
+    }
+
+    #[test]
+    fn custom_syscall_calls_function() {
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            // the low byte of the operand reads as a call opcode right before the syscall
+            0x0B, 0x02, 0x00, // 0x07: push_i16 2
+            0x03, 0x01, 0x00, // 0x0A: syscall Double
+            0x14, // 0x0D: push_return
+            0x15, 0x01, 0x00, // 0x0E: pop_global 1
+            0x03, 0x00, 0x00, // 0x11: syscall ThreadNext
+            0x06, 0x14, 0x00, 0x00, 0x00, // 0x14: jmp 0x14
+            0x01, 0x01, 0x00, // 0x19: init_stack 1 0
+            0x10, 0xFE, // 0x1C: push_stack -2
+            0x10, 0xFE, // 0x1E: push_stack -2
+            0x1A, // 0x20: add
+            0x05, // 0x21: retv
+        ];
+        let scenario = ScenarioBuilder::new(Nls::ShiftJIS)
+            .code(&code)
+            .non_volatile_global_count(1)
+            .volatile_global_count(1)
+            .syscall(0, "ThreadNext")
+            .custom_syscall(0x19, 1, "Double")
+            .build()
+            .unwrap();
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+        scripter.debugger_mut().add_breakpoint(0x20, None);
+
+        assert!(scripter.run(&scenario, 16).unwrap().is_none());
+        let frames = scripter.thread(0).frames(&scenario);
+        let functions = frames.iter().map(|f| (f.function, f.pc)).collect::<Vec<_>>();
+        assert_eq!(functions, [(Some(0x19), 0x20), (Some(4), 0x0A)]);
+
+        scripter.continue_execution();
+        let cmd = scripter.resume(&scenario, CommandResult::None).unwrap();
+        assert!(matches!(cmd, Some(Command::ThreadNext { .. })));
+        assert_eq!(scripter.globals().read(1).unwrap().as_int(), Some(4));
+    }
+
+    #[test]
+    fn snapshot_is_deterministic() {
+        use crate::format::scenario::variant::Table;
+
+        let scenario = make_scenario(&[0x01, 0x00, 0x00, 0x04], &[]);
+        let snapshot = || {
+            let mut scripter = Scripter::new();
+            scripter.init_globals(&scenario);
+            let mut table = Table::new();
+            for key in 0..32 {
+                table.insert(key * 7, Variant::Int(key as i32));
             }
+            scripter.globals_mut().write(1, Variant::Table(table)).unwrap();
+            scripter.start_main(scenario.get_entry_point());
+            scripter.snapshot(&scenario).to_bytes().unwrap()
+        };
+        assert_eq!(snapshot(), snapshot());
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let code = [
+            0x01, 0x00, 0x01, // init_stack 0 1
+            0x0C, 0x05, // push_i8 5
+            0x16, 0x00, // local_copy 0
+            0x10, 0x00, // push_stack 0
+            0x03, 0x00, 0x00, // syscall Rand
+            0x14, // push_return
+            0x1A, // add
+            0x16, 0x00, // local_copy 0
+            0x10, 0x00, // push_stack 0
+            0x15, 0x00, 0x00, // pop_global 0
+            0x06, 0x0B, 0x00, 0x00, 0x00, // jmp 0x0B
+        ];
+        let scenario = make_scenario(&code, &[(0, "Rand")]);
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+
+        assert!(scripter.run(&scenario, 16).unwrap().is_some());
+        let cmd = scripter.resume(&scenario, CommandResult::WriteR0(Variant::Int(10))).unwrap();
+        assert!(cmd.is_some());
+
+        let data = scripter.snapshot(&scenario).to_bytes().unwrap();
+
+        let next = |scripter: &mut Scripter| {
+            scripter.resume(&scenario, CommandResult::WriteR0(Variant::Int(100))).unwrap();
+            scripter.globals().read(0).unwrap().as_int()
+        };
+        assert_eq!(next(&mut scripter), Some(115));
+        assert_eq!(next(&mut scripter), Some(215));
+
+        // the restored VM is suspended in the same syscall and continues the same way
+        let snapshot = VmSnapshot::from_bytes(&data).unwrap();
+        let mut restored = Scripter::new();
+        restored.restore(&scenario, snapshot).unwrap();
+        assert!(restored.is_suspended());
+        assert_eq!(restored.globals().read(0).unwrap().as_int(), Some(15));
+        assert_eq!(next(&mut restored), Some(115));
+        assert_eq!(next(&mut restored), Some(215));
+
+        let other = make_scenario(&[0x00], &[(0, "Rand")]);
+        let snapshot = VmSnapshot::from_bytes(&data).unwrap();
+        assert!(Scripter::new().restore(&other, snapshot).is_err());
+        assert!(VmSnapshot::from_bytes(&data[..8]).is_err());
+    }
+
+    #[test]
+    fn snapshot_migrate() {
+        use crate::vm::{address_map::AddressMap, snapshot::scenario_checksum};
+
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            0x02, 0x11, 0x00, 0x00, 0x00, // 0x07: call 0x11
+            0x06, 0x07, 0x00, 0x00, 0x00, // 0x0C: jmp 0x07
+            0x01, 0x00, 0x00, // 0x11: init_stack 0 0
+            0x03, 0x00, 0x00, // 0x14: syscall Rand
+            0x04, // 0x17: ret
+        ];
+        let scenario = make_scenario(&code, &[(0, "Rand")]);
+        // the same script with a nop inserted before the call
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            0x00, // 0x07: nop
+            0x02, 0x12, 0x00, 0x00, 0x00, // 0x08: call 0x12
+            0x06, 0x08, 0x00, 0x00, 0x00, // 0x0D: jmp 0x08
+            0x01, 0x00, 0x00, // 0x12: init_stack 0 0
+            0x03, 0x00, 0x00, // 0x15: syscall Rand
+            0x04, // 0x18: ret
+        ];
+        let reassembled = make_scenario(&code, &[(0, "Rand")]);
+        let mut map = AddressMap::new(scenario_checksum(&scenario), &reassembled);
+        for (old, new) in [(0x04, 0x04), (0x07, 0x08), (0x0C, 0x0D), (0x11, 0x12)] {
+            map.insert(old, new);
         }
+        map.insert(0x14, 0x15);
+
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+        assert!(scripter.run(&scenario, 16).unwrap().is_some());
+        let snapshot = scripter.snapshot(&scenario);
+        // the thread is suspended in Rand, the return address is not in the map yet
+        assert!(snapshot.clone().migrate(&map).is_err());
+
+        map.insert(0x17, 0x18);
+        let map = AddressMap::from_bytes(&map.to_bytes().unwrap()).unwrap();
+        let snapshot = snapshot.migrate(&map).unwrap();
+        let mut restored = Scripter::new();
+        restored.restore(&reassembled, snapshot).unwrap();
+        assert_eq!(restored.thread(0).get_last_pc(), 0x15);
+        // a snapshot of another scenario is refused, even if its addresses are in the map
+        assert!(restored.snapshot(&reassembled).migrate(&map).is_err());
+
+        // it returns into the reassembled main and calls the routine again
+        let cmd = restored.resume(&reassembled, CommandResult::WriteR0(Variant::Int(1))).unwrap();
+        assert!(matches!(cmd, Some(Command::Rand { .. })));
+        assert_eq!(restored.thread(0).get_last_pc(), 0x15);
+    }
+
+    #[test]
+    fn error_backtrace() {
+        let code = [
+            0x01, 0x00, 0x00, // init_stack 0 0
+            0x0C, 0x07, // push_i8 7
+            0x02, 0x13, 0x00, 0x00, 0x00, // call 0x13
+            0x06, 0x04, 0x00, 0x00, 0x00, // jmp 0x04
+            0x01, 0x01, 0x00, // init_stack 1 0
+            0x03, 0x05, 0x00, // syscall 5, not declared
+        ];
+        let scenario = make_scenario(&code, &[]);
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+
+        let error = scripter.run(&scenario, 16).unwrap_err();
+        assert_eq!(error.thread_id, 0);
+        assert_eq!(error.pc, 0x16);
+        assert_eq!(error.mnemonic().as_deref(), Some("syscall"));
+        assert_eq!(
+            error.frames,
+            vec![
+                CallFrame {
+                    function: Some(0x13),
+                    pc: 0x16,
+                    stack_base: 3,
+                    args: 1,
+                },
+                CallFrame {
+                    function: Some(0x04),
+                    pc: 0x09,
+                    stack_base: 1,
+                    args: 0,
+                },
+            ]
+        );
+        assert!(error.to_string().contains("#1 0x00000009 in func_00000004"));
+
+        // the failed thread is stopped, the VM keeps going
+        assert!(scripter.resume(&scenario, CommandResult::None).unwrap().is_none());
+        assert!(scripter.run(&scenario, 16).unwrap().is_none());
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        use crate::vm::debugger::{StopInfo, StopReason};
+
+        let code = [
+            0x01, 0x00, 0x00, // init_stack 0 0
+            0x0C, 0x07, // push_i8 7
+            0x02, 0x1A, 0x00, 0x00, 0x00, // call 0x1A
+            0x14, // push_return
+            0x15, 0x01, 0x00, // pop_global 1
+            0x03, 0x00, 0x00, // syscall ThreadNext
+            0x06, 0x07, 0x00, 0x00, 0x00, // jmp 0x07
+            0x01, 0x01, 0x01, // init_stack 1 1
+            0x0C, 0x03, // push_i8 3
+            0x16, 0x00, // local_copy 0
+            0x10, 0x00, // push_stack 0
+            0x05, // retv
+        ];
+        let scenario = make_scenario(&code, &[(0, "ThreadNext")]);
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+        let bp = scripter.debugger_mut().add_breakpoint(0x21, None);
+
+        let stop_at = |scripter: &Scripter| scripter.debugger().unwrap().get_stop().map(|s| s.pc);
+        let step = |scripter: &mut Scripter, mode| {
+            scripter.step(mode).unwrap();
+            scripter.resume(&scenario, CommandResult::None).unwrap()
+        };
+
+        // nothing runs while stopped
+        assert!(scripter.run(&scenario, 16).unwrap().is_none());
+        assert_eq!(
+            scripter.debugger().unwrap().get_stop(),
+            Some(&StopInfo {
+                thread: 0,
+                pc: 0x21,
+                reason: StopReason::Breakpoint(bp),
+            })
+        );
+        assert!(scripter.run(&scenario, 16).unwrap().is_none());
+        assert_eq!(stop_at(&scripter), Some(0x21));
+
+        {
+            let thread = scripter.thread(0);
+            let frames = thread.frames(&scenario);
+            assert_eq!(frames.len(), 2);
+            assert_eq!((frames[0].function, frames[0].pc), (Some(0x1A), 0x21));
+            assert_eq!((frames[1].function, frames[1].pc), (Some(0x04), 0x09));
+            assert_eq!(thread.frame_args(&frames[0])[0].as_int(), Some(7));
+            assert_eq!(thread.frame_locals(&scenario, &frames[0])[0].as_int(), Some(3));
+        }
+
+        assert!(step(&mut scripter, StepMode::Over).is_none());
+        assert_eq!(stop_at(&scripter), Some(0x23));
+        assert!(step(&mut scripter, StepMode::Out).is_none());
+        assert_eq!(stop_at(&scripter), Some(0x0E));
+        assert_eq!(scripter.thread(0).get_return_value().as_int(), Some(3));
+        assert!(step(&mut scripter, StepMode::Over).is_none());
+        assert_eq!(stop_at(&scripter), Some(0x0F));
+
+        // stepping over a syscall hands the command to the host first
+        assert!(step(&mut scripter, StepMode::Over).is_none());
+        let cmd = step(&mut scripter, StepMode::Over);
+        assert!(matches!(cmd, Some(Command::ThreadNext { .. })));
+        scripter.thread_next();
+        assert!(scripter.resume(&scenario, CommandResult::None).unwrap().is_none());
+        assert!(!scripter.is_stopped());
+        assert!(scripter.run(&scenario, 16).unwrap().is_none());
+        assert_eq!(stop_at(&scripter), Some(0x15));
+
+        // step into the call
+        assert!(step(&mut scripter, StepMode::Into).is_none());
+        assert!(step(&mut scripter, StepMode::Into).is_none());
+        assert!(step(&mut scripter, StepMode::Into).is_none());
+        assert_eq!(stop_at(&scripter), Some(0x1A));
+        assert_eq!(scripter.thread(0).call_depth(), 2);
+
+        scripter.continue_execution();
+        assert!(scripter.resume(&scenario, CommandResult::None).unwrap().is_none());
+        assert_eq!(stop_at(&scripter), Some(0x21));
+        assert_eq!(scripter.debugger().unwrap().get_breakpoint(bp).unwrap().hit_count, 2);
 
-        None
+        // a breakpoint of another thread is ignored
+        let debugger = scripter.debugger_mut();
+        debugger.remove_breakpoint(bp);
+        debugger.add_breakpoint(0x21, Some(1));
+        scripter.continue_execution();
+        let cmd = scripter.resume(&scenario, CommandResult::None).unwrap();
+        assert!(matches!(cmd, Some(Command::ThreadNext { .. })));
+        assert!(!scripter.is_stopped());
     }
 }
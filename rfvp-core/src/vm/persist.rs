@@ -0,0 +1,225 @@
+//! Persistence of the non-volatile global variables.
+//!
+//! FVP games keep cleared routes, unlocked CGs and settings in the non-volatile globals, so they
+//! have to survive between sessions. The file starts with a magic, the format version and a
+//! CRC32 of the payload, a file that does not match is reported as corrupted instead of being
+//! loaded.
+use std::{
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::format::{
+    save::crc32::crc32,
+    scenario::{global::Global, variant::Variant},
+};
+
+const PERSIST_MAGIC: &[u8; 8] = b"RFVPGLOB";
+const PERSIST_VERSION: u32 = 1;
+const HEADER_LEN: usize = PERSIST_MAGIC.len() + size_of::<u32>() * 2;
+
+/// encode the non-volatile slots of `globals`
+pub fn encode_non_volatile(globals: &Global) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(&globals.non_volatile_values())?;
+
+    let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+    data.extend_from_slice(PERSIST_MAGIC);
+    data.extend_from_slice(&PERSIST_VERSION.to_le_bytes());
+    data.extend_from_slice(&crc32(&payload, 0).to_le_bytes());
+    data.extend_from_slice(&payload);
+    Ok(data)
+}
+
+/// decode the values written by [`encode_non_volatile`]
+pub fn decode_non_volatile(data: &[u8]) -> Result<Vec<Variant>> {
+    if data.len() < HEADER_LEN || &data[..PERSIST_MAGIC.len()] != PERSIST_MAGIC {
+        bail!("not a global variable file");
+    }
+
+    let version = u32::from_le_bytes(data[8..12].try_into()?);
+    if version != PERSIST_VERSION {
+        bail!("unsupported global variable file version {}", version);
+    }
+
+    let checksum = u32::from_le_bytes(data[12..16].try_into()?);
+    let payload = &data[HEADER_LEN..];
+    if crc32(payload, 0) != checksum {
+        bail!("global variable file is corrupted: checksum mismatch");
+    }
+
+    Ok(bincode::deserialize(payload)?)
+}
+
+/// the title of a game as a directory name
+fn game_dir_name(title: &str) -> String {
+    let title = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // `.` and `..` would leave the directory of the games
+    match title.trim() {
+        title if title.chars().all(|c| c == '.') => "untitled".to_string(),
+        title => title.to_string(),
+    }
+}
+
+/// The file the non-volatile globals of a game are kept in
+#[derive(Debug, Clone)]
+pub struct NonVolatileStore {
+    path: PathBuf,
+}
+
+impl NonVolatileStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `<data dir>/rfvp/<game title>/globals.bin`, see `dirs_next::data_dir`
+    pub fn for_game(title: &str) -> Result<Self> {
+        let Some(data_dir) = dirs_next::data_dir() else {
+            bail!("the user data directory is not available");
+        };
+
+        Ok(Self::new(data_dir.join("rfvp").join(game_dir_name(title)).join("globals.bin")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Restore the non-volatile slots of `globals` from the file
+    ///
+    /// Returns `false` if there is no file yet. A corrupted file is an error, the globals are
+    /// left untouched in this case.
+    pub fn load(&self, globals: &mut Global) -> Result<bool> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", self.path.display()))
+            }
+        };
+
+        let values = decode_non_volatile(&data)
+            .with_context(|| format!("failed to load {}", self.path.display()))?;
+        if values.len() != globals.get_non_volatile_count() as usize {
+            log::warn!(
+                "{} has {} non-volatile globals, the scenario declares {}",
+                self.path.display(),
+                values.len(),
+                globals.get_non_volatile_count()
+            );
+        }
+
+        globals.set_non_volatile_values(values);
+        // the values are on disk already
+        globals.take_non_volatile_changed();
+        Ok(true)
+    }
+
+    /// Write the non-volatile slots to the file
+    ///
+    /// The file is replaced atomically, a crash while writing leaves the previous one intact.
+    pub fn store(&self, globals: &Global) -> Result<()> {
+        let data = encode_non_volatile(globals)?;
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Write the file if a non-volatile slot was written since the last store
+    pub fn store_if_changed(&self, globals: &mut Global) -> Result<bool> {
+        if !globals.take_non_volatile_changed() {
+            return Ok(false);
+        }
+
+        self.store(globals)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::scenario::variant::Table;
+
+    #[test]
+    fn roundtrip() {
+        let mut globals = Global::new();
+        globals.init_with(3, 1);
+        let mut table = Table::new();
+        table.push(Variant::Int(1));
+        table.insert(5, Variant::String("cleared".to_string()));
+        globals.write(0, Variant::Table(table)).unwrap();
+        globals.write(2, Variant::Float(0.5)).unwrap();
+        globals.write(3, Variant::Int(9)).unwrap();
+
+        let data = encode_non_volatile(&globals).unwrap();
+        let mut restored = Global::new();
+        restored.init_with(3, 1);
+        restored.set_non_volatile_values(decode_non_volatile(&data).unwrap());
+
+        match restored.read(0).unwrap() {
+            Variant::Table(table) => {
+                assert_eq!(table.get(0).and_then(|v| v.as_int()), Some(1));
+                assert_eq!(
+                    table.get(5).and_then(|v| v.as_string()).map(|s| s.as_str()),
+                    Some("cleared")
+                );
+            }
+            v => panic!("unexpected value: {:?}", v),
+        }
+        assert_eq!(restored.read(2).unwrap().as_float(), Some(0.5));
+        // volatile slots are not persisted
+        assert!(restored.read(3).unwrap().is_nil());
+
+        let mut corrupted = data.clone();
+        *corrupted.last_mut().unwrap() ^= 0xff;
+        assert!(decode_non_volatile(&corrupted).is_err());
+        assert!(decode_non_volatile(&data[..10]).is_err());
+    }
+
+    #[test]
+    fn game_dir() {
+        assert_eq!(game_dir_name("サクラノ詩"), "サクラノ詩");
+        assert_eq!(game_dir_name(" a/b\\c: d?\n"), "a_b_c_ d__");
+        assert_eq!(game_dir_name("  "), "untitled");
+        assert_eq!(game_dir_name(".."), "untitled");
+        assert_eq!(game_dir_name("v1.0"), "v1.0");
+    }
+
+    #[test]
+    fn store_on_change() {
+        let dir = std::env::temp_dir().join(format!("rfvp-persist-{}", std::process::id()));
+        let store = NonVolatileStore::new(dir.join("globals.bin"));
+        let mut globals = Global::new();
+        globals.init_with(1, 1);
+
+        assert!(!store.load(&mut globals).unwrap());
+        globals.write(1, Variant::Int(1)).unwrap();
+        assert!(!store.store_if_changed(&mut globals).unwrap());
+        globals.write(0, Variant::Int(2)).unwrap();
+        assert!(store.store_if_changed(&mut globals).unwrap());
+
+        let mut restored = Global::new();
+        restored.init_with(1, 1);
+        assert!(store.load(&mut restored).unwrap());
+        assert_eq!(restored.read(0).unwrap().as_int(), Some(2));
+
+        std::fs::write(store.path(), b"RFVPGLOB garbage").unwrap();
+        assert!(store.load(&mut restored).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
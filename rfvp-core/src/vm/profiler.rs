@@ -0,0 +1,325 @@
+//! Per-function execution profile.
+//!
+//! The profiler follows the call stack of every thread through the `call` and `ret`
+//! instructions and charges every instruction, syscall and the time spent executing it to the
+//! routine on top of the stack. The stack of a thread is read from its saved frames each time
+//! it is scheduled, so threads restarted by the host are picked up again.
+//!
+//! The stacks are kept as a call tree: a routine reached from different callers has a node per
+//! call path, which is what the folded output of flamegraph tools needs.
+//!
+//! The profile is only read through the headless runner for now, the game has no overlay for
+//! it while its modules are not built.
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::vm::program::Op;
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    function: u32,
+    parent: Option<usize>,
+    children: HashMap<u32, usize>,
+    calls: u64,
+    instructions: u64,
+    syscalls: u64,
+    time: Duration,
+}
+
+/// What the folded stacks count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weight {
+    Instructions,
+    Syscalls,
+    /// wall time in microseconds
+    Time,
+}
+
+/// What was spent in a routine, over all its call paths
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub function: u32,
+    pub calls: u64,
+    pub self_instructions: u64,
+    /// including the routines it called
+    pub instructions: u64,
+    pub syscalls: u64,
+    pub self_time: Duration,
+    /// including the routines it called
+    pub time: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    nodes: Vec<Node>,
+    roots: HashMap<u32, usize>,
+    /// the node each thread is executing
+    threads: HashMap<u32, usize>,
+    /// when the last instruction started and the node it is charged to
+    clock: Option<(Instant, usize)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// nothing was recorded yet
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// forget everything recorded so far
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// a thread is scheduled, `stack` lists its routines from the outermost one
+    pub(crate) fn enter(&mut self, thread: u32, stack: impl IntoIterator<Item = u32>) {
+        let mut node = None;
+        for function in stack {
+            node = Some(self.child(node, function));
+        }
+        match node {
+            Some(node) => self.threads.insert(thread, node),
+            None => self.threads.remove(&thread),
+        };
+    }
+
+    /// charge the instruction the thread is about to execute
+    pub(crate) fn record(&mut self, thread: u32, op: &Op) {
+        let now = Instant::now();
+        self.charge(now);
+        let Some(&node) = self.threads.get(&thread) else {
+            return;
+        };
+
+        self.nodes[node].instructions += 1;
+        let next = match op {
+            Op::Syscall(_) => {
+                self.nodes[node].syscalls += 1;
+                node
+            }
+            Op::Call(target) => {
+                let child = self.child(Some(node), target.addr);
+                self.nodes[child].calls += 1;
+                child
+            }
+            // the outermost routine of a thread has nowhere to return to
+            Op::Ret | Op::RetV => self.nodes[node].parent.unwrap_or(node),
+            _ => node,
+        };
+        self.threads.insert(thread, next);
+        self.clock = Some((now, next));
+    }
+
+    /// the thread yielded, the time until the next instruction is not charged
+    pub(crate) fn pause(&mut self) {
+        self.charge(Instant::now());
+    }
+
+    fn charge(&mut self, now: Instant) {
+        if let Some((since, node)) = self.clock.take() {
+            self.nodes[node].time += now - since;
+        }
+    }
+
+    fn child(&mut self, parent: Option<usize>, function: u32) -> usize {
+        let existing = match parent {
+            Some(parent) => self.nodes[parent].children.get(&function),
+            None => self.roots.get(&function),
+        };
+        if let Some(index) = existing {
+            return *index;
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            function,
+            parent,
+            ..Default::default()
+        });
+        match parent {
+            Some(parent) => self.nodes[parent].children.insert(function, index),
+            None => self.roots.insert(function, index),
+        };
+        index
+    }
+
+    /// the routines from the outermost one down to the node
+    fn path(&self, index: usize) -> Vec<u32> {
+        let mut path = Vec::new();
+        let mut node = Some(index);
+        while let Some(index) = node {
+            path.push(self.nodes[index].function);
+            node = self.nodes[index].parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Write the call paths in the folded stack format of flamegraph tools
+    ///
+    /// One line per call path, `outer;inner weight`, paths that weigh nothing are left out.
+    pub fn write_folded(
+        &self,
+        writer: &mut impl Write,
+        weight: Weight,
+        name: impl Fn(u32) -> String,
+    ) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let value = match weight {
+                Weight::Instructions => node.instructions,
+                Weight::Syscalls => node.syscalls,
+                Weight::Time => node.time.as_micros() as u64,
+            };
+            if value == 0 {
+                continue;
+            }
+            let path = self.path(index).into_iter().map(&name).collect::<Vec<_>>();
+            lines.push((path.join(";"), value));
+        }
+
+        lines.sort();
+        for (path, value) in lines {
+            writeln!(writer, "{} {}", path, value)?;
+        }
+        Ok(())
+    }
+
+    /// the routines, the ones that took the most time first
+    pub fn summary(&self) -> Vec<FunctionProfile> {
+        // children are always created after their parent
+        let mut totals = self
+            .nodes
+            .iter()
+            .map(|node| (node.instructions, node.time))
+            .collect::<Vec<_>>();
+        for index in (0..self.nodes.len()).rev() {
+            if let Some(parent) = self.nodes[index].parent {
+                let (instructions, time) = totals[index];
+                totals[parent].0 += instructions;
+                totals[parent].1 += time;
+            }
+        }
+
+        let mut functions = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let profile = functions
+                .entry(node.function)
+                .or_insert_with(|| FunctionProfile {
+                    function: node.function,
+                    ..Default::default()
+                });
+            profile.calls += node.calls;
+            profile.self_instructions += node.instructions;
+            profile.syscalls += node.syscalls;
+            profile.self_time += node.time;
+
+            // a recursive call is already part of the outermost one
+            let path = self.path(index);
+            if !path[..path.len() - 1].contains(&node.function) {
+                profile.instructions += totals[index].0;
+                profile.time += totals[index].1;
+            }
+        }
+
+        let mut functions = functions.into_values().collect::<Vec<_>>();
+        functions.sort_by(|a, b| {
+            b.time
+                .cmp(&a.time)
+                .then(b.instructions.cmp(&a.instructions))
+                .then(a.function.cmp(&b.function))
+        });
+        functions
+    }
+
+    /// the summary as a text table
+    pub fn write_summary(
+        &self,
+        writer: &mut impl Write,
+        name: impl Fn(u32) -> String,
+    ) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{:<24} {:>8} {:>12} {:>12} {:>8} {:>10} {:>10}",
+            "function", "calls", "self insts", "insts", "syscalls", "self ms", "ms"
+        )?;
+        for profile in self.summary() {
+            writeln!(
+                writer,
+                "{:<24} {:>8} {:>12} {:>12} {:>8} {:>10.3} {:>10.3}",
+                name(profile.function),
+                profile.calls,
+                profile.self_instructions,
+                profile.instructions,
+                profile.syscalls,
+                profile.self_time.as_secs_f64() * 1000.0,
+                profile.time.as_secs_f64() * 1000.0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        format::scenario::{Nls, ScenarioBuilder},
+        vm::{
+            command::{Command, CommandResult},
+            Scripter,
+        },
+    };
+
+    #[test]
+    fn profile_calls() {
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            0x02, 0x15, 0x00, 0x00, 0x00, // 0x07: call 0x15
+            0x02, 0x15, 0x00, 0x00, 0x00, // 0x0C: call 0x15
+            0x03, 0x00, 0x00, // 0x11: syscall ThreadNext
+            0x04, // 0x14: ret
+            0x01, 0x00, 0x00, // 0x15: init_stack 0 0
+            0x0C, 0x05, // 0x18: push_i8 5
+            0x03, 0x01, 0x00, // 0x1A: syscall FloatToInt
+            0x04, // 0x1D: ret
+        ];
+        let scenario = ScenarioBuilder::new(Nls::ShiftJIS)
+            .code(&code)
+            .syscall(0, "ThreadNext")
+            .syscall(1, "FloatToInt")
+            .build()
+            .unwrap();
+        let mut scripter = Scripter::new();
+        scripter.start_main(scenario.get_entry_point());
+        scripter.profiler_mut();
+
+        let mut cmd = scripter.run(&scenario, 16).unwrap();
+        while let Some(Command::FloatToInt { .. }) = cmd {
+            cmd = scripter.resume(&scenario, CommandResult::None).unwrap();
+        }
+        assert!(matches!(cmd, Some(Command::ThreadNext { .. })));
+
+        let profiler = scripter.profiler().unwrap();
+        let mut folded = Vec::new();
+        profiler
+            .write_folded(&mut folded, Weight::Instructions, |f| format!("{:x}", f))
+            .unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "4 4\n4;15 8\n");
+
+        let summary = profiler.summary();
+        let callee = summary.iter().find(|p| p.function == 0x15).unwrap();
+        assert_eq!(
+            (callee.calls, callee.self_instructions, callee.syscalls),
+            (2, 8, 2)
+        );
+        let main = summary.iter().find(|p| p.function == 4).unwrap();
+        assert_eq!((main.self_instructions, main.instructions), (4, 12));
+    }
+}
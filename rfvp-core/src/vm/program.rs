@@ -0,0 +1,278 @@
+//! The code area of a scenario, decoded once.
+//!
+//! Interpreting the raw bytes means reading and bounds-checking every operand again each time an
+//! instruction runs, and decoding string literals from the NLS codepage on every push. The
+//! program holds the decoded instructions instead, jump and call targets are resolved to
+//! instruction indices so the interpreter only maps addresses back to indices on returns.
+use crate::format::scenario::{
+    instructions::{Inst, Opcode},
+    Scenario,
+};
+
+/// A decoded instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Nop,
+    InitStack {
+        args: i8,
+        locals: i8,
+    },
+    Call(Target),
+    Syscall(u16),
+    Ret,
+    RetV,
+    Jmp(Target),
+    Jz(Target),
+    PushNil,
+    PushTrue,
+    PushI32(i32),
+    PushI16(i16),
+    PushI8(i8),
+    PushF32(f32),
+    PushString(String),
+    PushGlobal(u16),
+    PushStack(i8),
+    PushGlobalTable(u16),
+    PushLocalTable(i8),
+    PushTop,
+    PushReturn,
+    PopGlobal(u16),
+    PopStack(i8),
+    PopGlobalTable(u16),
+    PopLocalTable(i8),
+    Neg,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitTest,
+    And,
+    Or,
+    SetE,
+    SetNE,
+    SetG,
+    SetLE,
+    SetL,
+    SetGE,
+    /// an opcode the engine does not know, executed as a nop
+    Unknown(u8),
+    /// an instruction that failed to decode, executing it is an error
+    Invalid(String),
+}
+
+/// The destination of a jump or a call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub addr: u32,
+    /// the instruction at `addr`, `None` if no instruction starts there
+    pub index: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    ops: Vec<Op>,
+    /// address of every instruction, followed by the end of the decoded code
+    addrs: Vec<u32>,
+}
+
+impl Program {
+    /// decode the code area of the scenario
+    ///
+    /// An instruction that fails to decode is kept as [`Op::Invalid`] and the decoding goes on
+    /// after it, only executing that instruction is reported as an error.
+    /// Syscalls are kept by id, they are resolved against the syscall table when executed.
+    /// Custom syscalls become calls to their script function.
+    pub fn new(scenario: &Scenario) -> Self {
+        let mut ops = Vec::new();
+        let mut addrs = Vec::new();
+
+        let mut iter = scenario.instructions();
+        loop {
+            let offset = iter.offset();
+            let op = match iter.next() {
+                None => break,
+                Some(Ok((_, Inst::Syscall(inst)))) => {
+                    match scenario.get_custom_syscall(inst.get_id()) {
+                        Some(custom) => Op::Call(target(custom.address)),
+                        None => Op::Syscall(inst.get_id()),
+                    }
+                }
+                Some(Ok((_, inst))) => Op::from(inst),
+                Some(Err(e)) => {
+                    let opcode = scenario.raw()[offset];
+                    match Opcode::try_from(opcode as i32) {
+                        Err(_) => Op::Unknown(opcode),
+                        // skipped over, only the syscall id is unknown to the scenario
+                        Ok(Opcode::Syscall) if iter.offset() > offset => {
+                            Op::Syscall(scenario.read_u16(offset + 1).unwrap_or_default())
+                        }
+                        Ok(_) => {
+                            log::error!("{:#}", e);
+                            Op::Invalid(format!("{:#}", e))
+                        }
+                    }
+                }
+            };
+            ops.push(op);
+            addrs.push(offset as u32);
+        }
+        addrs.push(iter.offset() as u32);
+
+        let mut program = Self { ops, addrs };
+        program.resolve_targets();
+        program
+    }
+
+    fn resolve_targets(&mut self) {
+        let addrs = &self.addrs[..self.ops.len()];
+        for op in &mut self.ops {
+            if let Op::Call(target) | Op::Jmp(target) | Op::Jz(target) = op {
+                target.index = addrs.binary_search(&target.addr).ok().map(|i| i as u32);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Op> {
+        self.ops.get(index)
+    }
+
+    /// the address of an instruction, `len()` is the end of the decoded code
+    pub fn address(&self, index: usize) -> Option<u32> {
+        self.addrs.get(index).copied()
+    }
+
+    /// the instruction starting at `addr`
+    pub fn index_of(&self, addr: usize) -> Option<usize> {
+        let addr = u32::try_from(addr).ok()?;
+        self.addrs[..self.ops.len()].binary_search(&addr).ok()
+    }
+
+    /// The instruction at `pc` after the instruction at `prev` was executed
+    ///
+    /// Falls through and taken branches are resolved without a lookup.
+    #[inline]
+    pub fn next_index(&self, prev: Option<usize>, pc: usize) -> Option<usize> {
+        if let Some(prev) = prev {
+            if self.addrs.get(prev + 1).map(|a| *a as usize) == Some(pc) && prev + 1 < self.len() {
+                return Some(prev + 1);
+            }
+            if let Some(Op::Call(target) | Op::Jmp(target) | Op::Jz(target)) = self.ops.get(prev) {
+                if target.addr as usize == pc {
+                    return target.index.map(|i| i as usize);
+                }
+            }
+        }
+        self.index_of(pc)
+    }
+}
+
+fn target(addr: u32) -> Target {
+    Target { addr, index: None }
+}
+
+impl From<Inst> for Op {
+    fn from(inst: Inst) -> Self {
+        match inst {
+            Inst::Nop(_) => Op::Nop,
+            Inst::InitStack(inst) => Op::InitStack {
+                args: inst.get_arg_count() as i8,
+                locals: inst.get_local_count() as i8,
+            },
+            Inst::Call(inst) => Op::Call(target(inst.get_target())),
+            Inst::Syscall(inst) => Op::Syscall(inst.get_id()),
+            Inst::Ret(_) => Op::Ret,
+            Inst::RetV(_) => Op::RetV,
+            Inst::Jmp(inst) => Op::Jmp(target(inst.get_target())),
+            Inst::Jz(inst) => Op::Jz(target(inst.get_target())),
+            Inst::PushNil(_) => Op::PushNil,
+            Inst::PushTrue(_) => Op::PushTrue,
+            Inst::PushI32(inst) => Op::PushI32(inst.get_value()),
+            Inst::PushI16(inst) => Op::PushI16(inst.get_value()),
+            Inst::PushI8(inst) => Op::PushI8(inst.get_value()),
+            Inst::PushF32(inst) => Op::PushF32(inst.get_value()),
+            Inst::PushString(inst) => Op::PushString(inst.get_value().clone()),
+            Inst::PushGlobal(inst) => Op::PushGlobal(inst.get_idx() as u16),
+            Inst::PushStack(inst) => Op::PushStack(inst.get_idx()),
+            Inst::PushGlobalTable(inst) => Op::PushGlobalTable(inst.get_idx() as u16),
+            Inst::PushLocalTable(inst) => Op::PushLocalTable(inst.get_idx()),
+            Inst::PushTop(_) => Op::PushTop,
+            Inst::PushReturn(_) => Op::PushReturn,
+            Inst::PopGlobal(inst) => Op::PopGlobal(inst.get_idx() as u16),
+            Inst::PopStack(inst) => Op::PopStack(inst.get_idx()),
+            Inst::PopGlobalTable(inst) => Op::PopGlobalTable(inst.get_idx() as u16),
+            Inst::PopLocalTable(inst) => Op::PopLocalTable(inst.get_idx()),
+            Inst::Neg(_) => Op::Neg,
+            Inst::Add(_) => Op::Add,
+            Inst::Sub(_) => Op::Sub,
+            Inst::Mul(_) => Op::Mul,
+            Inst::Div(_) => Op::Div,
+            Inst::Mod(_) => Op::Mod,
+            Inst::BitTest(_) => Op::BitTest,
+            Inst::And(_) => Op::And,
+            Inst::Or(_) => Op::Or,
+            Inst::SetE(_) => Op::SetE,
+            Inst::SetNE(_) => Op::SetNE,
+            Inst::SetG(_) => Op::SetG,
+            Inst::SetLE(_) => Op::SetLE,
+            Inst::SetL(_) => Op::SetL,
+            Inst::SetGE(_) => Op::SetGE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::tests::make_scenario;
+
+    #[test]
+    fn decode_and_resolve() {
+        let code = [
+            0x01, 0x00, 0x01, // init_stack 0 1
+            0x0E, 0x03, b'h', b'i', 0x00, // push_string "hi"
+            0x07, 0x11, 0x00, 0x00, 0x00, // jz 0x11
+            0x06, 0x05, 0x00, 0x00, 0x00, // jmp 0x05, the middle of init_stack
+            0xFF, // unknown
+            0x0A, 0x01, // truncated push_i32
+        ];
+        let scenario = make_scenario(&code, &[]);
+        let program = Program::new(&scenario);
+
+        assert_eq!(program.len(), 6);
+        assert_eq!(program.get(1), Some(&Op::PushString("hi".to_string())));
+        assert_eq!(
+            program.get(2),
+            Some(&Op::Jz(Target {
+                addr: 0x11,
+                index: Some(3),
+            }))
+        );
+        assert_eq!(
+            program.get(3),
+            Some(&Op::Jmp(Target {
+                addr: 0x05,
+                index: None,
+            }))
+        );
+        assert_eq!(program.get(4), Some(&Op::Unknown(0xFF)));
+        assert!(matches!(program.get(5), Some(Op::Invalid(_))));
+        assert_eq!(program.address(5), Some(0x17));
+        assert_eq!(program.address(6), Some(0x19));
+
+        assert_eq!(program.index_of(0x0C), Some(2));
+        assert_eq!(program.index_of(0x0D), None);
+        assert_eq!(program.next_index(Some(1), 0x0C), Some(2));
+        assert_eq!(program.next_index(Some(2), 0x11), Some(3));
+        assert_eq!(program.next_index(Some(3), 0x05), None);
+        assert_eq!(program.next_index(Some(4), 0x17), Some(5));
+    }
+}
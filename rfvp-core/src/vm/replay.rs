@@ -0,0 +1,380 @@
+//! Recording and replaying a play session.
+//!
+//! Given the same frame times and the same results for the syscalls that depend on the player
+//! or the clock, the VM goes through the exact same states. A recording holds the frame time
+//! of every frame and every command the script made with its result. On replay, the results
+//! of the nondeterministic commands come from the recording, the host executes the others, and
+//! every command is checked against the recorded one so a diverging replay is reported at the
+//! first difference.
+//!
+//! The non-volatile globals the session started with are part of the recording, they are
+//! restored before the replay starts.
+//!
+//! The file starts with a magic, the format version and the checksum of the scenario.
+use std::{mem::size_of, path::Path};
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format::scenario::{global::Global, variant::Variant, Scenario},
+    vm::{
+        command::{Command, CommandResult},
+        snapshot::scenario_checksum,
+    },
+};
+
+const RECORDING_MAGIC: &[u8; 8] = b"RFVPRPLY";
+const RECORDING_VERSION: u32 = 2;
+
+/// the commands whose result depends on the player or the clock
+pub fn is_nondeterministic(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Rand { .. }
+            | Command::TimerGet { .. }
+            | Command::InputGetCursIn { .. }
+            | Command::InputGetCursX { .. }
+            | Command::InputGetCursY { .. }
+            | Command::InputGetDown { .. }
+            | Command::InputGetEvent { .. }
+            | Command::InputGetRepeat { .. }
+            | Command::InputGetState { .. }
+            | Command::InputGetUp { .. }
+            | Command::InputGetWheel { .. }
+    )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCommand {
+    pub thread: u32,
+    pub pc: u32,
+    pub syscall: String,
+    pub args: Vec<Variant>,
+    /// the value written back to the script, `None` if nothing was written
+    pub result: Option<Variant>,
+}
+
+impl RecordedCommand {
+    fn matches(&self, thread: u32, pc: u32, command: &Command) -> bool {
+        // variants are compared by their encoding, floats included
+        let same_args =
+            bincode::serialize(&self.args).ok() == bincode::serialize(command.args()).ok();
+        self.thread == thread && self.pc == pc && self.syscall == command.name() && same_args
+    }
+
+    fn result(&self) -> CommandResult {
+        match &self.result {
+            Some(value) => CommandResult::WriteR0(value.clone()),
+            None => CommandResult::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// the frame time passed to [`Scripter::run`](crate::vm::Scripter::run)
+    pub frame_time: u64,
+    pub commands: Vec<RecordedCommand>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    scenario_checksum: u64,
+    /// the non-volatile globals when the session started
+    non_volatile: Vec<Variant>,
+    frames: Vec<RecordedFrame>,
+}
+
+impl Recording {
+    pub fn new(scenario: &Scenario, globals: &Global) -> Self {
+        Self {
+            scenario_checksum: scenario_checksum(scenario),
+            non_volatile: globals.non_volatile_values(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn get_scenario_checksum(&self) -> u64 {
+        self.scenario_checksum
+    }
+
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+
+    /// encode the recording, prefixed with a magic and the format version
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.extend_from_slice(RECORDING_MAGIC);
+        data.extend_from_slice(&RECORDING_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut data, self)?;
+        Ok(data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let header_len = RECORDING_MAGIC.len() + size_of::<u32>();
+        if data.len() < header_len || &data[..RECORDING_MAGIC.len()] != RECORDING_MAGIC {
+            bail!("not a session recording");
+        }
+
+        let version = u32::from_le_bytes(data[RECORDING_MAGIC.len()..header_len].try_into()?);
+        if version != RECORDING_VERSION {
+            bail!("unsupported session recording version {}", version);
+        }
+
+        Ok(bincode::deserialize(&data[header_len..])?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_bytes(&data).with_context(|| format!("failed to load {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path.as_ref(), self.to_bytes()?)
+            .with_context(|| format!("failed to write {}", path.as_ref().display()))
+    }
+}
+
+/// Records the frames of a session and the commands made during them
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    recording: Recording,
+}
+
+impl Recorder {
+    /// start recording, `globals` are the ones the session starts with
+    pub fn new(scenario: &Scenario, globals: &Global) -> Self {
+        Self {
+            recording: Recording::new(scenario, globals),
+        }
+    }
+
+    /// a frame starts, with the frame time passed to [`Scripter::run`](crate::vm::Scripter::run)
+    pub fn begin_frame(&mut self, frame_time: u64) {
+        self.recording.frames.push(RecordedFrame {
+            frame_time,
+            commands: Vec::new(),
+        });
+    }
+
+    /// a command of the thread at `pc` was executed by the host
+    pub fn record(&mut self, thread: u32, pc: u32, command: &Command, result: &CommandResult) {
+        let Some(frame) = self.recording.frames.last_mut() else {
+            log::warn!("{} was executed outside of a frame", command.name());
+            return;
+        };
+        frame.commands.push(RecordedCommand {
+            thread,
+            pc,
+            syscall: command.name().to_string(),
+            args: command.args().to_vec(),
+            result: match result {
+                CommandResult::None => None,
+                CommandResult::WriteR0(value) => Some(value.clone()),
+            },
+        });
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+/// Plays a recording back
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    recording: Recording,
+    /// the frame being replayed, `None` before the first one
+    frame: Option<usize>,
+    /// the next command of the frame
+    command: usize,
+}
+
+impl Replayer {
+    /// the recording must be made with the same scenario
+    pub fn new(recording: Recording, scenario: &Scenario) -> Result<Self> {
+        if recording.scenario_checksum != scenario_checksum(scenario) {
+            bail!("the session was recorded with a different scenario");
+        }
+        Ok(Self {
+            recording,
+            frame: None,
+            command: 0,
+        })
+    }
+
+    /// put back the non-volatile globals the session started with
+    pub fn restore_globals(&self, globals: &mut Global) {
+        globals.set_non_volatile_values(self.recording.non_volatile.clone());
+    }
+
+    /// start the next frame, returns its frame time or `None` once every frame was replayed
+    pub fn next_frame(&mut self) -> Option<u64> {
+        let next = self.frame.map_or(0, |frame| frame + 1);
+        let frame = self.recording.frames.get(next)?;
+        self.frame = Some(next);
+        self.command = 0;
+        Some(frame.frame_time)
+    }
+
+    /// Check a command against the recording
+    ///
+    /// Returns the recorded result of a nondeterministic command, `None` if the host has to
+    /// execute it. A command that is not the recorded one is an error.
+    pub fn replay(
+        &mut self,
+        thread: u32,
+        pc: u32,
+        command: &Command,
+    ) -> Result<Option<CommandResult>> {
+        let Some(index) = self.frame else {
+            bail!("{} was executed outside of a frame", command.name());
+        };
+        let frame = &self.recording.frames[index];
+        let Some(recorded) = frame.commands.get(self.command) else {
+            bail!(
+                "replay diverged in frame {}: unexpected {} at 0x{:08x} in thread {}",
+                index,
+                command.name(),
+                pc,
+                thread
+            );
+        };
+        if !recorded.matches(thread, pc, command) {
+            bail!(
+                "replay diverged in frame {} at command {}:\n  expected: {} {:?} at 0x{:08x} in \
+                 thread {}\n  actual:   {} {:?} at 0x{:08x} in thread {}",
+                index,
+                self.command,
+                recorded.syscall,
+                recorded.args,
+                recorded.pc,
+                recorded.thread,
+                command.name(),
+                command.args(),
+                pc,
+                thread
+            );
+        }
+
+        self.command += 1;
+        Ok(is_nondeterministic(command).then(|| recorded.result()))
+    }
+
+    /// the frame is over, every recorded command of it must have been made
+    pub fn end_frame(&self) -> Result<()> {
+        let Some(index) = self.frame else {
+            return Ok(());
+        };
+        let frame = &self.recording.frames[index];
+        if let Some(missing) = frame.commands.get(self.command) {
+            bail!(
+                "replay diverged in frame {}: {} at 0x{:08x} in thread {} was not made",
+                index,
+                missing.syscall,
+                missing.pc,
+                missing.thread
+            );
+        }
+        Ok(())
+    }
+
+    /// every frame was replayed
+    pub fn is_finished(&self) -> bool {
+        self.frame.map_or(0, |frame| frame + 1) >= self.recording.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{tests::make_scenario, Scripter};
+
+    /// run a frame, answering `Rand` with `rand` and executing the thread control commands
+    fn run_frame(
+        scripter: &mut Scripter,
+        scenario: &Scenario,
+        frame_time: u64,
+        mut host: impl FnMut(u32, u32, &Command) -> Result<CommandResult>,
+    ) -> Result<()> {
+        let mut command = scripter.run(scenario, frame_time)?;
+        while let Some(cmd) = command {
+            let thread = scripter.get_current_id();
+            let pc = scripter.thread(thread).get_last_pc() as u32;
+            let result = host(thread, pc, &cmd)?;
+            if let Command::ThreadNext { .. } = cmd {
+                scripter.thread_next();
+            }
+            command = scripter.resume(scenario, result)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let code = [
+            0x01, 0x00, 0x00, // 0x04: init_stack 0 0
+            0x03, 0x00, 0x00, // 0x07: syscall Rand
+            0x14, // 0x0A: push_return
+            0x15, 0x00, 0x00, // 0x0B: pop_global 0
+            0x03, 0x01, 0x00, // 0x0E: syscall ThreadNext
+            0x06, 0x07, 0x00, 0x00, 0x00, // 0x11: jmp 0x07
+        ];
+        let scenario = make_scenario(&code, &[(0, "Rand"), (0, "ThreadNext")]);
+
+        let mut scripter = Scripter::new();
+        scripter.init_globals(&scenario);
+        // the session starts with what an earlier one persisted
+        scripter.globals_mut().write(0, Variant::Int(5)).unwrap();
+        let mut recorder = Recorder::new(&scenario, scripter.globals());
+        scripter.start_main(scenario.get_entry_point());
+        for (frame_time, rand) in [(16, 7), (17, 42)] {
+            recorder.begin_frame(frame_time);
+            run_frame(&mut scripter, &scenario, frame_time, |thread, pc, cmd| {
+                let result = match cmd {
+                    Command::Rand { .. } => CommandResult::WriteR0(Variant::Int(rand)),
+                    _ => CommandResult::None,
+                };
+                recorder.record(thread, pc, cmd, &result);
+                Ok(result)
+            })
+            .unwrap();
+        }
+        let recording = Recording::from_bytes(&recorder.finish().to_bytes().unwrap()).unwrap();
+        assert_eq!(recording.frames()[1].commands.len(), 2);
+
+        let mut replayer = Replayer::new(recording.clone(), &scenario).unwrap();
+        let mut scripter = Scripter::new();
+        scripter.init_globals(&scenario);
+        replayer.restore_globals(scripter.globals_mut());
+        assert_eq!(scripter.globals().read(0).unwrap().as_int(), Some(5));
+        scripter.start_main(scenario.get_entry_point());
+        while let Some(frame_time) = replayer.next_frame() {
+            run_frame(&mut scripter, &scenario, frame_time, |thread, pc, cmd| {
+                Ok(replayer
+                    .replay(thread, pc, cmd)?
+                    .unwrap_or(CommandResult::None))
+            })
+            .unwrap();
+            replayer.end_frame().unwrap();
+        }
+        assert!(replayer.is_finished());
+        assert_eq!(scripter.globals().read(0).unwrap().as_int(), Some(42));
+
+        // a replay against a script that makes other syscalls is reported
+        let mut replayer = Replayer::new(recording, &scenario).unwrap();
+        replayer.next_frame();
+        let error = replayer
+            .replay(0, 0x07, &Command::ThreadNext { args: vec![] })
+            .unwrap_err();
+        assert!(error.to_string().contains("diverged in frame 0"));
+    }
+}
@@ -0,0 +1,94 @@
+//! Snapshot of the complete VM state.
+//!
+//! A snapshot holds everything needed to continue the execution exactly where it was taken:
+//! the thread contexts with their stacks, the scheduler state and the global variables.
+//! The syscall handlers are not part of it, they are resolved again from the scenario.
+use std::mem::size_of;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format::scenario::{context::Context, global::Global, Scenario},
+    vm::address_map::AddressMap,
+};
+
+/// Version of the snapshot layout, bumped on every incompatible change
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"RFVPSNAP";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VmSnapshot {
+    /// checksum of the scenario the snapshot was taken with
+    pub(crate) scenario_checksum: u64,
+    pub(crate) contexts: Vec<Context>,
+    pub(crate) current_id: u32,
+    pub(crate) thread_break: bool,
+    pub(crate) suspended_id: Option<u32>,
+    pub(crate) frame_time: u64,
+    pub(crate) globals: Global,
+}
+
+impl VmSnapshot {
+    /// encode the snapshot, prefixed with a magic and the snapshot version
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.extend_from_slice(SNAPSHOT_MAGIC);
+        data.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut data, self)?;
+        Ok(data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let header_len = SNAPSHOT_MAGIC.len() + size_of::<u32>();
+        if data.len() < header_len || &data[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+            bail!("not a vm snapshot");
+        }
+
+        let version = u32::from_le_bytes(data[SNAPSHOT_MAGIC.len()..header_len].try_into()?);
+        if version != SNAPSHOT_VERSION {
+            bail!(
+                "unsupported snapshot version {}, expected {}",
+                version,
+                SNAPSHOT_VERSION
+            );
+        }
+
+        Ok(bincode::deserialize(&data[header_len..])?)
+    }
+
+    pub fn get_scenario_checksum(&self) -> u64 {
+        self.scenario_checksum
+    }
+
+    /// Move a snapshot taken with the original scenario to the one it was reassembled into
+    ///
+    /// The snapshot must have been taken with the scenario the map starts from, and every
+    /// address the threads hold must be in the map: a thread in a removed instruction can't be
+    /// moved.
+    pub fn migrate(mut self, map: &AddressMap) -> Result<Self> {
+        if self.scenario_checksum != map.get_original_checksum() {
+            bail!("the snapshot was taken with another scenario than the one of the address map");
+        }
+        let relocate = |addr: usize| match map.get(addr as u32) {
+            Some(new) => Ok(new as usize),
+            None => bail!("0x{:08x} is not in the address map", addr),
+        };
+        for context in &mut self.contexts {
+            context.relocate(relocate)?;
+        }
+        self.scenario_checksum = map.get_scenario_checksum();
+        Ok(self)
+    }
+}
+
+/// FNV-1a of the scenario, used to refuse snapshots taken with another scenario
+pub fn scenario_checksum(scenario: &Scenario) -> u64 {
+    scenario
+        .raw()
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+        })
+}
@@ -0,0 +1,349 @@
+//! Maps the syscalls declared by a scenario to the code that implements them.
+//!
+//! A [`SyscallRegistry`] holds handlers keyed by syscall name. When a scenario is loaded the
+//! registry is resolved once into a [`SyscallTable`] indexed by syscall id, so the VM does not
+//! have to look anything up by name while executing.
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use anyhow::{bail, Result};
+use strum::VariantNames;
+
+use crate::{
+    format::scenario::{variant::Variant, Scenario},
+    vm::command::{
+        args::{SyscallArgs, SIGNATURES},
+        Command,
+    },
+};
+
+/// What a syscall handler produced
+#[derive(Debug)]
+pub enum SyscallResult {
+    /// the command is passed to the host, the thread is suspended until its result is delivered
+    Command(Command),
+    /// the syscall completed immediately, the value is written to the return value register
+    Value(Variant),
+}
+
+/// Implements a syscall
+///
+/// Closures with the signature `Fn(&str, Vec<Variant>) -> Result<SyscallResult>` implement
+/// this trait as well.
+pub trait SyscallHandler: Send + Sync {
+    /// `name` is the name of the syscall as declared by the scenario
+    fn call(&self, name: &str, args: Vec<Variant>) -> Result<SyscallResult>;
+}
+
+impl<F> SyscallHandler for F
+where
+    F: Fn(&str, Vec<Variant>) -> Result<SyscallResult> + Send + Sync,
+{
+    fn call(&self, name: &str, args: Vec<Variant>) -> Result<SyscallResult> {
+        self(name, args)
+    }
+}
+
+/// Produces the engine [`Command`] with the same name as the syscall
+struct EngineCommand;
+
+impl SyscallHandler for EngineCommand {
+    fn call(&self, name: &str, args: Vec<Variant>) -> Result<SyscallResult> {
+        match Command::from_syscall(name, args) {
+            Some(command) => Ok(SyscallResult::Command(command)),
+            None => bail!("syscall not found: {}", name),
+        }
+    }
+}
+
+/// Returns the same value every time
+struct Stub(Variant);
+
+impl SyscallHandler for Stub {
+    fn call(&self, _name: &str, _args: Vec<Variant>) -> Result<SyscallResult> {
+        Ok(SyscallResult::Value(self.0.clone()))
+    }
+}
+
+struct UnknownError;
+
+impl SyscallHandler for UnknownError {
+    fn call(&self, name: &str, _args: Vec<Variant>) -> Result<SyscallResult> {
+        bail!("syscall not found: {}", name)
+    }
+}
+
+struct UnknownWarn;
+
+impl SyscallHandler for UnknownWarn {
+    fn call(&self, name: &str, args: Vec<Variant>) -> Result<SyscallResult> {
+        log::warn!("unknown syscall {} {:?}, returning nil", name, args);
+        Ok(SyscallResult::Value(Variant::Nil))
+    }
+}
+
+/// What happens when the scenario calls a syscall without a registered handler
+#[derive(Clone, Default)]
+pub enum UnknownSyscallPolicy {
+    /// the call fails and the error is reported by the VM
+    #[default]
+    Error,
+    /// a warning is logged and nil is returned
+    WarnAndReturnNil,
+    /// the call is passed to the given handler
+    Custom(Arc<dyn SyscallHandler>),
+}
+
+impl UnknownSyscallPolicy {
+    fn handler(&self) -> Arc<dyn SyscallHandler> {
+        match self {
+            UnknownSyscallPolicy::Error => Arc::new(UnknownError),
+            UnknownSyscallPolicy::WarnAndReturnNil => Arc::new(UnknownWarn),
+            UnknownSyscallPolicy::Custom(handler) => handler.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for UnknownSyscallPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownSyscallPolicy::Error => write!(f, "Error"),
+            UnknownSyscallPolicy::WarnAndReturnNil => write!(f, "WarnAndReturnNil"),
+            UnknownSyscallPolicy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Syscall handlers keyed by syscall name
+#[derive(Clone)]
+pub struct SyscallRegistry {
+    handlers: HashMap<String, Arc<dyn SyscallHandler>>,
+    /// the argument count the scenario has to declare the syscall with
+    arg_counts: HashMap<String, usize>,
+    unknown: UnknownSyscallPolicy,
+}
+
+impl SyscallRegistry {
+    /// an empty registry, every syscall is handled by the unknown policy
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            arg_counts: HashMap::new(),
+            unknown: UnknownSyscallPolicy::Error,
+        }
+    }
+
+    /// a registry that passes every syscall known by the engine to the host as a [`Command`]
+    pub fn with_engine_commands() -> Self {
+        let mut registry = Self::new();
+        for name in Command::VARIANTS {
+            registry.register(name, EngineCommand);
+        }
+        for (name, count) in SIGNATURES {
+            registry.set_arg_count(name, Some(*count));
+        }
+        registry
+    }
+
+    /// register a handler, returns the handler it replaces
+    pub fn register(
+        &mut self,
+        name: &str,
+        handler: impl SyscallHandler + 'static,
+    ) -> Option<Arc<dyn SyscallHandler>> {
+        self.handlers.insert(name.to_string(), Arc::new(handler))
+    }
+
+    /// register a handler taking the typed arguments of the syscall
+    ///
+    /// The argument count of the signature is checked when the scenario is resolved.
+    pub fn register_typed<A, F>(&mut self, handler: F) -> Option<Arc<dyn SyscallHandler>>
+    where
+        A: SyscallArgs + 'static,
+        F: Fn(A) -> Result<SyscallResult> + Send + Sync + 'static,
+    {
+        self.set_arg_count(A::NAME, Some(A::ARG_COUNT));
+        self.register(A::NAME, move |_: &str, args: Vec<Variant>| {
+            handler(A::from_args(&args)?)
+        })
+    }
+
+    /// require the scenario to declare the syscall with `count` arguments, `None` accepts any
+    pub fn set_arg_count(&mut self, name: &str, count: Option<usize>) {
+        match count {
+            Some(count) => self.arg_counts.insert(name.to_string(), count),
+            None => self.arg_counts.remove(name),
+        };
+    }
+
+    /// make the syscall return `value` without reaching the host
+    pub fn stub(&mut self, name: &str, value: Variant) -> Option<Arc<dyn SyscallHandler>> {
+        self.register(name, Stub(value))
+    }
+
+    /// remove a handler, the syscall falls back to the unknown policy
+    pub fn unregister(&mut self, name: &str) -> Option<Arc<dyn SyscallHandler>> {
+        self.handlers.remove(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.contains_key(name)
+    }
+
+    pub fn set_unknown_policy(&mut self, policy: UnknownSyscallPolicy) {
+        self.unknown = policy;
+    }
+
+    pub fn get_unknown_policy(&self) -> &UnknownSyscallPolicy {
+        &self.unknown
+    }
+
+    /// bind the syscalls declared by the scenario to their handlers
+    ///
+    /// Fails if a syscall is declared with a different argument count than its signature.
+    pub fn resolve(&self, scenario: &Scenario) -> Result<SyscallTable> {
+        let count = scenario.get_all_syscalls().keys().max().map_or(0, |id| id + 1);
+        let mut syscalls = vec![None; count];
+        let unknown = self.unknown.handler();
+
+        for (id, syscall) in scenario.get_all_syscalls() {
+            if let Some(count) = self.arg_counts.get(&syscall.name) {
+                if *count != syscall.args as usize {
+                    bail!(
+                        "syscall {} ({}) is declared with {} arguments, expected {}",
+                        syscall.name,
+                        id,
+                        syscall.args,
+                        count
+                    );
+                }
+            }
+
+            let handler = match self.handlers.get(&syscall.name) {
+                Some(handler) => handler.clone(),
+                None => {
+                    log::debug!("no handler for syscall {} ({})", syscall.name, id);
+                    unknown.clone()
+                }
+            };
+
+            syscalls[*id] = Some(ResolvedSyscall {
+                name: syscall.name.clone(),
+                args: syscall.args,
+                handler,
+            });
+        }
+
+        Ok(SyscallTable { syscalls })
+    }
+}
+
+impl Default for SyscallRegistry {
+    fn default() -> Self {
+        Self::with_engine_commands()
+    }
+}
+
+impl fmt::Debug for SyscallRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.handlers.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_struct("SyscallRegistry")
+            .field("handlers", &names)
+            .field("unknown", &self.unknown)
+            .finish()
+    }
+}
+
+/// A syscall of the scenario bound to its handler
+#[derive(Clone)]
+pub struct ResolvedSyscall {
+    pub name: String,
+    pub args: u8,
+    handler: Arc<dyn SyscallHandler>,
+}
+
+impl ResolvedSyscall {
+    pub fn call(&self, args: Vec<Variant>) -> Result<SyscallResult> {
+        self.handler.call(&self.name, args)
+    }
+}
+
+impl fmt::Debug for ResolvedSyscall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolvedSyscall")
+            .field("name", &self.name)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+/// The syscalls of a scenario indexed by syscall id, see [`SyscallRegistry::resolve`]
+#[derive(Debug, Clone, Default)]
+pub struct SyscallTable {
+    syscalls: Vec<Option<ResolvedSyscall>>,
+}
+
+impl SyscallTable {
+    pub fn get(&self, id: u16) -> Option<&ResolvedSyscall> {
+        self.syscalls.get(id as usize).and_then(|s| s.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{command::args::PrimSetXY, tests::make_scenario};
+
+    #[test]
+    fn resolve_by_id() {
+        let scenario = make_scenario(&[0x00], &[(2, "TextPrint"), (0, "MyCustomCall")]);
+        let mut registry = SyscallRegistry::default();
+        registry.stub("FlagGet", Variant::True);
+
+        let table = registry.resolve(&scenario).unwrap();
+        let text = table.get(0).unwrap();
+        assert_eq!(text.name, "TextPrint");
+        assert!(matches!(
+            text.call(vec![Variant::Int(1), Variant::Nil]).unwrap(),
+            SyscallResult::Command(Command::TextPrint { .. })
+        ));
+        assert!(table.get(1).unwrap().call(vec![]).is_err());
+        assert!(table.get(2).is_none());
+
+        registry.set_unknown_policy(UnknownSyscallPolicy::WarnAndReturnNil);
+        registry.register("TextPrint", |_: &str, args: Vec<Variant>| {
+            Ok(SyscallResult::Value(Variant::Int(args.len() as i32)))
+        });
+        let table = registry.resolve(&scenario).unwrap();
+        assert!(matches!(
+            table.get(0).unwrap().call(vec![Variant::Nil]).unwrap(),
+            SyscallResult::Value(Variant::Int(1))
+        ));
+        assert!(matches!(
+            table.get(1).unwrap().call(vec![]).unwrap(),
+            SyscallResult::Value(Variant::Nil)
+        ));
+    }
+
+    #[test]
+    fn typed_handler() {
+        let scenario = make_scenario(&[0x00], &[(3, "PrimSetXY")]);
+        let mut registry = SyscallRegistry::new();
+        registry.register_typed(|args: PrimSetXY| {
+            Ok(SyscallResult::Value(Variant::Int(args.x + args.y)))
+        });
+
+        let table = registry.resolve(&scenario).unwrap();
+        let result = table.get(0).unwrap().call(vec![
+            Variant::Int(0),
+            Variant::Int(2),
+            Variant::Int(3),
+        ]);
+        assert!(matches!(result, Ok(SyscallResult::Value(Variant::Int(5)))));
+
+        // the scenario declares PrimSetXY with the wrong argument count
+        let scenario = make_scenario(&[0x00], &[(2, "PrimSetXY")]);
+        assert!(registry.resolve(&scenario).is_err());
+        assert!(SyscallRegistry::default().resolve(&scenario).is_err());
+    }
+}
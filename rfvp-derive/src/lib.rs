@@ -5,6 +5,7 @@ mod command;
 mod rational;
 pub(crate) mod sanitization;
 mod syntax_kind;
+mod syscall_args;
 mod texture_archive;
 mod util;
 mod vertex;
@@ -14,6 +15,7 @@ use synstructure::macros::DeriveInput;
 
 use crate::{
     syntax_kind::{impl_syntax_kind, SyntaxKindInput},
+    syscall_args::impl_syscall_args,
     vertex::impl_vertex,
 };
 
@@ -36,6 +38,21 @@ pub fn derive_vertex(input: TokenStream) -> TokenStream {
     }
 }
 
+/// Implements `SyscallArgs` for a struct holding the typed arguments of a syscall.
+///
+/// Fields are converted from the arguments in declaration order, `Option` fields accept nil.
+/// The syscall name defaults to the struct name and can be set with `#[syscall(name = "...")]`.
+#[proc_macro_derive(SyscallArgs, attributes(syscall))]
+pub fn derive_syscall_args(input: TokenStream) -> TokenStream {
+    match synstructure::macros::parse::<DeriveInput>(input) {
+        Ok(p) => match synstructure::Structure::try_new(&p) {
+            Ok(s) => synstructure::MacroResult::into_stream(impl_syscall_args(s)),
+            Err(e) => e.to_compile_error().into(),
+        },
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
 /// Generates a `SyntaxKind` enum, and some associated impls. For use in `shin-asm`.
 #[proc_macro]
 pub fn syntax_kind(input: TokenStream) -> TokenStream {
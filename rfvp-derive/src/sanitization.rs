@@ -81,6 +81,12 @@ ident_str! {
     pub REGISTER = from_rfvp_core!(format::scenario::instruction_elements::Register);
     pub COMMAND_RESULT = from_rfvp_core!(vm::command::CommandResult);
     pub RATIONAL = from_rfvp_core!(rational::Rational);
+    pub VARIANT = from_rfvp_core!(format::scenario::variant::Variant);
+    pub SYSCALL_ARGS = from_rfvp_core!(vm::command::args::SyscallArgs);
+    pub ARGS_RESULT = from_rfvp_core!(vm::command::args::ArgsResult);
+    pub ARGS_ERROR = from_rfvp_core!(vm::command::args::ArgsError);
+    pub CHECK_ARG_COUNT = from_rfvp_core!(vm::command::args::check_arg_count);
+    pub CONVERT_ARG = from_rfvp_core!(vm::command::args::convert_arg);
 
     pub TEXTURE_ARCHIVE = from_shin!(asset::texture_archive::TextureArchive);
     pub TEXTURE_ARCHIVE_BUILDER = from_shin!(asset::texture_archive::TextureArchiveBuilder);
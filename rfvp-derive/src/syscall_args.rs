@@ -0,0 +1,83 @@
+use darling::FromMeta;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use synstructure::Structure;
+
+use crate::{
+    sanitization::{ARGS_ERROR, ARGS_RESULT, CHECK_ARG_COUNT, CONVERT_ARG, SYSCALL_ARGS, VARIANT},
+    util::parse_opt_attribute,
+};
+
+#[derive(FromMeta)]
+struct SyscallMeta {
+    name: String,
+}
+
+pub fn impl_syscall_args(input: Structure) -> TokenStream {
+    let ast = input.ast();
+    let ident = &ast.ident;
+
+    let fields = match &ast.data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(fields),
+            ..
+        }) => fields.named.iter().collect::<Vec<_>>(),
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Unit,
+            ..
+        }) => Vec::new(),
+        _ => {
+            return syn::Error::new(
+                ast.span(),
+                "SyscallArgs can only be derived for structs with named fields",
+            )
+            .to_compile_error()
+        }
+    };
+
+    let name = match parse_opt_attribute::<SyscallMeta>(&ast.ident, "syscall", &ast.attrs) {
+        Ok(Some(meta)) => meta.name,
+        Ok(None) => ident.to_string(),
+        Err(e) => return e.write_errors(),
+    };
+
+    let syscall_args = &SYSCALL_ARGS;
+    let variant = &VARIANT;
+    let args_result = &ARGS_RESULT;
+    let args_error = &ARGS_ERROR;
+    let check_arg_count = &CHECK_ARG_COUNT;
+    let convert_arg = &CONVERT_ARG;
+
+    let arg_count = fields.len();
+    let field_inits = fields.iter().enumerate().map(|(index, f)| {
+        let field_ident = f.ident.as_ref().unwrap();
+        quote! {
+            #field_ident: #convert_arg(#name, #index, &args[#index])?
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics #syscall_args for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+            const ARG_COUNT: usize = #arg_count;
+
+            fn from_args(args: &[#variant]) -> #args_result<Self> {
+                #check_arg_count(#name, #arg_count, args.len())?;
+                Ok(Self {
+                    #(#field_inits,)*
+                })
+            }
+        }
+
+        impl #impl_generics ::core::convert::TryFrom<::std::vec::Vec<#variant>> for #ident #ty_generics #where_clause {
+            type Error = #args_error;
+
+            fn try_from(args: ::std::vec::Vec<#variant>) -> #args_result<Self> {
+                <Self as #syscall_args>::from_args(&args)
+            }
+        }
+    }
+}
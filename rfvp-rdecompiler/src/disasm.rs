@@ -1,7 +1,7 @@
 use crate::ir::{NamedVariant, StackAnalyzer, Statement};
 use anyhow::{bail, Result};
 use bytes::Bytes;
-use rfvp_core::format::scenario::instructions::{inst::*, Opcode, OpcodeBase};
+use rfvp_core::format::scenario::instructions::{Inst, OpcodeBase};
 use rfvp_core::format::scenario::{Nls, Scenario};
 use std::collections::HashMap;
 use std::path::Path;
@@ -16,7 +16,6 @@ pub struct Function {
 
 pub struct Disassembler {
     scenario: Scenario,
-    cursor: usize,
     functions: HashMap<u32, Function>,
     stack_analyzer: Option<StackAnalyzer>,
     current_function_address: Option<u32>,
@@ -29,7 +28,6 @@ impl Disassembler {
         let scenario = Scenario::new(data, Some(nls))?;
         Ok(Self {
             scenario,
-            cursor: 4,
             functions: HashMap::new(),
             stack_analyzer: None,
             current_function_address: None,
@@ -40,12 +38,7 @@ impl Disassembler {
         &self.scenario
     }
 
-    pub fn get_pc(&self) -> usize {
-        self.cursor
-    }
-
     fn rewind(&mut self) {
-        self.cursor = 4;
         self.current_function_address = None;
         self.stack_analyzer = None;
     }
@@ -67,45 +60,15 @@ impl Disassembler {
     /// 0x00 nop instruction
     /// nop, no operation
     pub fn nop(&mut self) -> Result<()> {
-        self.cursor += 1;
         // do nothing here, just skip it
 
         Ok(())
     }
 
-    pub fn one_byte_bypass(&mut self) -> Result<()> {
-        self.cursor += 1;
-        Ok(())
-    }
-
-    pub fn two_bytes_bypass(&mut self) -> Result<()> {
-        self.cursor += 2;
-        Ok(())
-    }
-
-    pub fn three_bytes_bypass(&mut self) -> Result<()> {
-        self.cursor += 3;
-        Ok(())
-    }
-
-    pub fn five_bytes_bypass(&mut self) -> Result<()> {
-        self.cursor += 5;
-        Ok(())
-    }
-
     /// 0x01 init stack instruction
     /// initialize the local routine stack, as well as
     /// the post-phase of perforimg call instruction or launching a new routine
-    pub fn init_stack(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        // how many arguments are passed to the routine
-        self.cursor += size_of::<i8>();
-
-        // how many locals are declared in the routine
-        self.cursor += size_of::<i8>();
-
+    pub fn init_stack(&mut self, addr: u32) -> Result<()> {
         // function should be inserted into the map in the previous phase
         if let Some(func) = self.functions.get(&addr) {
             let stack_analyzer =
@@ -120,22 +83,14 @@ impl Disassembler {
         Ok(())
     }
 
-    pub fn init_stack_bypass(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
-        let args_count = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
-        let locals_count = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
+    /// record the routine started by an init stack instruction
+    pub fn define_function(&mut self, addr: u32, args_count: u8, locals_count: u8) -> Result<()> {
         self.functions.insert(
             addr,
             Function {
                 address: addr,
-                args_count: args_count as u8,
-                locals_count: locals_count as u8,
+                args_count,
+                locals_count,
                 statements: Vec::new(),
             },
         );
@@ -143,23 +98,9 @@ impl Disassembler {
         Ok(())
     }
 
-    pub fn push_string_bypass(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let len = scenario.read_u8(self.cursor)? as usize;
-        self.cursor += size_of::<u8>();
-        self.cursor += len;
-
-        Ok(())
-    }
-
     /// 0x02 call instruction
     /// call a routine
-    pub fn call(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let target = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
-
+    pub fn call(&mut self, addr: u32, target: u32) -> Result<()> {
         let callee_args_count = if let Some(func) = self.functions.get(&target) {
             func.args_count
         } else {
@@ -187,16 +128,13 @@ impl Disassembler {
 
     /// 0x03 syscall
     /// call a system call
-    pub fn syscall(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let id = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
-        if let Some(syscall) = scenario.get_syscall(id) {
+    pub fn syscall(&mut self, addr: u32, scenario: &Scenario, id: u16) -> Result<()> {
+        if let (Some(name), Some(arg_count)) =
+            (scenario.get_syscall_name(id), scenario.get_syscall_args(id))
+        {
             let mut args = Vec::new();
             if let Some(stack_analyzer) = &mut self.stack_analyzer {
-                for _ in 0..syscall.args {
+                for _ in 0..arg_count {
                     if let Ok(arg) = stack_analyzer.pop() {
                         args.push(arg);
                     } else {
@@ -207,7 +145,7 @@ impl Disassembler {
                 bail!("stack analyzer not found");
             }
 
-            let statement = Statement::from_syscall(addr, syscall.name.clone(), args);
+            let statement = Statement::from_syscall(addr, name.to_string(), args);
             self.push_statement_to_current_function(statement)?;
         } else {
             bail!("syscall not found: {}", id);
@@ -218,10 +156,7 @@ impl Disassembler {
 
     /// 0x04 ret instruction
     /// return from a routine
-    pub fn ret(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn ret(&mut self, addr: u32) -> Result<()> {
         let statement = Statement::from_return(addr, false);
         self.push_statement_to_current_function(statement)?;
 
@@ -230,10 +165,7 @@ impl Disassembler {
 
     /// 0x05 retv instruction
     /// return from a routine with a value
-    pub fn retv(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn retv(&mut self, addr: u32) -> Result<()> {
         let statement = Statement::from_return(addr, true);
         self.push_statement_to_current_function(statement)?;
 
@@ -242,12 +174,7 @@ impl Disassembler {
 
     /// 0x06 jmp instruction
     /// jump to the address
-    pub fn jmp(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let target = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
-
+    pub fn jmp(&mut self, addr: u32, target: u32) -> Result<()> {
         let statement = Statement::from_jmp(addr, target);
         self.push_statement_to_current_function(statement)?;
 
@@ -256,12 +183,7 @@ impl Disassembler {
 
     /// 0x07 jz instruction
     /// jump to the address if the top of the stack is zero
-    pub fn jz(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let target = scenario.read_u32(self.cursor)?;
-        self.cursor += size_of::<u32>();
-
+    pub fn jz(&mut self, addr: u32, target: u32) -> Result<()> {
         let condition_var = if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.pop()?
         } else {
@@ -277,8 +199,6 @@ impl Disassembler {
     /// 0x08 push nil
     /// push a nil value onto the stack
     pub fn push_nil(&mut self) -> Result<()> {
-        self.cursor += 1;
-
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_nil()?;
         } else {
@@ -291,8 +211,6 @@ impl Disassembler {
     /// 0x09 push true
     /// push a true value onto the stack
     pub fn push_true(&mut self) -> Result<()> {
-        self.cursor += 1;
-
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_true()?;
         } else {
@@ -304,12 +222,7 @@ impl Disassembler {
 
     /// 0x0A push i32
     /// push an i32 value onto the stack
-    pub fn push_i32(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-
-        let value = scenario.read_i32(self.cursor)?;
-        self.cursor += size_of::<i32>();
-
+    pub fn push_i32(&mut self, value: i32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_int(value)?;
         } else {
@@ -321,11 +234,7 @@ impl Disassembler {
 
     /// 0x0B push i16
     /// push an i16 value onto the stack
-    pub fn push_i16(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let value = scenario.read_i16(self.cursor)?;
-        self.cursor += size_of::<i16>();
-
+    pub fn push_i16(&mut self, value: i16) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_int(value as i32)?;
         } else {
@@ -337,11 +246,7 @@ impl Disassembler {
 
     /// 0x0C push i8
     /// push an i8 value onto the stack
-    pub fn push_i8(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let value = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
+    pub fn push_i8(&mut self, value: i8) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_int(value as i32)?;
         } else {
@@ -353,11 +258,7 @@ impl Disassembler {
 
     /// 0x0D push f32
     /// push an f32 value onto the stack
-    pub fn push_f32(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let value = scenario.read_f32(self.cursor)?;
-        self.cursor += size_of::<f32>();
-
+    pub fn push_f32(&mut self, value: f32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_float(value)?;
         } else {
@@ -369,14 +270,7 @@ impl Disassembler {
 
     /// 0x0E push string
     /// push a string onto the stack
-    pub fn push_string(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let len = scenario.read_u8(self.cursor)? as usize;
-        self.cursor += size_of::<u8>();
-
-        let s = scenario.read_cstring(self.cursor, len)?;
-        self.cursor += len;
-
+    pub fn push_string(&mut self, s: String) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_string(s)?;
         } else {
@@ -388,11 +282,7 @@ impl Disassembler {
 
     /// 0x0F push global
     /// push a global variable onto the stack
-    pub fn push_global(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
+    pub fn push_global(&mut self, key: u16) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_global(key as u32)?;
         } else {
@@ -404,11 +294,7 @@ impl Disassembler {
 
     /// 0x10 push stack
     /// push a stack variable onto the stack
-    pub fn push_stack(&mut self, scenario: &Scenario) -> Result<()> {
-        self.cursor += 1;
-        let offset = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
+    pub fn push_stack(&mut self, offset: i8) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_stack(offset)?;
         } else {
@@ -422,12 +308,7 @@ impl Disassembler {
     /// push a value than stored in the global table by immediate key onto the stack
     /// we assume that if any failure occurs, such as the key not found,
     /// we will push a nil value onto the stack for compatibility reasons.
-    pub fn push_global_table(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
+    pub fn push_global_table(&mut self, addr: u32, key: u16) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let table = NamedVariant::from_global(key as u32);
             let table_key = stack_analyzer.pop()?;
@@ -443,12 +324,7 @@ impl Disassembler {
 
     /// 0x12 push local table
     /// push a value than stored in the local table by key onto the stack
-    pub fn push_local_table(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
+    pub fn push_local_table(&mut self, addr: u32, idx: i8) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let table = stack_analyzer.get(idx)?;
             let table_key = stack_analyzer.pop()?;
@@ -465,8 +341,6 @@ impl Disassembler {
     /// 0x13 push top
     /// push the top of the stack onto the stack
     pub fn push_top(&mut self) -> Result<()> {
-        self.cursor += 1;
-
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_top()?;
         } else {
@@ -479,8 +353,6 @@ impl Disassembler {
     /// 0x14 push return value
     /// push the return value onto the stack
     pub fn push_return_value(&mut self) -> Result<()> {
-        self.cursor += 1;
-
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             stack_analyzer.push_return_value()?;
         } else {
@@ -492,12 +364,7 @@ impl Disassembler {
 
     /// 0x15 pop global
     /// pop the top of the stack and store it in the global table
-    pub fn pop_global(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
+    pub fn pop_global(&mut self, addr: u32, key: u16) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = NamedVariant::from_global(key as u32);
@@ -513,12 +380,7 @@ impl Disassembler {
 
     /// 0x16 local copy
     /// copy the top of the stack to the local variable
-    pub fn local_copy(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
+    pub fn local_copy(&mut self, addr: u32, idx: i8) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.get(idx)?;
@@ -534,12 +396,7 @@ impl Disassembler {
 
     /// 0x17 pop global table
     /// pop the top of the stack and store it in the global table by key
-    pub fn pop_global_table(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let key = scenario.read_u16(self.cursor)?;
-        self.cursor += size_of::<u16>();
-
+    pub fn pop_global_table(&mut self, addr: u32, key: u16) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let table_key = stack_analyzer.pop()?;
@@ -558,12 +415,7 @@ impl Disassembler {
 
     /// 0x18 pop local table
     /// pop the top of the stack and store it in the local table by key
-    pub fn pop_local_table(&mut self, scenario: &Scenario) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-        let idx = scenario.read_i8(self.cursor)?;
-        self.cursor += size_of::<i8>();
-
+    pub fn pop_local_table(&mut self, addr: u32, idx: i8) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let table_key = stack_analyzer.pop()?;
@@ -582,10 +434,7 @@ impl Disassembler {
 
     /// 0x19 neg
     /// negate the top of the stack, only works for integers and floats
-    pub fn neg(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn neg(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let var = stack_analyzer.pop()?;
             let statement = Statement::from_unary_op(addr, "pvm_neg".into(), var);
@@ -599,10 +448,7 @@ impl Disassembler {
 
     /// 0x1A add
     /// add the top two values on the stack
-    pub fn add(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn add(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -617,10 +463,7 @@ impl Disassembler {
 
     /// 0x1B sub
     /// subtract the top two values on the stack
-    pub fn sub(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn sub(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -635,10 +478,7 @@ impl Disassembler {
 
     /// 0x1C mul
     /// multiply the top two values on the stack
-    pub fn mul(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn mul(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -653,10 +493,7 @@ impl Disassembler {
 
     /// 0x1D div
     /// divide the top two values on the stack
-    pub fn div(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn div(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -671,10 +508,7 @@ impl Disassembler {
 
     /// 0x1E modulo
     /// modulo the top two values on the stack
-    pub fn modulo(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn modulo(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -689,10 +523,7 @@ impl Disassembler {
 
     /// 0x1F bittest
     /// test with the top two values on the stack
-    pub fn bittest(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn bittest(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -707,10 +538,7 @@ impl Disassembler {
 
     /// 0x20 and
     /// push true if both the top two values on the stack are none-nil
-    pub fn and(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn and(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -725,10 +553,7 @@ impl Disassembler {
 
     /// 0x21 or
     /// push true if either of the top two values on the stack is none-nil
-    pub fn or(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn or(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -743,10 +568,7 @@ impl Disassembler {
 
     /// 0x22 sete
     /// set the top of the stack to true if the top two values on the stack are equal
-    pub fn sete(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn sete(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -761,10 +583,7 @@ impl Disassembler {
 
     /// 0x23 setne
     /// set the top of the stack to true if the top two values on the stack are not equal
-    pub fn setne(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn setne(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -779,10 +598,7 @@ impl Disassembler {
 
     /// 0x24 setg
     /// set the top of the stack to true if the top two values on the stack are greater
-    pub fn setg(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn setg(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -797,10 +613,7 @@ impl Disassembler {
 
     /// 0x25 setle
     /// set the top of the stack to true if the top two values on the stack are less or equal
-    pub fn setle(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn setle(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -815,10 +628,7 @@ impl Disassembler {
 
     /// 0x26 setl
     /// set the top of the stack to true if the top two values on the stack are less
-    pub fn setl(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn setl(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -833,10 +643,7 @@ impl Disassembler {
 
     /// 0x27 setge
     /// set the top of the stack to true if the top two values on the stack are greater or equal
-    pub fn setge(&mut self) -> Result<()> {
-        let addr = self.get_pc() as u32;
-        self.cursor += 1;
-
+    pub fn setge(&mut self, addr: u32) -> Result<()> {
         if let Some(stack_analyzer) = &mut self.stack_analyzer {
             let right = stack_analyzer.pop()?;
             let left = stack_analyzer.pop()?;
@@ -849,265 +656,137 @@ impl Disassembler {
         Ok(())
     }
 
-    fn routine_defination_pass(&mut self, scenario: &Scenario) -> Result<()> {
-        let opcode = scenario.read_u8(self.get_pc())? as i32;
-
-        match opcode.try_into() {
-            Ok(Opcode::Nop) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::InitStack) => {
-                self.init_stack_bypass(scenario)?;
-            }
-            Ok(Opcode::Call) => {
-                self.five_bytes_bypass()?;
-            }
-            Ok(Opcode::Syscall) => {
-                self.three_bytes_bypass()?;
-            }
-            Ok(Opcode::Ret) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::RetV) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::Jmp) => {
-                self.five_bytes_bypass()?;
-            }
-            Ok(Opcode::Jz) => {
-                self.five_bytes_bypass()?;
-            }
-            Ok(Opcode::PushNil) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::PushTrue) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::PushI32) => {
-                self.five_bytes_bypass()?;
-            }
-            Ok(Opcode::PushI16) => {
-                self.three_bytes_bypass()?;
-            }
-            Ok(Opcode::PushI8) => {
-                self.two_bytes_bypass()?;
-            }
-            Ok(Opcode::PushF32) => {
-                self.five_bytes_bypass()?;
-            }
-            Ok(Opcode::PushString) => {
-                self.push_string_bypass(scenario)?;
-            }
-            Ok(Opcode::PushGlobal) => {
-                self.three_bytes_bypass()?;
-            }
-            Ok(Opcode::PushStack) => {
-                self.two_bytes_bypass()?;
-            }
-            Ok(Opcode::PushGlobalTable) => {
-                self.three_bytes_bypass()?;
-            }
-            Ok(Opcode::PushLocalTable) => {
-                self.two_bytes_bypass()?;
-            }
-            Ok(Opcode::PushTop) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::PushReturn) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::PopGlobal) => {
-                self.three_bytes_bypass()?;
-            }
-            Ok(Opcode::PopStack) => {
-                self.two_bytes_bypass()?;
-            }
-            Ok(Opcode::PopGlobalTable) => {
-                self.three_bytes_bypass()?;
-            }
-            Ok(Opcode::PopLocalTable) => {
-                self.two_bytes_bypass()?;
-            }
-            Ok(Opcode::Neg) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::Add) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::Sub) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::Mul) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::Div) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::Mod) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::BitTest) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::And) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::Or) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::SetE) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::SetNE) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::SetG) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::SetLE) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::SetL) => {
-                self.one_byte_bypass()?;
-            }
-            Ok(Opcode::SetGE) => {
-                self.one_byte_bypass()?;
-            }
-            _ => {
-                bail!("unexpected opcode: {}", opcode);
-            }
+    fn routine_defination_pass(&mut self, inst: &Inst) -> Result<()> {
+        if let Inst::InitStack(inst) = inst {
+            self.define_function(inst.address(), inst.get_arg_count(), inst.get_local_count())?;
         }
 
         Ok(())
     }
 
-    fn disassemble_pass(&mut self, scenario: &Scenario) -> Result<()> {
-        let opcode = scenario.read_u8(self.get_pc())? as i32;
+    fn disassemble_pass(&mut self, scenario: &Scenario, inst: Inst) -> Result<()> {
+        let addr = inst.address();
 
-        match opcode.try_into() {
-            Ok(Opcode::Nop) => {
+        match inst {
+            Inst::Nop(_) => {
                 self.nop()?;
             }
-            Ok(Opcode::InitStack) => {
-                self.init_stack()?;
+            Inst::InitStack(_) => {
+                self.init_stack(addr)?;
             }
-            Ok(Opcode::Call) => {
-                self.call(scenario)?;
+            Inst::Call(inst) => {
+                self.call(addr, inst.get_target())?;
             }
-            Ok(Opcode::Syscall) => {
-                self.syscall(scenario)?;
+            Inst::Syscall(inst) => {
+                self.syscall(addr, scenario, inst.get_id())?;
             }
-            Ok(Opcode::Ret) => {
-                self.ret()?;
+            Inst::Ret(_) => {
+                self.ret(addr)?;
             }
-            Ok(Opcode::RetV) => {
-                self.retv()?;
+            Inst::RetV(_) => {
+                self.retv(addr)?;
             }
-            Ok(Opcode::Jmp) => {
-                self.jmp(scenario)?;
+            Inst::Jmp(inst) => {
+                self.jmp(addr, inst.get_target())?;
             }
-            Ok(Opcode::Jz) => {
-                self.jz(scenario)?;
+            Inst::Jz(inst) => {
+                self.jz(addr, inst.get_target())?;
             }
-            Ok(Opcode::PushNil) => {
+            Inst::PushNil(_) => {
                 self.push_nil()?;
             }
-            Ok(Opcode::PushTrue) => {
+            Inst::PushTrue(_) => {
                 self.push_true()?;
             }
-            Ok(Opcode::PushI32) => {
-                self.push_i32(scenario)?;
+            Inst::PushI32(inst) => {
+                self.push_i32(inst.get_value())?;
             }
-            Ok(Opcode::PushI16) => {
-                self.push_i16(scenario)?;
+            Inst::PushI16(inst) => {
+                self.push_i16(inst.get_value())?;
             }
-            Ok(Opcode::PushI8) => {
-                self.push_i8(scenario)?;
+            Inst::PushI8(inst) => {
+                self.push_i8(inst.get_value())?;
             }
-            Ok(Opcode::PushF32) => {
-                self.push_f32(scenario)?;
+            Inst::PushF32(inst) => {
+                self.push_f32(inst.get_value())?;
             }
-            Ok(Opcode::PushString) => {
-                self.push_string(scenario)?;
+            Inst::PushString(inst) => {
+                self.push_string(inst.get_value().clone())?;
             }
-            Ok(Opcode::PushGlobal) => {
-                self.push_global(scenario)?;
+            Inst::PushGlobal(inst) => {
+                self.push_global(inst.get_idx() as u16)?;
             }
-            Ok(Opcode::PushStack) => {
-                self.push_stack(scenario)?;
+            Inst::PushStack(inst) => {
+                self.push_stack(inst.get_idx())?;
             }
-            Ok(Opcode::PushGlobalTable) => {
-                self.push_global_table(scenario)?;
+            Inst::PushGlobalTable(inst) => {
+                self.push_global_table(addr, inst.get_idx() as u16)?;
             }
-            Ok(Opcode::PushLocalTable) => {
-                self.push_local_table(scenario)?;
+            Inst::PushLocalTable(inst) => {
+                self.push_local_table(addr, inst.get_idx())?;
             }
-            Ok(Opcode::PushTop) => {
+            Inst::PushTop(_) => {
                 self.push_top()?;
             }
-            Ok(Opcode::PushReturn) => {
+            Inst::PushReturn(_) => {
                 self.push_return_value()?;
             }
-            Ok(Opcode::PopGlobal) => {
-                self.pop_global(scenario)?;
-            }
-            Ok(Opcode::PopStack) => {
-                self.local_copy(scenario)?;
+            Inst::PopGlobal(inst) => {
+                self.pop_global(addr, inst.get_idx() as u16)?;
             }
-            Ok(Opcode::PopGlobalTable) => {
-                self.pop_global_table(scenario)?;
+            Inst::PopStack(inst) => {
+                self.local_copy(addr, inst.get_idx())?;
             }
-            Ok(Opcode::PopLocalTable) => {
-                self.pop_local_table(scenario)?;
+            Inst::PopGlobalTable(inst) => {
+                self.pop_global_table(addr, inst.get_idx() as u16)?;
             }
-            Ok(Opcode::Neg) => {
-                self.neg()?;
+            Inst::PopLocalTable(inst) => {
+                self.pop_local_table(addr, inst.get_idx())?;
             }
-            Ok(Opcode::Add) => {
-                self.add()?;
+            Inst::Neg(_) => {
+                self.neg(addr)?;
             }
-            Ok(Opcode::Sub) => {
-                self.sub()?;
+            Inst::Add(_) => {
+                self.add(addr)?;
             }
-            Ok(Opcode::Mul) => {
-                self.mul()?;
+            Inst::Sub(_) => {
+                self.sub(addr)?;
             }
-            Ok(Opcode::Div) => {
-                self.div()?;
+            Inst::Mul(_) => {
+                self.mul(addr)?;
             }
-            Ok(Opcode::Mod) => {
-                self.modulo()?;
+            Inst::Div(_) => {
+                self.div(addr)?;
             }
-            Ok(Opcode::BitTest) => {
-                self.bittest()?;
+            Inst::Mod(_) => {
+                self.modulo(addr)?;
             }
-            Ok(Opcode::And) => {
-                self.and()?;
+            Inst::BitTest(_) => {
+                self.bittest(addr)?;
             }
-            Ok(Opcode::Or) => {
-                self.or()?;
+            Inst::And(_) => {
+                self.and(addr)?;
             }
-            Ok(Opcode::SetE) => {
-                self.sete()?;
+            Inst::Or(_) => {
+                self.or(addr)?;
             }
-            Ok(Opcode::SetNE) => {
-                self.setne()?;
+            Inst::SetE(_) => {
+                self.sete(addr)?;
             }
-            Ok(Opcode::SetG) => {
-                self.setg()?;
+            Inst::SetNE(_) => {
+                self.setne(addr)?;
             }
-            Ok(Opcode::SetLE) => {
-                self.setle()?;
+            Inst::SetG(_) => {
+                self.setg(addr)?;
             }
-            Ok(Opcode::SetL) => {
-                self.setl()?;
+            Inst::SetLE(_) => {
+                self.setle(addr)?;
             }
-            Ok(Opcode::SetGE) => {
-                self.setge()?;
+            Inst::SetL(_) => {
+                self.setl(addr)?;
             }
-            _ => {
-                self.nop()?;
-                log::error!("unknown opcode: {}", opcode);
+            Inst::SetGE(_) => {
+                self.setge(addr)?;
             }
         };
 
@@ -1118,14 +797,16 @@ impl Disassembler {
         let scenario = self.scenario.clone();
 
         // pass 1: routine defination
-        while self.get_pc() < scenario.get_sys_desc_offset() as usize {
-            self.routine_defination_pass(&scenario)?;
+        for inst in scenario.instructions() {
+            let (_, inst) = inst?;
+            self.routine_defination_pass(&inst)?;
         }
 
         // pass 2: disassemble
         self.rewind();
-        while self.get_pc() < scenario.get_sys_desc_offset() as usize {
-            self.disassemble_pass(&scenario)?;
+        for inst in scenario.instructions() {
+            let (_, inst) = inst?;
+            self.disassemble_pass(&scenario, inst)?;
         }
 
         println!("{:?}", self.functions.values().last().unwrap());